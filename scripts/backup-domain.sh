@@ -3,8 +3,8 @@
 DOMAIN="$1"
 
 if [[ -z "$DOMAIN" ]]; then
-  >&2 echo "Usage: $0 <domain>"
+  >&2 echo "Usage: $0 <domain> [--incremental]"
   exit 1
 fi
 
-tar -cvzf ./messages/$(date +%Y%m%d_%H%M%S)-${DOMAIN}-backup.tar.gz ./messages/${DOMAIN}
\ No newline at end of file
+off-the-cloud archive create --domain=${DOMAIN} ${@:2}
@@ -18,9 +18,11 @@ try:
         reader = csv.reader(csvfile)
         for row in reader:
             email = row[0]
-            password = row[1]
+            # Header of lists with per-account overrides
+            if email.lstrip("\ufeff").lower() == "email":
+                continue
             print(f"Email: {email}")
-            subprocess.run(["./scripts/push.sh", email, password]) 
+            subprocess.run(["off-the-cloud", "--accounts", filename, "imap", "push", f"--email={email}"])
 
 except FileNotFoundError:
     print(f"File {filename} not found.", file=sys.stderr)
@@ -0,0 +1,342 @@
+use anyhow::Context;
+use human_bytes::human_bytes;
+use std::fs::{self, File};
+use tokio::time::Instant;
+
+use super::{
+    absolute_path, list_files, message_key, sha256_hex, ArchiveIndex, IndexedArchive, Manifest,
+    ManifestEntry, INDEX_FILE_NAME, MANIFEST_FILE_NAME, MANIFEST_VERSION,
+};
+
+pub async fn create(
+    in_dir: String,
+    out_dir: String,
+    scope: String,
+    incremental: bool,
+    compression_level: i32,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let create_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = scope.split("@").last().context("wrong scope {scope}")?;
+    let scope_dir = if scope.contains('@') {
+        format!("{domain}/{scope}")
+    } else {
+        domain.to_string()
+    };
+
+    let in_path = absolute_path(&in_dir);
+    let scope_path = in_path.join(&scope_dir);
+    if !scope_path.is_dir() {
+        anyhow::bail!("nothing to archive in {}", scope_path.display());
+    }
+
+    let archive_dir_path = absolute_path(&out_dir).join(domain);
+    fs::create_dir_all(&archive_dir_path)?;
+
+    let index_path = archive_dir_path.join(format!("{scope}.index.json"));
+    let mut index = ArchiveIndex::load(&index_path, &scope)?;
+
+    let incremental = if incremental && index.archives.is_empty() {
+        log::warn!("No previous archive found for {scope}, creating full archive");
+        false
+    } else {
+        incremental
+    };
+
+    log::info!("Scanning {}", scope_path.display());
+    let files = list_files(&in_path, &scope_path).await?;
+
+    let mut entries = Vec::new();
+    for file in files {
+        if create_cancellation_token.is_cancelled() {
+            log::info!("Archive creation cancelled");
+            return Ok(());
+        }
+
+        let data = fs::read(in_path.join(&file)).context(format!("unable to read {file}"))?;
+        let sha256 = sha256_hex(&data);
+
+        if incremental && index.messages.get(&message_key(&file)) == Some(&sha256) {
+            continue;
+        }
+
+        entries.push(ManifestEntry {
+            path: file,
+            size: data.len() as u64,
+            sha256,
+        });
+    }
+
+    let created_at = chrono::Utc::now();
+    let file_name = format!(
+        "{}-{scope}{}.tar.zst",
+        created_at.format("%Y%m%d_%H%M%S"),
+        if incremental { "-incremental" } else { "" }
+    );
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        created_at: created_at.to_rfc3339(),
+        scope: scope.clone(),
+        incremental,
+        base: if incremental {
+            index
+                .archives
+                .last()
+                .map(|archive| archive.file_name.clone())
+        } else {
+            None
+        },
+        entries,
+    };
+
+    let bytes: u64 = manifest.entries.iter().map(|entry| entry.size).sum();
+
+    if !incremental {
+        index.messages.clear();
+    }
+    for entry in manifest.entries.iter() {
+        index
+            .messages
+            .insert(message_key(&entry.path), entry.sha256.clone());
+    }
+    index.archives.push(IndexedArchive {
+        file_name: file_name.clone(),
+        created_at: manifest.created_at.clone(),
+        incremental,
+        entries: manifest.entries.len(),
+        bytes,
+    });
+
+    let file_path = archive_dir_path.join(&file_name);
+    let part_file_path = archive_dir_path.join(format!("{file_name}.part"));
+
+    log::info!(
+        "Creating {} with {} files ({})",
+        file_path.display(),
+        manifest.entries.len(),
+        human_bytes(bytes as f64)
+    );
+
+    let out_file = File::create(&part_file_path).context("unable to create archive file")?;
+    let encoder = zstd::Encoder::new(out_file, compression_level)
+        .context("unable to initialize compression")?;
+    let mut builder = tar::Builder::new(encoder);
+
+    let manifest_data = serde_json::to_vec_pretty(&manifest)?;
+    append_data(&mut builder, MANIFEST_FILE_NAME, &manifest_data)?;
+
+    for entry in manifest.entries.iter() {
+        if create_cancellation_token.is_cancelled() {
+            drop(builder);
+            fs::remove_file(&part_file_path).ok();
+            log::info!("Archive creation cancelled");
+            return Ok(());
+        }
+
+        let data = fs::read(in_path.join(&entry.path))
+            .context(format!("unable to read {}", entry.path))?;
+        if sha256_hex(&data) != entry.sha256 {
+            drop(builder);
+            fs::remove_file(&part_file_path).ok();
+            anyhow::bail!("{} changed while archiving", entry.path);
+        }
+
+        append_data(&mut builder, &entry.path, &data)?;
+        log::debug!("{} added ({})", entry.path, human_bytes(entry.size as f64));
+    }
+
+    let index_data = serde_json::to_vec_pretty(&index)?;
+    append_data(&mut builder, INDEX_FILE_NAME, &index_data)?;
+
+    builder
+        .into_inner()
+        .context("error writing archive")?
+        .finish()
+        .context("error finishing compression")?;
+
+    fs::rename(&part_file_path, &file_path).context("unable to finalize archive")?;
+    index.save(&index_path)?;
+
+    log::info!(
+        "Done in {:?}, {} files archived to {}.",
+        start.elapsed(),
+        manifest.entries.len(),
+        file_name
+    );
+
+    Ok(())
+}
+
+fn append_data<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    builder
+        .append_data(&mut header, path, data)
+        .context(format!("unable to add {path} to archive"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::verify::read_archive;
+    use std::path::{Path, PathBuf};
+
+    const ACCOUNT_DIR: &str = "example.com/user@example.com";
+
+    fn write(in_path: &Path, path: &str, data: &str) {
+        let file_path = in_path.join(ACCOUNT_DIR).join(path);
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(file_path, data).unwrap();
+    }
+
+    fn archive_paths(out_path: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = fs::read_dir(out_path.join("example.com"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().ends_with(".tar.zst"))
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    async fn create_archive(in_path: &Path, out_path: &Path, incremental: bool) {
+        create(
+            in_path.to_string_lossy().to_string(),
+            out_path.to_string_lossy().to_string(),
+            "user@example.com".to_string(),
+            incremental,
+            3,
+        )
+        .await
+        .unwrap();
+    }
+
+    fn manifest_paths(manifest: &Manifest) -> Vec<&str> {
+        manifest
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn create_writes_manifest_and_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let (in_path, out_path) = (dir.path().join("messages"), dir.path().join("archives"));
+        write(&in_path, "INBOX/.00000001.eml", "first");
+        write(&in_path, "Sent/00000001.eml", "second");
+
+        create_archive(&in_path, &out_path, false).await;
+
+        let archives = archive_paths(&out_path);
+        assert_eq!(archives.len(), 1);
+        let manifest = read_archive(&archives[0], |_, _| Ok(())).unwrap();
+        assert!(!manifest.incremental);
+        assert_eq!(manifest.scope, "user@example.com");
+        assert_eq!(
+            manifest_paths(&manifest),
+            [
+                "example.com/user@example.com/INBOX/.00000001.eml",
+                "example.com/user@example.com/Sent/00000001.eml",
+            ]
+        );
+        assert_eq!(manifest.entries[0].size, 5);
+        assert_eq!(manifest.entries[0].sha256, sha256_hex(b"first"));
+
+        let index = ArchiveIndex::load(
+            &out_path.join("example.com/user@example.com.index.json"),
+            "user@example.com",
+        )
+        .unwrap();
+        assert_eq!(index.archives.len(), 1);
+        assert_eq!(index.archives[0].entries, 2);
+        assert_eq!(index.archives[0].bytes, 11);
+        assert_eq!(
+            index
+                .messages
+                .get("example.com/user@example.com/INBOX/00000001.eml"),
+            Some(&sha256_hex(b"first"))
+        );
+    }
+
+    #[tokio::test]
+    async fn incremental_archive_holds_new_and_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (in_path, out_path) = (dir.path().join("messages"), dir.path().join("archives"));
+        write(&in_path, "INBOX/.00000001.eml", "first");
+        write(&in_path, "INBOX/.00000002.eml", "second");
+        create_archive(&in_path, &out_path, false).await;
+
+        // Pushed, changed and new messages
+        let account_path = in_path.join(ACCOUNT_DIR);
+        fs::rename(
+            account_path.join("INBOX/.00000001.eml"),
+            account_path.join("INBOX/00000001.eml"),
+        )
+        .unwrap();
+        write(&in_path, "INBOX/.00000002.eml", "second, changed");
+        write(&in_path, "INBOX/.00000003.eml", "third");
+        create_archive(&in_path, &out_path, true).await;
+
+        let archives = archive_paths(&out_path);
+        assert_eq!(archives.len(), 2);
+        let (full_path, incremental_path) = if archives[0].to_string_lossy().contains("incremental")
+        {
+            (&archives[1], &archives[0])
+        } else {
+            (&archives[0], &archives[1])
+        };
+        let manifest = read_archive(incremental_path, |_, _| Ok(())).unwrap();
+        assert!(manifest.incremental);
+        assert_eq!(
+            manifest.base.as_deref(),
+            full_path.file_name().and_then(|name| name.to_str())
+        );
+        assert_eq!(
+            manifest_paths(&manifest),
+            [
+                "example.com/user@example.com/INBOX/.00000002.eml",
+                "example.com/user@example.com/INBOX/.00000003.eml",
+            ]
+        );
+
+        let index = ArchiveIndex::load(
+            &out_path.join("example.com/user@example.com.index.json"),
+            "user@example.com",
+        )
+        .unwrap();
+        assert_eq!(index.archives.len(), 2);
+        assert_eq!(index.messages.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn incremental_without_archive_is_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let (in_path, out_path) = (dir.path().join("messages"), dir.path().join("archives"));
+        write(&in_path, "INBOX/.00000001.eml", "first");
+
+        create_archive(&in_path, &out_path, true).await;
+
+        let archives = archive_paths(&out_path);
+        let manifest = read_archive(&archives[0], |_, _| Ok(())).unwrap();
+        assert!(!manifest.incremental);
+        assert!(manifest.base.is_none());
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Context;
+use async_walkdir::{Filtering, WalkDir};
+use futures_lite::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env::current_dir,
+    fs::{self, File},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+pub mod create;
+pub mod restore;
+pub mod verify;
+
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST.json";
+pub const INDEX_FILE_NAME: &str = "INDEX.json";
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub version: u32,
+    pub created_at: String,
+    pub scope: String,
+    pub incremental: bool,
+    pub base: Option<String>,
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IndexedArchive {
+    pub file_name: String,
+    pub created_at: String,
+    pub incremental: bool,
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// Archive history of a single scope (domain or account). `messages` maps
+/// every archived message key to its SHA-256 so incremental runs can skip
+/// what has already been stored.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ArchiveIndex {
+    pub scope: String,
+    pub archives: Vec<IndexedArchive>,
+    pub messages: BTreeMap<String, String>,
+}
+
+impl ArchiveIndex {
+    pub fn load(path: &Path, scope: &str) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(ArchiveIndex {
+                scope: scope.to_string(),
+                ..Default::default()
+            });
+        }
+
+        let f = File::open(path).context(format!("unable to open {}", path.display()))?;
+        let index: ArchiveIndex = serde_json::from_reader(f)
+            .context(format!("malformed archive index {}", path.display()))?;
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        fs::write(path, data).context(format!("unable to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Message key that survives the push rename (`.00000001.eml` -> `00000001.eml`).
+pub fn message_key(path: &str) -> String {
+    match path.rsplit_once('/') {
+        Some((dir, file_name)) => format!("{dir}/{}", file_name.trim_start_matches('.')),
+        None => path.trim_start_matches('.').to_string(),
+    }
+}
+
+/// Checks that an archived path stays below the restore directory: relative
+/// and without `..`.
+pub fn entry_path(path: &str) -> anyhow::Result<&Path> {
+    let entry_path = Path::new(path);
+    let is_normal = entry_path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if path.is_empty() || !is_normal {
+        anyhow::bail!("unsafe archive entry path {path:?}");
+    }
+
+    Ok(entry_path)
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+pub fn absolute_path(path: &str) -> PathBuf {
+    if path.starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(path)
+    } else {
+        current_dir().unwrap().join(path)
+    }
+}
+
+/// Lists all files below `root` as paths relative to `base`, sorted.
+pub async fn list_files(base: &Path, root: &Path) -> anyhow::Result<Vec<String>> {
+    let mut files = Vec::new();
+
+    let mut entries = WalkDir::new(root).filter(|entry| async move {
+        match entry.file_type().await {
+            Ok(file_type) => {
+                if file_type.is_file() {
+                    Filtering::Continue
+                } else {
+                    Filtering::Ignore
+                }
+            }
+            Err(_) => Filtering::Continue,
+        }
+    });
+
+    while let Some(entry) = entries.next().await {
+        let entry = entry.context("error reading dir")?;
+        let path = entry.path();
+        let relative_path = path
+            .strip_prefix(base)
+            .context("wrong in_dir path")?
+            .to_str()
+            .context("wrong file name")?
+            .to_string();
+        files.push(relative_path);
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_key_ignores_push_rename() {
+        assert_eq!(
+            message_key("example.com/user@example.com/INBOX/.00000001.eml"),
+            "example.com/user@example.com/INBOX/00000001.eml"
+        );
+        assert_eq!(message_key(".00000001.eml"), "00000001.eml");
+    }
+
+    #[test]
+    fn entry_path_accepts_relative_paths() {
+        assert_eq!(
+            entry_path("example.com/user@example.com/INBOX/.00000001.eml").unwrap(),
+            Path::new("example.com/user@example.com/INBOX/.00000001.eml")
+        );
+    }
+
+    #[test]
+    fn entry_path_rejects_escaping_paths() {
+        for path in [
+            "",
+            "/etc/passwd",
+            "../evil.eml",
+            "example.com/../../evil.eml",
+            "./a",
+        ] {
+            assert!(entry_path(path).is_err(), "{path:?} accepted");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Context;
+use std::fs;
+use tokio::time::Instant;
+
+use super::{absolute_path, entry_path, verify::read_archive};
+
+pub async fn restore(archive: String, out_dir: String, overwrite: bool) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let archive_path = absolute_path(&archive);
+    let out_path = absolute_path(&out_dir);
+
+    log::info!("Verifying {}", archive_path.display());
+    read_archive(&archive_path, |_, _| Ok(()))?;
+
+    log::info!(
+        "Restoring {} to {}",
+        archive_path.display(),
+        out_path.display()
+    );
+
+    let mut restored_count = 0;
+    let mut skipped_count = 0;
+
+    let manifest = read_archive(&archive_path, |entry, data| {
+        let file_path = out_path.join(entry_path(&entry.path)?);
+
+        // Both the pending (`.00000001.eml`) and pushed (`00000001.eml`) names
+        // stand for the same message
+        let file_name = file_path
+            .file_name()
+            .context("malformed archive entry path")?
+            .to_string_lossy()
+            .to_string();
+        let sibling_file_path = match file_name.strip_prefix('.') {
+            Some(pushed_file_name) => file_path.with_file_name(pushed_file_name),
+            None => file_path.with_file_name(format!(".{file_name}")),
+        };
+
+        if !overwrite && (file_path.exists() || sibling_file_path.exists()) {
+            log::debug!("{} exists, skipping", entry.path);
+            skipped_count += 1;
+            return Ok(());
+        }
+
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file_path, data).context(format!("unable to write {}", entry.path))?;
+        restored_count += 1;
+
+        Ok(())
+    })?;
+
+    log::info!(
+        "Done in {:?}, {} of {} files restored, {} existing skipped.",
+        start.elapsed(),
+        restored_count,
+        manifest.entries.len(),
+        skipped_count
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{
+        create::create, sha256_hex, Manifest, ManifestEntry, MANIFEST_FILE_NAME, MANIFEST_VERSION,
+    };
+    use std::path::Path;
+
+    const MESSAGE_PATH: &str = "example.com/user@example.com/INBOX/.00000001.eml";
+
+    async fn create_archive(dir: &Path) -> String {
+        let in_path = dir.join("messages");
+        let file_path = in_path.join(MESSAGE_PATH);
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(file_path, "message").unwrap();
+
+        let out_path = dir.join("archives");
+        create(
+            in_path.to_string_lossy().to_string(),
+            out_path.to_string_lossy().to_string(),
+            "user@example.com".to_string(),
+            false,
+            3,
+        )
+        .await
+        .unwrap();
+
+        let archive_path = fs::read_dir(out_path.join("example.com"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().ends_with(".tar.zst"))
+            .unwrap();
+        archive_path.to_string_lossy().to_string()
+    }
+
+    /// Archive listing `path` in its manifest, as a crafted archive would.
+    fn create_crafted_archive(archive_path: &Path, path: &str) {
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            scope: "user@example.com".to_string(),
+            incremental: false,
+            base: None,
+            entries: vec![ManifestEntry {
+                path: path.to_string(),
+                size: 4,
+                sha256: sha256_hex(b"evil"),
+            }],
+        };
+        let manifest_data = serde_json::to_vec(&manifest).unwrap();
+
+        let encoder = zstd::Encoder::new(fs::File::create(archive_path).unwrap(), 3).unwrap();
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_data.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, MANIFEST_FILE_NAME, manifest_data.as_slice())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn restore_writes_archived_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = create_archive(dir.path()).await;
+        let restore_path = dir.path().join("restored");
+
+        restore(archive, restore_path.to_string_lossy().to_string(), false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(restore_path.join(MESSAGE_PATH)).unwrap(),
+            "message"
+        );
+    }
+
+    #[tokio::test]
+    async fn restore_skips_pushed_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = create_archive(dir.path()).await;
+        let restore_path = dir.path().join("restored");
+        let pushed_path = restore_path.join(MESSAGE_PATH.replace("/.", "/"));
+        fs::create_dir_all(pushed_path.parent().unwrap()).unwrap();
+        fs::write(&pushed_path, "pushed").unwrap();
+
+        restore(archive, restore_path.to_string_lossy().to_string(), false)
+            .await
+            .unwrap();
+
+        assert!(!restore_path.join(MESSAGE_PATH).exists());
+        assert_eq!(fs::read_to_string(pushed_path).unwrap(), "pushed");
+    }
+
+    #[tokio::test]
+    async fn restore_rejects_escaping_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let restore_path = dir.path().join("restored");
+
+        for path in ["../evil.eml", "/tmp/evil.eml"] {
+            let archive_path = dir.path().join("crafted.tar.zst");
+            create_crafted_archive(&archive_path, path);
+
+            let result = restore(
+                archive_path.to_string_lossy().to_string(),
+                restore_path.to_string_lossy().to_string(),
+                true,
+            )
+            .await;
+
+            assert!(result.is_err(), "{path:?} restored");
+        }
+        assert!(!dir.path().join("evil.eml").exists());
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Context;
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
+use tokio::time::Instant;
+
+use super::{
+    absolute_path, entry_path, sha256_hex, Manifest, ManifestEntry, INDEX_FILE_NAME,
+    MANIFEST_FILE_NAME,
+};
+
+pub async fn verify(archive: String) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let archive_path = absolute_path(&archive);
+    log::info!("Verifying {}", archive_path.display());
+
+    let manifest = read_archive(&archive_path, |_, _| Ok(()))?;
+
+    log::info!(
+        "Done in {:?}, {} files verified ok.",
+        start.elapsed(),
+        manifest.entries.len()
+    );
+
+    Ok(())
+}
+
+/// Streams through the archive checking every file against the manifest and
+/// passes verified files to `sink`. Fails on missing, unexpected or corrupted
+/// files.
+pub fn read_archive<F>(archive_path: &Path, mut sink: F) -> anyhow::Result<Manifest>
+where
+    F: FnMut(&ManifestEntry, &[u8]) -> anyhow::Result<()>,
+{
+    let f =
+        File::open(archive_path).context(format!("unable to open {}", archive_path.display()))?;
+    let decoder = zstd::Decoder::new(f).context("unable to initialize decompression")?;
+    let mut tar = tar::Archive::new(decoder);
+
+    let mut manifest: Option<Manifest> = None;
+    let mut pending = HashMap::<String, ManifestEntry>::new();
+
+    for entry in tar.entries().context("error reading archive")? {
+        let mut entry = entry.context("error reading archive entry")?;
+        let path = entry
+            .path()
+            .context("malformed archive entry path")?
+            .to_str()
+            .context("malformed archive entry path")?
+            .to_string();
+
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .context(format!("error reading {path}"))?;
+
+        match manifest {
+            None => {
+                if path != MANIFEST_FILE_NAME {
+                    anyhow::bail!("{MANIFEST_FILE_NAME} is missing");
+                }
+                let m: Manifest =
+                    serde_json::from_slice(&data).context("malformed archive manifest")?;
+                for entry in m.entries.iter() {
+                    entry_path(&entry.path)?;
+                }
+                pending = m
+                    .entries
+                    .iter()
+                    .map(|entry| (entry.path.clone(), entry.clone()))
+                    .collect();
+                manifest = Some(m);
+            }
+            Some(_) => {
+                if path == INDEX_FILE_NAME {
+                    continue;
+                }
+                let manifest_entry = pending
+                    .remove(&path)
+                    .context(format!("{path} is not listed in manifest"))?;
+                if data.len() as u64 != manifest_entry.size
+                    || sha256_hex(&data) != manifest_entry.sha256
+                {
+                    anyhow::bail!("{path} checksum mismatch");
+                }
+                log::debug!("{path} ok");
+                sink(&manifest_entry, &data)?;
+            }
+        }
+    }
+
+    let manifest = manifest.context(format!("{MANIFEST_FILE_NAME} is missing"))?;
+
+    if !pending.is_empty() {
+        let mut missing: Vec<_> = pending.into_keys().collect();
+        missing.sort();
+        anyhow::bail!("{} files missing: {}", missing.len(), missing.join(", "));
+    }
+
+    Ok(manifest)
+}
@@ -1,16 +1,57 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::{
+    carddav::vcard::VcardVersion,
+    imap::{list::ServerRole, sync::ConflictWinner},
+};
+
+/// Output of the reporting commands, `imap list` and `status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
 
 #[derive(Debug, Parser)]
 #[clap(version, about)]
 pub struct OffTheCloudArgs {
+    /// Config file, `OTC_CONFIG` or ./config.yaml and the XDG config directories by default
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+    /// Profile of the config file, `OTC_PROFILE` by default
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Account list (CSV or YAML) with per-account server overrides, `OTC_ACCOUNTS` by default
+    #[arg(long, global = true)]
+    pub accounts: Option<String>,
     #[clap(subcommand)]
     pub command: CommandType,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum CommandType {
-    /// Shows current progress and completed spans
+    /// IMAP commands
     Imap(ImapCommand),
+    /// POP3 commands
+    Pop3(Pop3Command),
+    /// JMAP commands
+    Jmap(JmapCommand),
+    /// CalDAV commands
+    Caldav(CaldavCommand),
+    /// CardDAV commands
+    Carddav(CarddavCommand),
+    /// WebDAV commands
+    Webdav(WebdavCommand),
+    /// Sieve commands
+    Sieve(SieveCommand),
+    /// SMTP/LMTP commands
+    Smtp(SmtpCommand),
+    /// Archives stored messages
+    Archive(ArchiveCommand),
+    /// Shows migration progress of accounts from local files
+    Status(StatusCommand),
+    /// Config file commands
+    Config(ConfigCommand),
 }
 
 #[derive(Debug, Args)]
@@ -25,6 +66,12 @@ pub enum ImapSubcommand {
     Pull(ImapPullSubcommand),
     /// Pushes data with IMAP protocol
     Push(ImapPushSubcommand),
+    /// Forwards new messages as they arrive using IMAP IDLE
+    Watch(ImapWatchSubcommand),
+    /// Syncs flags and expunges of copied messages
+    Sync(ImapSyncSubcommand),
+    /// Lists folders with message counts and sizes, capabilities and quota
+    List(ImapListSubcommand),
 }
 
 #[derive(Debug, Args)]
@@ -32,9 +79,9 @@ pub struct ImapPullSubcommand {
     /// E-mail
     #[arg(long)]
     pub email: String,
-    /// Password
+    /// Password, may be given in the config or account list instead
     #[arg(long)]
-    pub password: String,
+    pub password: Option<String>,
     /// Output directory
     #[arg(long, default_value = "messages")]
     pub out_dir: String,
@@ -44,10 +91,322 @@ pub struct ImapPullSubcommand {
     /// Mbox file size limit in megabytes (applies only if --export-mbox is set)
     #[arg(long, default_value = "50 MB")]
     pub max_file_size: String,
+    /// Total size of messages fetched in one batch, larger messages are fetched one by one
+    #[arg(long, default_value = "100 MB")]
+    pub memory_limit: String,
+    /// Write a JSON report of the run to this file
+    #[arg(long)]
+    pub report: Option<String>,
+    /// Print JSON-lines progress events to stdout
+    #[arg(long, default_value_t = false)]
+    pub events: bool,
 }
 
 #[derive(Debug, Args)]
 pub struct ImapPushSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password, may be given in the config or account list instead
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+    /// Stored account to push when it differs from --email
+    #[arg(long)]
+    pub from_account: Option<String>,
+    /// Push even if pending messages don't fit into the target quota
+    #[arg(long, default_value_t = false)]
+    pub ignore_quota: bool,
+    /// Push only messages that failed in earlier runs
+    #[arg(long, default_value_t = false)]
+    pub retry_failed: bool,
+    /// Write a JSON report of the run to this file
+    #[arg(long)]
+    pub report: Option<String>,
+    /// Print JSON-lines progress events to stdout
+    #[arg(long, default_value_t = false)]
+    pub events: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ImapWatchSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password, may be given in the config or account list instead
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Messages directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+    /// Mailbox to watch, may be repeated
+    #[arg(long = "mailbox", default_value = "INBOX")]
+    pub mailboxes: Vec<String>,
+    /// Polling interval in seconds for servers without IDLE
+    #[arg(long, default_value_t = 60)]
+    pub poll_interval: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct ImapSyncSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password, may be given in the config or account list instead
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Messages directory holding the sync state
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+    /// Propagate changes made on the target back to the source
+    #[arg(long, default_value_t = false)]
+    pub bidirectional: bool,
+    /// Side kept when a message changed on both servers (applies only if --bidirectional is set)
+    #[arg(long, default_value = "source")]
+    pub conflict_winner: ConflictWinner,
+}
+
+#[derive(Debug, Args)]
+pub struct ImapListSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password, may be given in the config or account list instead
+    #[arg(long)]
+    pub password: Option<String>,
+    /// Server from config.yaml to list
+    #[arg(long, default_value = "pull")]
+    pub server: ServerRole,
+    /// Output format
+    #[arg(long, default_value = "table")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct Pop3Command {
+    #[clap(subcommand)]
+    pub subcommand: Pop3Subcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Pop3Subcommand {
+    /// Pulls data with POP3 protocol
+    Pull(Pop3PullSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct Pop3PullSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+    /// Only list message headers using TOP without downloading
+    #[arg(long, default_value_t = false)]
+    pub list_headers: bool,
+    /// Delete messages from server after they are stored
+    #[arg(long, default_value_t = false)]
+    pub delete: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct JmapCommand {
+    #[clap(subcommand)]
+    pub subcommand: JmapSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum JmapSubcommand {
+    /// Pulls data with JMAP protocol
+    Pull(JmapPullSubcommand),
+    /// Pushes data with JMAP protocol
+    Push(JmapPushSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct JmapPullSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password or API token
+    #[arg(long)]
+    pub password: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct JmapPushSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password or API token
+    #[arg(long)]
+    pub password: String,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CaldavCommand {
+    #[clap(subcommand)]
+    pub subcommand: CaldavSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CaldavSubcommand {
+    /// Pulls calendars with CalDAV protocol
+    Pull(CaldavPullSubcommand),
+    /// Pushes calendars with CalDAV protocol
+    Push(CaldavPushSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct CaldavPullSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CaldavPushSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CarddavCommand {
+    #[clap(subcommand)]
+    pub subcommand: CarddavSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CarddavSubcommand {
+    /// Pulls address books with CardDAV protocol
+    Pull(CarddavPullSubcommand),
+    /// Pushes address books with CardDAV protocol
+    Push(CarddavPushSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct CarddavPullSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct CarddavPushSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+    /// Convert contacts to the given vCard version before uploading
+    #[arg(long)]
+    pub vcard_version: Option<VcardVersion>,
+}
+
+#[derive(Debug, Args)]
+pub struct WebdavCommand {
+    #[clap(subcommand)]
+    pub subcommand: WebdavSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WebdavSubcommand {
+    /// Pulls files with WebDAV protocol
+    Pull(WebdavPullSubcommand),
+    /// Pushes files with WebDAV protocol
+    Push(WebdavPushSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct WebdavPullSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct WebdavPushSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SieveCommand {
+    #[clap(subcommand)]
+    pub subcommand: SieveSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SieveSubcommand {
+    /// Pulls filters with ManageSieve protocol
+    Pull(SievePullSubcommand),
+    /// Pushes filters with ManageSieve protocol
+    Push(SievePushSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct SievePullSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Password
+    #[arg(long)]
+    pub password: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SievePushSubcommand {
     /// E-mail
     #[arg(long)]
     pub email: String,
@@ -57,4 +416,128 @@ pub struct ImapPushSubcommand {
     /// Input directory
     #[arg(long, default_value = "messages")]
     pub in_dir: String,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Args)]
+pub struct SmtpCommand {
+    #[clap(subcommand)]
+    pub subcommand: SmtpSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SmtpSubcommand {
+    /// Delivers data with SMTP or LMTP protocol
+    Push(SmtpPushSubcommand),
+}
+
+#[derive(Debug, Args)]
+pub struct SmtpPushSubcommand {
+    /// E-mail
+    #[arg(long)]
+    pub email: String,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveCommand {
+    #[clap(subcommand)]
+    pub subcommand: ArchiveSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ArchiveSubcommand {
+    /// Creates tar.zst archive of an account or domain
+    Create(ArchiveCreateSubcommand),
+    /// Restores messages from archive
+    Restore(ArchiveRestoreSubcommand),
+    /// Verifies archive integrity
+    Verify(ArchiveVerifySubcommand),
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false, id = "scope")]
+pub struct ArchiveScope {
+    /// E-mail of the account to archive
+    #[arg(long, group = "scope")]
+    pub email: Option<String>,
+    /// Domain to archive
+    #[arg(long, group = "scope")]
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveCreateSubcommand {
+    #[clap(flatten)]
+    pub scope: ArchiveScope,
+    /// Input directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+    /// Output directory
+    #[arg(long, default_value = "archives")]
+    pub out_dir: String,
+    /// Archive only messages added since the last archive
+    #[arg(long, default_value_t = false)]
+    pub incremental: bool,
+    /// Zstd compression level
+    #[arg(long, default_value_t = 3)]
+    pub compression_level: i32,
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveRestoreSubcommand {
+    /// Archive file
+    #[arg(long)]
+    pub archive: String,
+    /// Output directory
+    #[arg(long, default_value = "messages")]
+    pub out_dir: String,
+    /// Overwrite existing messages
+    #[arg(long, default_value_t = false)]
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ArchiveVerifySubcommand {
+    /// Archive file
+    #[arg(long)]
+    pub archive: String,
+}
+
+#[derive(Debug, Args)]
+pub struct StatusCommand {
+    /// Messages directory
+    #[arg(long, default_value = "messages")]
+    pub in_dir: String,
+    /// Show accounts of this domain only
+    #[arg(long)]
+    pub domain: Option<String>,
+    /// Output format
+    #[arg(long, default_value = "table")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigCommand {
+    #[clap(subcommand)]
+    pub subcommand: ConfigSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigSubcommand {
+    /// Validates the config file and its profiles
+    Check(ConfigCheckSubcommand),
+    /// Prints the JSON Schema of the config file
+    Schema,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigCheckSubcommand {
+    /// Log in to the IMAP servers with this e-mail
+    #[arg(long)]
+    pub email: Option<String>,
+    /// Password, may be given in the config or account list instead
+    #[arg(long, requires = "email")]
+    pub password: Option<String>,
+}
@@ -0,0 +1,8 @@
+pub mod pull;
+pub mod push;
+
+/// Folder inside the account directory holding one subfolder per calendar.
+pub const CALENDARS_DIR: &str = "calendars";
+
+/// Keeps ETags of already downloaded events, one `{name}\t{etag}` per line.
+pub const ETAGS_STATE_FILE_NAME: &str = ".caldav-etags";
@@ -0,0 +1,160 @@
+use anyhow::Context;
+use reqwest::Url;
+use std::{
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+};
+use tokio::time::Instant;
+
+use super::{CALENDARS_DIR, ETAGS_STATE_FILE_NAME};
+use crate::{
+    config::Config,
+    dav::{resource_file_name, sanitize_name, DavClient, CALDAV_NS},
+    storage::load_etags,
+};
+
+pub async fn pull(
+    config: &Config,
+    email: String,
+    password: String,
+    out_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let pull_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pulled_count = 0;
+
+    let caldav_config = config
+        .caldav
+        .clone()
+        .context("CalDAV config is not provided in config.yaml")?
+        .pull
+        .context("CalDAV pull server config not provided")?;
+    let caldav_url = Url::parse(&caldav_config.url).context("malformed CalDAV url")?;
+
+    log::debug!("Pulling CalDAV for account {email}...");
+    let client = DavClient::new(&email, &password);
+    let home_url = client
+        .home_set(&caldav_url, CALDAV_NS, "calendar-home-set")
+        .await?;
+    log::info!("Calendar home {home_url}");
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:displayname/><d:resourcetype/></d:prop></d:propfind>"#;
+    let calendars: Vec<_> = client
+        .propfind(&home_url, "1", body)
+        .await?
+        .into_iter()
+        .filter(|resource| resource.is("calendar"))
+        .collect();
+
+    log::info!("Loaded {} calendars", calendars.len());
+
+    for calendar in calendars {
+        if pull_cancellation_token.is_cancelled() {
+            break;
+        }
+
+        let calendar_name = match calendar.prop("displayname") {
+            Some(displayname) if !displayname.is_empty() => displayname.to_string(),
+            _ => calendar.name(),
+        };
+        log::info!("Calendar: {:?}", calendar_name);
+
+        let folder_name = format!(
+            "{out_dir}/{domain}/{email}/{CALENDARS_DIR}/{}",
+            sanitize_name(&calendar_name)
+        );
+        let folder_path = if folder_name.clone().starts_with("/") {
+            PathBuf::from_str("/").unwrap().join(folder_name.clone())
+        } else {
+            current_dir().unwrap().join(folder_name.clone())
+        };
+        fs::create_dir_all(&folder_path)?;
+
+        log::info!("Folder {}", folder_path.display());
+
+        let state_file_path = folder_path.join(ETAGS_STATE_FILE_NAME);
+        let etags = load_etags(&state_file_path)?;
+        let mut state_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&state_file_path)
+            .context("unable to open CalDAV state file")?;
+
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/><d:getcontenttype/><d:resourcetype/></d:prop></d:propfind>"#;
+        let events: Vec<_> = client
+            .propfind(&calendar.url, "1", body)
+            .await?
+            .into_iter()
+            .filter(|resource| !resource.is("collection"))
+            .collect();
+
+        let mut pulled_count = 0;
+
+        for event in events {
+            if pull_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            let event_name = event.name();
+            let etag = event.prop("getetag").unwrap_or_default().to_string();
+            let file_name = resource_file_name(&event_name, "ics");
+            let pending_file_path = folder_path.join(format!(".{file_name}"));
+            let pushed_file_path = folder_path.join(&file_name);
+
+            if !etag.is_empty()
+                && etags.get(&event_name) == Some(&etag)
+                && (pending_file_path.exists() || pushed_file_path.exists())
+            {
+                continue;
+            }
+
+            log::debug!("Downloading {}", event.url);
+            let (data, _) = client.get(&event.url).await?;
+
+            // Changed events become pending again so they are pushed once more
+            if pushed_file_path.exists() {
+                fs::remove_file(&pushed_file_path)?;
+            }
+            fs::write(&pending_file_path, &data).context("unable to save *.ics file")?;
+            writeln!(state_file, "{event_name}\t{etag}")
+                .context("unable to update CalDAV state file")?;
+
+            pulled_count += 1;
+            total_pulled_count += 1;
+
+            log::debug!("{} bytes event added", data.len());
+        }
+
+        state_file.flush().context("error flushing file")?;
+
+        log::info!("Downloaded {pulled_count} events from {calendar_name}");
+    }
+
+    log::info!(
+        "Done in {:?}, {} new or changed events stored ok.",
+        start.elapsed(),
+        total_pulled_count
+    );
+
+    Ok(())
+}
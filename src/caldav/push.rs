@@ -0,0 +1,173 @@
+use anyhow::Context;
+use human_bytes::human_bytes;
+use reqwest::Url;
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use tokio::time::Instant;
+
+use super::CALENDARS_DIR;
+use crate::{
+    config::Config,
+    dav::{collection_url, encode_name, xml_escape, DavClient, CALDAV_NS},
+};
+
+pub async fn push(
+    config: &Config,
+    email: String,
+    password: String,
+    in_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let push_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pushed_count = 0;
+
+    let folder_name = format!("{in_dir}/{domain}/{email}/{CALENDARS_DIR}");
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+
+    log::info!("Getting calendars in {}", folder_path.display());
+
+    let mut calendars = Vec::<(String, PathBuf)>::new();
+    if folder_path.is_dir() {
+        for entry in fs::read_dir(&folder_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                calendars.push((
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                ));
+            }
+        }
+    }
+    calendars.sort();
+
+    log::info!("Found {} calendars", calendars.len());
+
+    if !calendars.is_empty() {
+        let caldav_config = config
+            .caldav
+            .clone()
+            .context("CalDAV config is not provided in config.yaml")?
+            .push
+            .context("CalDAV push server config not provided")?;
+        let caldav_url = Url::parse(&caldav_config.url).context("malformed CalDAV url")?;
+
+        log::debug!("Pushing CalDAV for account {email}...");
+        let client = DavClient::new(&email, &password);
+        let home_url = client
+            .home_set(&caldav_url, CALDAV_NS, "calendar-home-set")
+            .await?;
+        log::info!("Calendar home {home_url}");
+
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:displayname/><d:resourcetype/></d:prop></d:propfind>"#;
+        let target_calendars: Vec<_> = client
+            .propfind(&home_url, "1", body)
+            .await?
+            .into_iter()
+            .filter(|resource| resource.is("calendar"))
+            .collect();
+
+        for (calendar_name, calendar_path) in calendars {
+            if push_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            log::info!("Processing calendar {}", calendar_name);
+
+            let calendar_url = match target_calendars.iter().find(|calendar| {
+                calendar.prop("displayname") == Some(calendar_name.as_str())
+                    || calendar.name() == calendar_name
+            }) {
+                Some(calendar) => calendar.url.clone(),
+                None => {
+                    let calendar_url = collection_url(&home_url)
+                        .join(&format!("{}/", encode_name(&calendar_name)))?;
+                    log::debug!("Creating calendar {calendar_url}");
+                    let body = format!(
+                        r#"<?xml version="1.0" encoding="utf-8"?>
+<c:mkcalendar xmlns:d="DAV:" xmlns:c="{CALDAV_NS}"><d:set><d:prop><d:displayname>{}</d:displayname></d:prop></d:set></c:mkcalendar>"#,
+                        xml_escape(&calendar_name)
+                    );
+                    client
+                        .mkcol("MKCALENDAR", &calendar_url, Some(body))
+                        .await?;
+                    calendar_url
+                }
+            };
+
+            let mut event_files = Vec::new();
+            for entry in fs::read_dir(&calendar_path)? {
+                let path = entry?.path();
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                if path.extension() == Some("ics".as_ref()) && file_name.starts_with('.') {
+                    event_files.push(path);
+                }
+            }
+            event_files.sort();
+
+            let mut pushed_count = 0;
+
+            for event_file_path in event_files {
+                if push_cancellation_token.is_cancelled() {
+                    break;
+                }
+
+                let event_file_name = event_file_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                let event_name = event_file_name.trim_start_matches('.');
+                let event_url = collection_url(&calendar_url).join(&encode_name(event_name))?;
+
+                log::debug!("Pushing event {} to {}...", event_name, calendar_name);
+
+                let data = fs::read(&event_file_path)?;
+                let size = data.len();
+
+                match client
+                    .put(&event_url, data, "text/calendar; charset=utf-8")
+                    .await
+                {
+                    Ok(_) => {
+                        fs::rename(&event_file_path, event_file_path.with_file_name(event_name))?;
+
+                        pushed_count += 1;
+                        total_pushed_count += 1;
+
+                        log::debug!("{} sent ok", human_bytes(size as f64));
+                    }
+                    Err(err) => log::warn!("Error pushing event {}: {:#}", event_name, err),
+                }
+            }
+
+            log::info!("Uploaded {pushed_count} events to {calendar_name}");
+        }
+    }
+
+    log::info!(
+        "Done in {:?}, {} events uploaded ok.",
+        start.elapsed(),
+        total_pushed_count
+    );
+
+    Ok(())
+}
@@ -0,0 +1,12 @@
+pub mod pull;
+pub mod push;
+pub mod vcard;
+
+/// Folder inside the account directory holding one subfolder per address book.
+pub const CONTACTS_DIR: &str = "contacts";
+
+/// Keeps ETags of already downloaded contacts, one `{name}\t{etag}` per line.
+pub const ETAGS_STATE_FILE_NAME: &str = ".carddav-etags";
+
+/// Keeps the sync token of the last `sync-collection` run.
+pub const SYNC_TOKEN_STATE_FILE_NAME: &str = ".carddav-sync-token";
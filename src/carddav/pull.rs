@@ -0,0 +1,206 @@
+use anyhow::Context;
+use reqwest::Url;
+use std::{
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+};
+use tokio::time::Instant;
+
+use super::{CONTACTS_DIR, ETAGS_STATE_FILE_NAME, SYNC_TOKEN_STATE_FILE_NAME};
+use crate::{
+    config::Config,
+    dav::{resource_file_name, sanitize_name, DavClient, CARDDAV_NS},
+    storage::load_etags,
+};
+
+pub async fn pull(
+    config: &Config,
+    email: String,
+    password: String,
+    out_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let pull_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pulled_count = 0;
+
+    let carddav_config = config
+        .carddav
+        .clone()
+        .context("CardDAV config is not provided in config.yaml")?
+        .pull
+        .context("CardDAV pull server config not provided")?;
+    let carddav_url = Url::parse(&carddav_config.url).context("malformed CardDAV url")?;
+
+    log::debug!("Pulling CardDAV for account {email}...");
+    let client = DavClient::new(&email, &password);
+    let home_url = client
+        .home_set(&carddav_url, CARDDAV_NS, "addressbook-home-set")
+        .await?;
+    log::info!("Address book home {home_url}");
+
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:displayname/><d:resourcetype/><d:sync-token/></d:prop></d:propfind>"#;
+    let address_books: Vec<_> = client
+        .propfind(&home_url, "1", body)
+        .await?
+        .into_iter()
+        .filter(|resource| resource.is("addressbook"))
+        .collect();
+
+    log::info!("Loaded {} address books", address_books.len());
+
+    for address_book in address_books {
+        if pull_cancellation_token.is_cancelled() {
+            break;
+        }
+
+        let address_book_name = match address_book.prop("displayname") {
+            Some(displayname) if !displayname.is_empty() => displayname.to_string(),
+            _ => address_book.name(),
+        };
+        log::info!("Address book: {:?}", address_book_name);
+
+        let folder_name = format!(
+            "{out_dir}/{domain}/{email}/{CONTACTS_DIR}/{}",
+            sanitize_name(&address_book_name)
+        );
+        let folder_path = if folder_name.clone().starts_with("/") {
+            PathBuf::from_str("/").unwrap().join(folder_name.clone())
+        } else {
+            current_dir().unwrap().join(folder_name.clone())
+        };
+        fs::create_dir_all(&folder_path)?;
+
+        log::info!("Folder {}", folder_path.display());
+
+        let state_file_path = folder_path.join(ETAGS_STATE_FILE_NAME);
+        let etags = load_etags(&state_file_path)?;
+        let mut state_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&state_file_path)
+            .context("unable to open CardDAV state file")?;
+
+        let sync_token_file_path = folder_path.join(SYNC_TOKEN_STATE_FILE_NAME);
+        let sync_token = fs::read_to_string(&sync_token_file_path)
+            .ok()
+            .map(|sync_token| sync_token.trim().to_string())
+            .filter(|sync_token| !sync_token.is_empty());
+
+        let props = "<d:getetag/><d:resourcetype/>";
+        let sync = match sync_token {
+            Some(ref sync_token) if address_book.prop("sync-token").is_some() => {
+                match client
+                    .sync_collection(&address_book.url, sync_token, props)
+                    .await
+                {
+                    Ok(sync) => Some(sync),
+                    Err(err) => {
+                        // Tokens expire, a full listing compared by ETags still works
+                        log::warn!("Sync failed, listing whole address book: {:#}", err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let (contacts, new_sync_token) = match sync {
+            Some((contacts, new_sync_token)) => {
+                log::debug!("{} contacts changed since last sync", contacts.len());
+                (contacts, Some(new_sync_token))
+            }
+            None => {
+                let body = format!(
+                    r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop>{props}</d:prop></d:propfind>"#
+                );
+                let contacts = client.propfind(&address_book.url, "1", &body).await?;
+                (
+                    contacts,
+                    address_book
+                        .prop("sync-token")
+                        .map(|sync_token| sync_token.to_string()),
+                )
+            }
+        };
+
+        let mut pulled_count = 0;
+        let mut completed = true;
+
+        for contact in contacts
+            .into_iter()
+            .filter(|resource| !resource.is("collection"))
+        {
+            if pull_cancellation_token.is_cancelled() {
+                completed = false;
+                break;
+            }
+
+            let contact_name = contact.name();
+            let etag = contact.prop("getetag").unwrap_or_default().to_string();
+            let file_name = resource_file_name(&contact_name, "vcf");
+            let pending_file_path = folder_path.join(format!(".{file_name}"));
+            let pushed_file_path = folder_path.join(&file_name);
+
+            if !etag.is_empty()
+                && etags.get(&contact_name) == Some(&etag)
+                && (pending_file_path.exists() || pushed_file_path.exists())
+            {
+                continue;
+            }
+
+            log::debug!("Downloading {}", contact.url);
+            let (data, _) = client.get(&contact.url).await?;
+
+            // Changed contacts become pending again so they are pushed once more
+            if pushed_file_path.exists() {
+                fs::remove_file(&pushed_file_path)?;
+            }
+            fs::write(&pending_file_path, &data).context("unable to save *.vcf file")?;
+            writeln!(state_file, "{contact_name}\t{etag}")
+                .context("unable to update CardDAV state file")?;
+
+            pulled_count += 1;
+            total_pulled_count += 1;
+
+            log::debug!("{} bytes contact added", data.len());
+        }
+
+        state_file.flush().context("error flushing file")?;
+
+        // The token is only advanced once every change it covers is stored
+        if let (true, Some(new_sync_token)) = (completed, new_sync_token) {
+            fs::write(&sync_token_file_path, new_sync_token)
+                .context("unable to save CardDAV sync token")?;
+        }
+
+        log::info!("Downloaded {pulled_count} contacts from {address_book_name}");
+    }
+
+    log::info!(
+        "Done in {:?}, {} new or changed contacts stored ok.",
+        start.elapsed(),
+        total_pulled_count
+    );
+
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use anyhow::Context;
+use human_bytes::human_bytes;
+use reqwest::Url;
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use tokio::time::Instant;
+
+use super::{
+    vcard::{self, VcardVersion},
+    CONTACTS_DIR,
+};
+use crate::{
+    config::Config,
+    dav::{collection_url, encode_name, xml_escape, DavClient, CARDDAV_NS},
+};
+
+pub async fn push(
+    config: &Config,
+    email: String,
+    password: String,
+    in_dir: String,
+    vcard_version: Option<VcardVersion>,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let push_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pushed_count = 0;
+
+    let folder_name = format!("{in_dir}/{domain}/{email}/{CONTACTS_DIR}");
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+
+    log::info!("Getting address books in {}", folder_path.display());
+
+    let mut address_books = Vec::<(String, PathBuf)>::new();
+    if folder_path.is_dir() {
+        for entry in fs::read_dir(&folder_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                address_books.push((
+                    entry.file_name().to_string_lossy().to_string(),
+                    entry.path(),
+                ));
+            }
+        }
+    }
+    address_books.sort();
+
+    log::info!("Found {} address books", address_books.len());
+
+    if !address_books.is_empty() {
+        let carddav_config = config
+            .carddav
+            .clone()
+            .context("CardDAV config is not provided in config.yaml")?
+            .push
+            .context("CardDAV push server config not provided")?;
+        let carddav_url = Url::parse(&carddav_config.url).context("malformed CardDAV url")?;
+
+        log::debug!("Pushing CardDAV for account {email}...");
+        let client = DavClient::new(&email, &password);
+        let home_url = client
+            .home_set(&carddav_url, CARDDAV_NS, "addressbook-home-set")
+            .await?;
+        log::info!("Address book home {home_url}");
+
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:displayname/><d:resourcetype/></d:prop></d:propfind>"#;
+        let target_address_books: Vec<_> = client
+            .propfind(&home_url, "1", body)
+            .await?
+            .into_iter()
+            .filter(|resource| resource.is("addressbook"))
+            .collect();
+
+        for (address_book_name, address_book_path) in address_books {
+            if push_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            log::info!("Processing address book {}", address_book_name);
+
+            let address_book_url = match target_address_books.iter().find(|address_book| {
+                address_book.prop("displayname") == Some(address_book_name.as_str())
+                    || address_book.name() == address_book_name
+            }) {
+                Some(address_book) => address_book.url.clone(),
+                None => {
+                    let address_book_url = collection_url(&home_url)
+                        .join(&format!("{}/", encode_name(&address_book_name)))?;
+                    log::debug!("Creating address book {address_book_url}");
+                    // Extended MKCOL (RFC 5689)
+                    let body = format!(
+                        r#"<?xml version="1.0" encoding="utf-8"?>
+<d:mkcol xmlns:d="DAV:" xmlns:r="{CARDDAV_NS}"><d:set><d:prop><d:resourcetype><d:collection/><r:addressbook/></d:resourcetype><d:displayname>{}</d:displayname></d:prop></d:set></d:mkcol>"#,
+                        xml_escape(&address_book_name)
+                    );
+                    client.mkcol("MKCOL", &address_book_url, Some(body)).await?;
+                    address_book_url
+                }
+            };
+
+            let mut contact_files = Vec::new();
+            for entry in fs::read_dir(&address_book_path)? {
+                let path = entry?.path();
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                if path.extension() == Some("vcf".as_ref()) && file_name.starts_with('.') {
+                    contact_files.push(path);
+                }
+            }
+            contact_files.sort();
+
+            let mut pushed_count = 0;
+
+            for contact_file_path in contact_files {
+                if push_cancellation_token.is_cancelled() {
+                    break;
+                }
+
+                let contact_file_name = contact_file_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                let contact_name = contact_file_name.trim_start_matches('.');
+                let contact_url =
+                    collection_url(&address_book_url).join(&encode_name(contact_name))?;
+
+                log::debug!(
+                    "Pushing contact {} to {}...",
+                    contact_name,
+                    address_book_name
+                );
+
+                let data = fs::read(&contact_file_path)?;
+                let data = match vcard_version {
+                    Some(vcard_version) => {
+                        vcard::convert(&String::from_utf8_lossy(&data), vcard_version).into_bytes()
+                    }
+                    None => data,
+                };
+                let size = data.len();
+
+                match client
+                    .put(&contact_url, data, "text/vcard; charset=utf-8")
+                    .await
+                {
+                    Ok(_) => {
+                        fs::rename(
+                            &contact_file_path,
+                            contact_file_path.with_file_name(contact_name),
+                        )?;
+
+                        pushed_count += 1;
+                        total_pushed_count += 1;
+
+                        log::debug!("{} sent ok", human_bytes(size as f64));
+                    }
+                    Err(err) => log::warn!("Error pushing contact {}: {:#}", contact_name, err),
+                }
+            }
+
+            log::info!("Uploaded {pushed_count} contacts to {address_book_name}");
+        }
+    }
+
+    log::info!(
+        "Done in {:?}, {} contacts uploaded ok.",
+        start.elapsed(),
+        total_pushed_count
+    );
+
+    Ok(())
+}
@@ -0,0 +1,373 @@
+//! Best-effort vCard 3.0 (RFC 2426) <-> 4.0 (RFC 6350) conversion covering
+//! the differences that matter to CardDAV servers: `VERSION`, preference
+//! parameters, inline binary data and the `N` property required by 3.0.
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VcardVersion {
+    #[value(name = "3.0")]
+    V3,
+    #[value(name = "4.0")]
+    V4,
+}
+
+impl VcardVersion {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VcardVersion::V3 => "3.0",
+            VcardVersion::V4 => "4.0",
+        }
+    }
+}
+
+struct Property {
+    group: Option<String>,
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        let (head, value) = split_unquoted(line, ':')?;
+        let mut parts = head.split(';');
+        let full_name = parts.next()?;
+        let (group, name) = match full_name.split_once('.') {
+            Some((group, name)) => (Some(group.to_string()), name),
+            None => (None, full_name),
+        };
+        let params = parts
+            .map(|param| match param.split_once('=') {
+                Some((key, value)) => (key.to_uppercase(), value.to_string()),
+                // vCard 2.1 style bare parameter, e.g. `TEL;CELL:`
+                None => ("TYPE".to_string(), param.to_string()),
+            })
+            .collect();
+
+        Some(Property {
+            group,
+            name: name.to_uppercase(),
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn remove_param(&mut self, key: &str) {
+        self.params.retain(|(k, _)| k != key);
+    }
+
+    /// Removes a value from comma separated `TYPE` parameters and reports
+    /// whether it was there.
+    fn take_type(&mut self, value: &str) -> bool {
+        let mut found = false;
+        for (key, values) in self.params.iter_mut() {
+            if key != "TYPE" {
+                continue;
+            }
+            let kept: Vec<_> = values
+                .split(',')
+                .filter(|v| {
+                    let matches = v.trim_matches('"').eq_ignore_ascii_case(value);
+                    found |= matches;
+                    !matches
+                })
+                .collect();
+            *values = kept.join(",");
+        }
+        self.params
+            .retain(|(key, values)| key != "TYPE" || !values.is_empty());
+        found
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = String::new();
+        if let Some(group) = &self.group {
+            line.push_str(group);
+            line.push('.');
+        }
+        line.push_str(&self.name);
+        for (key, value) in self.params.iter() {
+            line.push(';');
+            line.push_str(key);
+            line.push('=');
+            line.push_str(value);
+        }
+        line.push(':');
+        line.push_str(&self.value);
+        line
+    }
+}
+
+/// Converts every vCard in `data` to the given version. Converting a card
+/// that is already in the target version only normalizes it.
+pub fn convert(data: &str, version: VcardVersion) -> String {
+    let mut lines = Vec::new();
+    let mut has_n = false;
+    let mut full_name: Option<String> = None;
+
+    for line in unfold(data) {
+        let Some(mut property) = Property::parse(&line) else {
+            lines.push(line);
+            continue;
+        };
+
+        match property.name.as_str() {
+            "BEGIN" => {
+                has_n = false;
+                full_name = None;
+            }
+            "VERSION" => property.value = version.as_str().to_string(),
+            "N" => has_n = true,
+            "FN" => full_name = Some(property.value.clone()),
+            "END" if version == VcardVersion::V3 && !has_n => {
+                // N is mandatory in vCard 3.0
+                lines.push(format!("N:{};;;;", full_name.clone().unwrap_or_default()));
+            }
+            _ => {}
+        }
+
+        property.remove_param("CHARSET");
+
+        let mut property = Some(property);
+        match version {
+            VcardVersion::V4 => to_v4(&mut property),
+            VcardVersion::V3 => to_v3(&mut property),
+        }
+
+        if let Some(property) = property {
+            lines.push(property.to_line());
+        }
+    }
+
+    let mut result = String::new();
+    for line in lines {
+        result.push_str(&fold(&line));
+    }
+    result
+}
+
+fn to_v4(property: &mut Option<Property>) {
+    let Some(p) = property.as_mut() else {
+        return;
+    };
+
+    if p.take_type("pref") {
+        p.params.push(("PREF".to_string(), "1".to_string()));
+    }
+    if p.name == "EMAIL" {
+        p.take_type("internet");
+    }
+
+    // PHOTO;ENCODING=b;TYPE=JPEG:... -> PHOTO:data:image/jpeg;base64,...
+    if matches!(p.name.as_str(), "PHOTO" | "LOGO" | "SOUND")
+        && p.param("ENCODING")
+            .is_some_and(|e| e.eq_ignore_ascii_case("b") || e.eq_ignore_ascii_case("base64"))
+    {
+        let media_type = p.param("TYPE").unwrap_or("jpeg").to_lowercase();
+        let kind = if p.name == "SOUND" { "audio" } else { "image" };
+        p.value = format!("data:{kind}/{media_type};base64,{}", p.value);
+        p.remove_param("ENCODING");
+        p.remove_param("TYPE");
+    }
+
+    if p.name == "LABEL" || p.name == "NAME" || p.name == "MAILER" {
+        log::debug!("Dropping {} not supported by vCard 4.0", p.name);
+        *property = None;
+    }
+}
+
+fn to_v3(property: &mut Option<Property>) {
+    let Some(p) = property.as_mut() else {
+        return;
+    };
+
+    if p.param("PREF").is_some() {
+        p.remove_param("PREF");
+        p.params.push(("TYPE".to_string(), "PREF".to_string()));
+    }
+    if p.name == "EMAIL" && p.param("TYPE").is_none() {
+        p.params.push(("TYPE".to_string(), "INTERNET".to_string()));
+    }
+
+    // PHOTO:data:image/jpeg;base64,... -> PHOTO;ENCODING=b;TYPE=JPEG:...
+    if matches!(p.name.as_str(), "PHOTO" | "LOGO" | "SOUND") {
+        if let Some((media_type, data)) = p
+            .value
+            .strip_prefix("data:")
+            .and_then(|rest| rest.split_once(";base64,"))
+        {
+            let subtype = media_type
+                .split_once('/')
+                .map(|(_, subtype)| subtype)
+                .unwrap_or(media_type)
+                .to_uppercase();
+            let data = data.to_string();
+            p.remove_param("MEDIATYPE");
+            p.params.push(("ENCODING".to_string(), "b".to_string()));
+            p.params.push(("TYPE".to_string(), subtype));
+            p.value = data;
+        }
+    }
+
+    match p.name.as_str() {
+        "KIND" => {
+            p.name = "X-ADDRESSBOOKSERVER-KIND".to_string();
+        }
+        "MEMBER" => {
+            p.name = "X-ADDRESSBOOKSERVER-MEMBER".to_string();
+        }
+        "GENDER" | "LANG" | "ANNIVERSARY" | "CLIENTPIDMAP" => {
+            log::debug!("Dropping {} not supported by vCard 3.0", p.name);
+            *property = None;
+        }
+        _ => {}
+    }
+}
+
+fn unfold(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.split('\n') {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with([' ', '\t']) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(&line[1..]);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line.to_string());
+        }
+    }
+    lines
+}
+
+/// Folds a content line at 75 octets without splitting UTF-8 sequences.
+fn fold(line: &str) -> String {
+    let mut result = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            result.push_str("\r\n ");
+            length = 1;
+        }
+        result.push(c);
+        length += c.len_utf8();
+    }
+    result.push_str("\r\n");
+    result
+}
+
+fn split_unquoted(line: &str, separator: char) -> Option<(&str, &str)> {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => return Some((&line[..i], &line[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V3_CARD: &str = "BEGIN:VCARD\r\n\
+VERSION:3.0\r\n\
+FN:Jane Doe\r\n\
+N:Doe;Jane;;;\r\n\
+EMAIL;TYPE=INTERNET,PREF:jane@example.com\r\n\
+PHOTO;ENCODING=b;TYPE=JPEG:AAAA\r\n\
+END:VCARD\r\n";
+
+    #[test]
+    fn convert_to_v4() {
+        assert_eq!(
+            convert(V3_CARD, VcardVersion::V4),
+            "BEGIN:VCARD\r\n\
+VERSION:4.0\r\n\
+FN:Jane Doe\r\n\
+N:Doe;Jane;;;\r\n\
+EMAIL;PREF=1:jane@example.com\r\n\
+PHOTO:data:image/jpeg;base64,AAAA\r\n\
+END:VCARD\r\n"
+        );
+    }
+
+    #[test]
+    fn convert_round_trip() {
+        let v4 = convert(V3_CARD, VcardVersion::V4);
+        assert_eq!(
+            convert(&v4, VcardVersion::V3),
+            "BEGIN:VCARD\r\n\
+VERSION:3.0\r\n\
+FN:Jane Doe\r\n\
+N:Doe;Jane;;;\r\n\
+EMAIL;TYPE=PREF:jane@example.com\r\n\
+PHOTO;ENCODING=b;TYPE=JPEG:AAAA\r\n\
+END:VCARD\r\n"
+        );
+        assert_eq!(convert(&v4, VcardVersion::V4), v4);
+    }
+
+    #[test]
+    fn convert_to_v3_adds_n_and_drops_v4_properties() {
+        let v4 = "BEGIN:VCARD\nVERSION:4.0\nKIND:group\nFN:Team\nGENDER:O\nEMAIL:team@example.com\nEND:VCARD\n";
+        assert_eq!(
+            convert(v4, VcardVersion::V3),
+            "BEGIN:VCARD\r\n\
+VERSION:3.0\r\n\
+X-ADDRESSBOOKSERVER-KIND:group\r\n\
+FN:Team\r\n\
+EMAIL;TYPE=INTERNET:team@example.com\r\n\
+N:Team;;;;\r\n\
+END:VCARD\r\n"
+        );
+    }
+
+    #[test]
+    fn convert_to_v4_drops_v3_properties() {
+        let v3 = "BEGIN:VCARD\nVERSION:3.0\nN:Doe;Jane;;;\nLABEL;CHARSET=UTF-8:Main St\nTEL;CELL:123\nEND:VCARD\n";
+        assert_eq!(
+            convert(v3, VcardVersion::V4),
+            "BEGIN:VCARD\r\nVERSION:4.0\r\nN:Doe;Jane;;;\r\nTEL;TYPE=CELL:123\r\nEND:VCARD\r\n"
+        );
+    }
+
+    #[test]
+    fn fold_and_unfold() {
+        let line = format!("NOTE:{}", "ä".repeat(40));
+        let folded = fold(&line);
+        let lines: Vec<_> = folded.split("\r\n").collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1].starts_with(' '));
+        assert_eq!(lines[2], "");
+        assert_eq!(unfold(&folded), vec![line]);
+
+        assert_eq!(fold("FN:Jane"), "FN:Jane\r\n");
+        assert_eq!(
+            unfold("FN:Ja\r\n ne\r\n\tDoe\r\n\r\nN:Doe;;;;"),
+            vec!["FN:JaneDoe", "N:Doe;;;;"]
+        );
+    }
+
+    #[test]
+    fn split_unquoted_skips_quoted_separators() {
+        assert_eq!(
+            split_unquoted(r#"ADR;LABEL="a:b":;;Main St"#, ':'),
+            Some((r#"ADR;LABEL="a:b""#, ";;Main St"))
+        );
+        assert_eq!(split_unquoted("FN:a:b", ':'), Some(("FN", "a:b")));
+        assert_eq!(split_unquoted(r#"X;P="a:b""#, ':'), None);
+    }
+}
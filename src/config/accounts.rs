@@ -0,0 +1,265 @@
+//! Account lists of batch migrations. Every account may override fields of
+//! the `imap.pull` and `imap.push` servers, e.g. the source host, folder
+//! mappings or credentials, and may push messages stored for a different
+//! source account.
+
+use anyhow::Context;
+use serde_yaml::{Mapping, Value};
+use std::{env, fs, path::Path};
+
+use super::{field_value, imap_field_keys, merge, set_value};
+
+/// Fields applied to both servers when given for the whole account
+const SHARED_FIELDS: &[&str] = &["login", "password"];
+
+const FROM_ACCOUNT_FIELD: &str = "from_account";
+
+#[derive(Clone)]
+pub struct Account {
+    pub email: String,
+    /// Stored account pushed to `email`
+    pub from_account: Option<String>,
+    /// `login` and `password` of both configured servers
+    pub credentials: Mapping,
+    /// Merged into the `imap` section of the config
+    pub overrides: Mapping,
+}
+
+/// `--accounts` or `OTC_ACCOUNTS`.
+pub fn list_path(path: Option<&str>) -> Option<String> {
+    path.map(str::to_string)
+        .or_else(|| env::var("OTC_ACCOUNTS").ok())
+}
+
+/// Finds `email` in the account list, `None` without a list.
+pub fn find(path: Option<&str>, email: &str) -> anyhow::Result<Option<Account>> {
+    let Some(path) = list_path(path) else {
+        return Ok(None);
+    };
+
+    let account = load(Path::new(&path))?
+        .into_iter()
+        .find(|account| account.email.eq_ignore_ascii_case(email))
+        .context(format!("account {email} not found in {path}"))?;
+    log::debug!("Using account list {path}");
+
+    Ok(Some(account))
+}
+
+/// Reads a YAML list, or a CSV file with `email,password` rows or a header
+/// naming the columns.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Account>> {
+    let data = fs::read_to_string(path)
+        .context(format!("unable to read account list {}", path.display()))?;
+
+    let is_yaml = path
+        .extension()
+        .is_some_and(|extension| extension == "yaml" || extension == "yml");
+    let accounts = if is_yaml {
+        load_yaml(&data)
+    } else {
+        load_csv(&data)
+    };
+
+    accounts.context(format!("malformed account list {}", path.display()))
+}
+
+fn load_yaml(data: &str) -> anyhow::Result<Vec<Account>> {
+    let entries: Vec<Mapping> = serde_yaml::from_str(data)?;
+
+    let mut accounts = Vec::new();
+    for (index, mut entry) in entries.into_iter().enumerate() {
+        let email = entry
+            .remove("email")
+            .and_then(|email| email.as_str().map(str::to_string))
+            .context(format!("entry {} has no email", index + 1))?;
+        let from_account = match entry.remove(FROM_ACCOUNT_FIELD) {
+            Some(Value::String(from_account)) => Some(from_account),
+            Some(_) => anyhow::bail!("{FROM_ACCOUNT_FIELD} of {email} must be an e-mail"),
+            None => None,
+        };
+
+        let mut credentials = Mapping::new();
+        for field in SHARED_FIELDS {
+            if let Some(value) = entry.remove(*field) {
+                credentials.insert(Value::from(*field), value);
+            }
+        }
+        let mut overrides = Mapping::new();
+        for role in ["pull", "push"] {
+            if let Some(role_overrides) = entry.remove(role) {
+                if !role_overrides.is_mapping() {
+                    anyhow::bail!("{role} of {email} must be a mapping");
+                }
+                let role_entry = overrides
+                    .entry(Value::from(role))
+                    .or_insert_with(|| Value::Mapping(Mapping::new()));
+                let role_mapping = role_entry
+                    .as_mapping_mut()
+                    .expect("role overrides are a mapping");
+                merge(role_mapping, role_overrides);
+            }
+        }
+
+        if let Some(key) = entry.keys().next() {
+            anyhow::bail!(
+                "unknown field {:?} of {}, expected email, from_account, login, password, pull or push",
+                key.as_str().unwrap_or_default(),
+                email
+            );
+        }
+
+        accounts.push(Account {
+            email,
+            from_account,
+            credentials,
+            overrides,
+        });
+    }
+
+    Ok(accounts)
+}
+
+fn load_csv(data: &str) -> anyhow::Result<Vec<Account>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+    let mut rows = reader.records();
+
+    // Lists without a header hold e-mail and password
+    let mut columns = vec!["email".to_string(), "password".to_string()];
+    let mut first_row = None;
+    if let Some(row) = rows.next() {
+        let row = row?;
+        // Spreadsheets may capitalize the header or start the file with a BOM
+        let is_header = row.get(0).is_some_and(|column| {
+            column
+                .trim_start_matches('\u{feff}')
+                .eq_ignore_ascii_case("email")
+        });
+        if is_header {
+            columns = row.iter().map(|column| column.to_lowercase()).collect();
+        } else {
+            first_row = Some(row);
+        }
+    }
+
+    let from_account_column = columns
+        .iter()
+        .position(|column| column == FROM_ACCOUNT_FIELD);
+    let mut column_keys = Vec::new();
+    for column in columns.iter().skip(1) {
+        if column == FROM_ACCOUNT_FIELD {
+            column_keys.push((column, None));
+            continue;
+        }
+        let keys = if SHARED_FIELDS.contains(&column.as_str()) {
+            None
+        } else {
+            Some(imap_field_keys(column).context(format!(
+                "unknown column {column:?}, expected email, from_account, login, password, pull_<field> or push_<field>"
+            ))?)
+        };
+        column_keys.push((column, keys));
+    }
+
+    let mut accounts = Vec::new();
+    for row in first_row.into_iter().map(Ok).chain(rows) {
+        let row = row?;
+        let Some(email) = row.get(0).filter(|email| !email.is_empty()) else {
+            continue;
+        };
+
+        let mut credentials = Mapping::new();
+        let mut overrides = Value::Mapping(Mapping::new());
+        for (cell, (column, keys)) in row.iter().skip(1).zip(column_keys.iter()) {
+            if cell.is_empty() {
+                continue;
+            }
+            match keys {
+                Some(keys) => {
+                    let value = field_value(keys, cell).context(format!(
+                        "invalid {} of {}",
+                        keys.join("_"),
+                        email
+                    ))?;
+                    set_value(&mut overrides, keys, value);
+                }
+                None if *column == FROM_ACCOUNT_FIELD => {}
+                None => {
+                    credentials.insert(Value::from(column.as_str()), Value::from(cell));
+                }
+            }
+        }
+
+        let from_account = from_account_column
+            .and_then(|column| row.get(column))
+            .filter(|from_account| !from_account.is_empty())
+            .map(str::to_string);
+
+        accounts.push(Account {
+            email: email.to_string(),
+            from_account,
+            credentials,
+            overrides: match overrides {
+                Value::Mapping(overrides) => overrides,
+                _ => Mapping::new(),
+            },
+        });
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn csv_without_header() {
+        let accounts = load_csv("a@example.com,secret\nb@example.com,other\n").unwrap();
+
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].email, "a@example.com");
+        assert_eq!(
+            Value::Mapping(accounts[0].credentials.clone()),
+            value("password: secret")
+        );
+        assert!(accounts[0].overrides.is_empty());
+        assert_eq!(accounts[1].email, "b@example.com");
+    }
+
+    #[test]
+    fn csv_header_is_case_insensitive() {
+        for header in ["email", "Email", "EMAIL", "\u{feff}Email"] {
+            let data = format!(
+                "{header},Password,PULL_PORT,From_Account\na@example.com,secret,1143,old@example.com\n"
+            );
+            let accounts = load_csv(&data).unwrap();
+
+            assert_eq!(accounts.len(), 1, "{header:?}");
+            let account = &accounts[0];
+            assert_eq!(account.email, "a@example.com");
+            assert_eq!(account.from_account.as_deref(), Some("old@example.com"));
+            assert_eq!(
+                Value::Mapping(account.credentials.clone()),
+                value("password: secret")
+            );
+            assert_eq!(
+                Value::Mapping(account.overrides.clone()),
+                value("pull: { port: 1143 }")
+            );
+        }
+    }
+
+    #[test]
+    fn csv_rejects_unknown_columns() {
+        assert!(load_csv("Email,colour\na@example.com,red\n").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use super::{accounts, selected_profile, Config, ConfigSource, ImapServerConfig};
+use crate::imap::connect;
+
+/// Validates the top-level config, every profile and the account list,
+/// optionally logs in to the IMAP servers of the selected profile.
+pub async fn check(
+    path: Option<&str>,
+    profile: Option<&str>,
+    accounts_path: Option<&str>,
+    email: Option<String>,
+    password: Option<String>,
+) -> anyhow::Result<()> {
+    let profile = selected_profile(profile);
+    let source = ConfigSource::read(path)?;
+    match source.path {
+        Some(ref path) => println!("Config: {}", path.display()),
+        None => println!("Config: environment variables only"),
+    }
+
+    let mut error_count = 0;
+    if let Some(ref profile) = profile {
+        if !source.profile_names().contains(profile) {
+            println!("✘ profile {profile:?} not found");
+            error_count += 1;
+        }
+    }
+
+    let mut selected_config = None;
+    let profiles = std::iter::once(None).chain(source.profile_names().into_iter().map(Some));
+    for checked_profile in profiles {
+        let name = checked_profile
+            .as_deref()
+            .map(|checked_profile| format!("profile {checked_profile}"))
+            .unwrap_or_else(|| "top-level".to_string());
+        match source.resolve(checked_profile.as_deref(), None) {
+            Ok(config) => {
+                println!("✔ {name}");
+                if checked_profile == profile {
+                    selected_config = Some(config);
+                }
+            }
+            Err(err) => {
+                println!("✘ {name}: {err}");
+                error_count += 1;
+            }
+        }
+    }
+
+    let account_list = accounts::list_path(accounts_path);
+    if let Some(ref account_list) = account_list {
+        println!("Accounts: {account_list}");
+        match accounts::load(Path::new(account_list)) {
+            Ok(accounts) => {
+                let mut valid_count = 0;
+                for account in accounts.iter() {
+                    match source.resolve(profile.as_deref(), Some(account)) {
+                        Ok(_) => valid_count += 1,
+                        Err(err) => {
+                            println!("✘ account {}: {}", account.email, err);
+                            error_count += 1;
+                        }
+                    }
+                }
+                println!("✔ {valid_count} of {} accounts", accounts.len());
+            }
+            Err(err) => {
+                println!("✘ {err:#}");
+                error_count += 1;
+            }
+        }
+    }
+
+    if let Some(ref config) = selected_config {
+        print_servers(config);
+    }
+
+    if let Some(email) = email.filter(|_| selected_config.is_some()) {
+        let account = accounts::find(accounts_path, &email)?;
+        let config = source.resolve(profile.as_deref(), account.as_ref())?;
+        let imap_config = config.imap.as_ref();
+        let servers = [
+            ("pull", imap_config.and_then(|imap| imap.pull.as_ref())),
+            ("push", imap_config.and_then(|imap| imap.push.as_ref())),
+        ];
+        for (role, imap_server_config) in servers {
+            let Some(imap_server_config) = imap_server_config else {
+                continue;
+            };
+            if let Err(err) = login(imap_server_config, &email, password.as_deref()).await {
+                println!("✘ IMAP {role} login as {email}: {err:#}");
+                error_count += 1;
+            } else {
+                println!("✔ IMAP {role} login as {email}");
+            }
+        }
+    }
+
+    if error_count > 0 {
+        anyhow::bail!("config check found {error_count} errors");
+    }
+
+    Ok(())
+}
+
+pub fn schema() -> anyhow::Result<()> {
+    let schema = schemars::schema_for!(Config);
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+
+    Ok(())
+}
+
+async fn login(
+    imap_config: &ImapServerConfig,
+    email: &str,
+    password: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut imap_session = connect(imap_config, email, password).await?;
+    imap_session.logout().await?;
+
+    Ok(())
+}
+
+fn print_servers(config: &Config) {
+    let mut servers = Vec::new();
+    if let Some(ref imap) = config.imap {
+        for (role, server) in [("pull", &imap.pull), ("push", &imap.push)] {
+            if let Some(server) = server {
+                servers.push(format!(
+                    "IMAP {role}: {}:{}",
+                    server.server,
+                    server.port.unwrap_or(993)
+                ));
+            }
+        }
+    }
+    if let Some(ref pop3) = config.pop3 {
+        if let Some(ref server) = pop3.pull {
+            servers.push(format!("POP3 pull: {}", server.server));
+        }
+    }
+    if let Some(ref jmap) = config.jmap {
+        for (role, server) in [("pull", &jmap.pull), ("push", &jmap.push)] {
+            if let Some(server) = server {
+                servers.push(format!("JMAP {role}: {}", server.url));
+            }
+        }
+    }
+    for (protocol, dav) in [
+        ("CalDAV", &config.caldav),
+        ("CardDAV", &config.carddav),
+        ("WebDAV", &config.webdav),
+    ] {
+        if let Some(dav) = dav {
+            for (role, server) in [("pull", &dav.pull), ("push", &dav.push)] {
+                if let Some(server) = server {
+                    servers.push(format!("{protocol} {role}: {}", server.url));
+                }
+            }
+        }
+    }
+    if let Some(ref sieve) = config.sieve {
+        for (role, server) in [("pull", &sieve.pull), ("push", &sieve.push)] {
+            if let Some(server) = server {
+                servers.push(format!("Sieve {role}: {}", server.server));
+            }
+        }
+    }
+    if let Some(ref smtp) = config.smtp {
+        if let Some(ref server) = smtp.push {
+            servers.push(format!("SMTP push: {}", server.server));
+        }
+    }
+
+    if servers.is_empty() {
+        println!("No servers configured");
+    }
+    for server in servers {
+        println!("  {server}");
+    }
+}
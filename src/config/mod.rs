@@ -0,0 +1,877 @@
+use anyhow::Context;
+use regex::Regex;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde::{Deserialize, Deserializer};
+use serde_yaml::{Mapping, Value};
+use std::{borrow::Cow, collections::HashMap, env, path::PathBuf};
+
+use accounts::Account;
+
+pub mod accounts;
+pub mod check;
+
+pub const CONFIG_FILE_NAME: &str = "config.yaml";
+/// Overrides `ImapServerConfig` fields, e.g. `OTC_IMAP_PUSH_SERVER`
+const IMAP_ENV_PREFIX: &str = "OTC_IMAP_";
+const IMAP_SERVER_FIELDS: &[&str] = &[
+    "server",
+    "port",
+    "folder_delimiter",
+    "folder_name_mappings",
+    "folder_rules",
+    "gmail_label_mappings",
+    "rate_limit",
+    "login",
+    "password",
+];
+/// Fields taken as given instead of parsed as YAML, e.g. numeric passwords
+const IMAP_TEXT_FIELDS: &[&str] = &["server", "login", "password"];
+const RATE_LIMIT_FIELDS: &[&str] = &["messages_per_second", "bytes_per_second", "daily_bytes"];
+
+/// Size in bytes, given as a number or text like `500 MB`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawSize {
+            Bytes(u64),
+            Text(String),
+        }
+
+        match RawSize::deserialize(deserializer)? {
+            RawSize::Bytes(bytes) => Ok(ByteSize(bytes)),
+            RawSize::Text(text) => parse_size::parse_size(&text)
+                .map(ByteSize)
+                .map_err(|err| serde::de::Error::custom(format!("invalid size {text:?}: {err}"))),
+        }
+    }
+}
+
+impl JsonSchema for ByteSize {
+    fn schema_name() -> Cow<'static, str> {
+        "ByteSize".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "Size in bytes, given as a number or text like `500 MB`",
+            "type": ["integer", "string"],
+            "minimum": 0
+        })
+    }
+}
+
+/// Password kept out of debug output
+#[derive(Clone, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Secret(pub String);
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("\"***\"")
+    }
+}
+
+/// Limits shared by all connections to a server
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub messages_per_second: Option<f64>,
+    pub bytes_per_second: Option<ByteSize>,
+    /// Transfers stop once the bytes of the current UTC day reach it
+    pub daily_bytes: Option<ByteSize>,
+}
+
+/// Rewrites target folders, rules are tried in order and the first matching
+/// one applies
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "FolderRuleConfig")]
+pub struct FolderRule {
+    matcher: FolderMatcher,
+    action: FolderAction,
+}
+
+#[derive(Debug, Clone)]
+enum FolderMatcher {
+    Pattern(Regex),
+    /// Folder and its subfolders, the empty prefix matches every folder
+    Prefix(String),
+}
+
+#[derive(Debug, Clone)]
+enum FolderAction {
+    Rename(String),
+    Flatten(Option<String>),
+    Drop,
+}
+
+/// Folder rule as written in the config
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct FolderRuleConfig {
+    /// Regular expression, `rename` replaces the matched part and may refer
+    /// to groups like `$1`
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    /// Folder matched together with its subfolders, levels are separated by `/`
+    prefix: Option<String>,
+    /// New name of the matched part, subfolders below `prefix` are kept
+    rename: Option<String>,
+    /// Merges the subfolders of `prefix` into the folder or into `rename`
+    #[serde(default)]
+    flatten: bool,
+    /// Skips the matched folders
+    #[serde(default)]
+    drop: bool,
+}
+
+impl TryFrom<FolderRuleConfig> for FolderRule {
+    type Error = String;
+
+    fn try_from(config: FolderRuleConfig) -> Result<Self, Self::Error> {
+        let matcher = match (config.pattern, config.prefix) {
+            (Some(pattern), None) => FolderMatcher::Pattern(
+                Regex::new(&pattern).map_err(|err| format!("invalid match {pattern:?}: {err}"))?,
+            ),
+            (None, Some(prefix)) => FolderMatcher::Prefix(prefix.trim_end_matches('/').to_string()),
+            _ => return Err("folder rule needs either match or prefix".to_string()),
+        };
+        let action = match (config.rename, config.flatten, config.drop) {
+            (None, false, true) => FolderAction::Drop,
+            (Some(rename), false, false) => FolderAction::Rename(rename),
+            (rename, true, false) => match matcher {
+                FolderMatcher::Prefix(ref prefix) if !prefix.is_empty() || rename.is_some() => {
+                    FolderAction::Flatten(rename)
+                }
+                _ => return Err("flatten needs a non-empty prefix or rename".to_string()),
+            },
+            _ => return Err("folder rule needs one of rename, flatten or drop".to_string()),
+        };
+
+        Ok(FolderRule { matcher, action })
+    }
+}
+
+impl JsonSchema for FolderRule {
+    fn schema_name() -> Cow<'static, str> {
+        "FolderRule".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        FolderRuleConfig::json_schema(generator)
+    }
+}
+
+impl FolderRule {
+    /// `None` when the rule doesn't match `name`, `Some(None)` when it drops
+    /// the folder.
+    fn apply(&self, name: &str) -> Option<Option<String>> {
+        match self.matcher {
+            FolderMatcher::Pattern(ref regex) => {
+                if !regex.is_match(name) {
+                    return None;
+                }
+                match self.action {
+                    FolderAction::Rename(ref rename) => {
+                        Some(Some(regex.replace(name, rename.as_str()).into_owned()))
+                    }
+                    _ => Some(None),
+                }
+            }
+            FolderMatcher::Prefix(ref prefix) => {
+                let subfolder = if prefix.is_empty() {
+                    name
+                } else if name == prefix {
+                    ""
+                } else {
+                    name.strip_prefix(prefix.as_str())?.strip_prefix('/')?
+                };
+                match self.action {
+                    FolderAction::Rename(ref rename) => {
+                        let rename = rename.trim_end_matches('/');
+                        Some(Some(match (rename.is_empty(), subfolder.is_empty()) {
+                            (true, _) => subfolder.to_string(),
+                            (false, true) => rename.to_string(),
+                            (false, false) => format!("{rename}/{subfolder}"),
+                        }))
+                    }
+                    FolderAction::Flatten(ref rename) => {
+                        Some(Some(rename.clone().unwrap_or_else(|| prefix.clone())))
+                    }
+                    FolderAction::Drop => Some(None),
+                }
+            }
+        }
+    }
+}
+
+/// Target folder of `name` by `folder_name_mappings` or the first matching
+/// `folder_rules`, `None` when the folder is dropped.
+fn map_folder_name(
+    folder_name_mappings: &Option<HashMap<String, String>>,
+    folder_rules: &Option<Vec<FolderRule>>,
+    name: &str,
+) -> Option<String> {
+    if let Some(mapped_name) = folder_name_mappings
+        .as_ref()
+        .and_then(|folder_name_mappings| folder_name_mappings.get(name))
+    {
+        return Some(mapped_name.clone());
+    }
+
+    let rules = folder_rules.as_deref().unwrap_or_default();
+    match rules.iter().find_map(|rule| rule.apply(name)) {
+        Some(mapped_name) => {
+            log::debug!("Folder {name:?} mapped to {mapped_name:?} by folder_rules");
+            mapped_name
+        }
+        None => Some(name.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImapServerConfig {
+    pub server: String,
+    pub port: Option<u16>,
+    pub folder_delimiter: Option<char>,
+    pub folder_name_mappings: Option<HashMap<String, String>>,
+    /// Applied to folders without an exact `folder_name_mappings` entry
+    pub folder_rules: Option<Vec<FolderRule>>,
+    /// Target folders of Gmail labels, `null` skips a label
+    pub gmail_label_mappings: Option<HashMap<String, Option<String>>>,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Login name, the account e-mail by default
+    pub login: Option<String>,
+    /// Used when `--password` is not given
+    pub password: Option<Secret>,
+}
+
+impl ImapServerConfig {
+    /// Target folder of `name`, `None` when the folder is dropped.
+    pub fn map_folder_name(&self, name: &str) -> Option<String> {
+        map_folder_name(&self.folder_name_mappings, &self.folder_rules, name)
+    }
+
+    /// Login name and password, `password` from the command line wins over
+    /// the configured one.
+    pub fn credentials(
+        &self,
+        email: &str,
+        password: Option<&str>,
+    ) -> anyhow::Result<(String, String)> {
+        let password = password
+            .map(str::to_string)
+            .or_else(|| self.password.as_ref().map(|password| password.0.clone()))
+            .context(format!(
+                "no password for {email} on {}, use --password or set it in the config or account list",
+                self.server
+            ))?;
+
+        Ok((
+            self.login.clone().unwrap_or_else(|| email.to_string()),
+            password,
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImapConfig {
+    pub pull: Option<ImapServerConfig>,
+    pub push: Option<ImapServerConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Pop3Security {
+    /// POP3S, implicit TLS
+    #[default]
+    Tls,
+    /// Plain connection upgraded with STLS
+    Starttls,
+    /// Unencrypted connection, for local testing only
+    Plain,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Pop3ServerConfig {
+    pub server: String,
+    pub port: Option<u16>,
+    pub security: Option<Pop3Security>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Pop3Config {
+    pub pull: Option<Pop3ServerConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SieveSecurity {
+    /// Plain connection upgraded with STARTTLS, the ManageSieve default
+    #[default]
+    Starttls,
+    /// Implicit TLS
+    Tls,
+    /// Unencrypted connection, for local testing only
+    Plain,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SieveServerConfig {
+    pub server: String,
+    pub port: Option<u16>,
+    pub security: Option<SieveSecurity>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SieveConfig {
+    pub pull: Option<SieveServerConfig>,
+    pub push: Option<SieveServerConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryProtocol {
+    /// SMTP (RFC 5321), default port 25
+    #[default]
+    Smtp,
+    /// LMTP (RFC 2033), default port 24
+    Lmtp,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS
+    #[default]
+    Starttls,
+    /// Implicit TLS
+    Tls,
+    /// Unencrypted connection, e.g. LMTP on a trusted network
+    Plain,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpServerConfig {
+    pub server: String,
+    pub port: Option<u16>,
+    pub protocol: Option<DeliveryProtocol>,
+    pub security: Option<SmtpSecurity>,
+    pub folder_delimiter: Option<char>,
+    pub folder_name_mappings: Option<HashMap<String, String>>,
+    /// Applied to folders without an exact `folder_name_mappings` entry
+    pub folder_rules: Option<Vec<FolderRule>>,
+    /// Header carrying the target folder, `+folder` sub-address is used when not set
+    pub folder_header: Option<String>,
+}
+
+impl SmtpServerConfig {
+    /// Target folder of `name`, `None` when the folder is dropped.
+    pub fn map_folder_name(&self, name: &str) -> Option<String> {
+        map_folder_name(&self.folder_name_mappings, &self.folder_rules, name)
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub push: Option<SmtpServerConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JmapAuth {
+    /// HTTP Basic with e-mail and password
+    #[default]
+    Basic,
+    /// Password is used as a bearer API token
+    Bearer,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JmapServerConfig {
+    pub url: String,
+    pub auth: Option<JmapAuth>,
+    pub folder_name_mappings: Option<HashMap<String, String>>,
+    /// Applied to folders without an exact `folder_name_mappings` entry
+    pub folder_rules: Option<Vec<FolderRule>>,
+}
+
+impl JmapServerConfig {
+    /// Target mailbox of `name`, `None` when the mailbox is dropped.
+    pub fn map_folder_name(&self, name: &str) -> Option<String> {
+        map_folder_name(&self.folder_name_mappings, &self.folder_rules, name)
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JmapConfig {
+    pub pull: Option<JmapServerConfig>,
+    pub push: Option<JmapServerConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DavServerConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DavConfig {
+    pub pull: Option<DavServerConfig>,
+    pub push: Option<DavServerConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub imap: Option<ImapConfig>,
+    pub pop3: Option<Pop3Config>,
+    pub jmap: Option<JmapConfig>,
+    pub caldav: Option<DavConfig>,
+    pub carddav: Option<DavConfig>,
+    pub webdav: Option<DavConfig>,
+    pub sieve: Option<SieveConfig>,
+    pub smtp: Option<SmtpConfig>,
+    /// Settings merged over the top-level ones with `--profile`
+    #[schemars(with = "Option<HashMap<String, serde_json::Value>>")]
+    pub profiles: Option<HashMap<String, Value>>,
+}
+
+impl Config {
+    /// Reads the config from `path`, `OTC_CONFIG` or the first default
+    /// location found, merges the selected profile into it and applies
+    /// `OTC_IMAP_*` environment variables.
+    pub fn load(
+        path: Option<&str>,
+        profile: Option<&str>,
+        account: Option<&Account>,
+    ) -> anyhow::Result<Config> {
+        let profile = selected_profile(profile);
+        if let Some(ref profile) = profile {
+            log::info!("Using profile {profile}");
+        }
+
+        ConfigSource::read(path)?.resolve(profile.as_deref(), account)
+    }
+}
+
+/// `--profile` or `OTC_PROFILE`.
+fn selected_profile(profile: Option<&str>) -> Option<String> {
+    profile
+        .map(str::to_string)
+        .or_else(|| env::var("OTC_PROFILE").ok())
+}
+
+/// Config file as read, before a profile and overrides are applied.
+#[derive(Debug)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    data: Option<String>,
+    value: Mapping,
+    profiles: Mapping,
+}
+
+impl ConfigSource {
+    pub fn read(path: Option<&str>) -> anyhow::Result<ConfigSource> {
+        let Some(path) = config_path(path)? else {
+            if env::vars().any(|(name, _)| name.starts_with(IMAP_ENV_PREFIX)) {
+                return Ok(ConfigSource {
+                    path: None,
+                    data: None,
+                    value: Mapping::new(),
+                    profiles: Mapping::new(),
+                });
+            }
+            anyhow::bail!(
+                "config.yaml not found in the current directory or {}, use --config or OTC_CONFIG",
+                default_paths()
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+
+        log::debug!("Loading config {}", path.display());
+        let data = std::fs::read_to_string(&path)
+            .context(format!("unable to read config {}", path.display()))?;
+        // Syntax errors and duplicate keys are reported with their line
+        let value: Value = serde_yaml::from_str(&data)
+            .map_err(|err| anyhow::anyhow!("config {} parse error: {}", path.display(), err))?;
+        let mut value = match value {
+            Value::Mapping(mapping) => mapping,
+            Value::Null => Mapping::new(),
+            _ => anyhow::bail!("config {} must be a mapping", path.display()),
+        };
+        let profiles = match value.remove("profiles") {
+            Some(Value::Mapping(profiles)) => profiles,
+            None | Some(Value::Null) => Mapping::new(),
+            Some(_) => anyhow::bail!("profiles of config {} must be a mapping", path.display()),
+        };
+
+        Ok(ConfigSource {
+            path: Some(path),
+            data: Some(data),
+            value,
+            profiles,
+        })
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles
+            .keys()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Builds the config of `profile`, or the top-level one for `None`, with
+    /// the overrides of `account` on top.
+    pub fn resolve(
+        &self,
+        profile: Option<&str>,
+        account: Option<&Account>,
+    ) -> anyhow::Result<Config> {
+        let mut value = self.value.clone();
+        if let Some(profile) = profile {
+            if self.profiles.is_empty() {
+                anyhow::bail!("profile {profile:?} not found, no profiles configured");
+            }
+            let profile_config = self.profiles.get(profile).context(format!(
+                "profile {:?} not found, configured: {}",
+                profile,
+                self.profile_names().join(", ")
+            ))?;
+            merge(&mut value, profile_config.clone());
+        }
+        let overridden = apply_env_overrides(&mut value)?;
+        if let Some(account) = account {
+            let imap = value
+                .entry(Value::from("imap"))
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            if !imap.is_mapping() {
+                *imap = Value::Mapping(Mapping::new());
+            }
+            let imap = imap.as_mapping_mut().expect("imap is a mapping");
+            // Credentials apply only to servers the config or the account sets up
+            for role in ["pull", "push"] {
+                if imap.contains_key(role) || account.overrides.contains_key(role) {
+                    let server = imap
+                        .entry(Value::from(role))
+                        .or_insert_with(|| Value::Mapping(Mapping::new()));
+                    if !server.is_mapping() {
+                        *server = Value::Mapping(Mapping::new());
+                    }
+                    let server = server.as_mapping_mut().expect("server is a mapping");
+                    merge(server, Value::Mapping(account.credentials.clone()));
+                }
+            }
+            merge(imap, Value::Mapping(account.overrides.clone()));
+        }
+
+        let file_name = self
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "from environment".to_string());
+        let config: Config = match serde_path_to_error::deserialize(Value::Mapping(value)) {
+            Ok(config) => config,
+            Err(err) => {
+                // Errors of the file itself are located by line
+                if profile.is_none() && !overridden && account.is_none() {
+                    if let Some(Err(file_err)) = self
+                        .data
+                        .as_ref()
+                        .map(|data| serde_yaml::from_str::<Config>(data))
+                    {
+                        anyhow::bail!("config {file_name} error: {file_err}");
+                    }
+                }
+                let mut origins = Vec::new();
+                if let Some(profile) = profile {
+                    origins.push(format!("profile {profile:?}"));
+                }
+                if overridden {
+                    origins.push("environment overrides".to_string());
+                }
+                if let Some(account) = account {
+                    origins.push(format!("account {}", account.email));
+                }
+                let origin = if origins.is_empty() {
+                    String::new()
+                } else {
+                    format!(" with {}", origins.join(" and "))
+                };
+                anyhow::bail!(
+                    "config {}{} error at {}: {}",
+                    file_name,
+                    origin,
+                    err.path(),
+                    err.inner()
+                );
+            }
+        };
+        log::debug!("Config: {:?}", config);
+
+        Ok(config)
+    }
+}
+
+/// Explicit path first, then `./config.yaml` and the XDG config directories.
+fn config_path(path: Option<&str>) -> anyhow::Result<Option<PathBuf>> {
+    let explicit_path = path
+        .map(str::to_string)
+        .or_else(|| env::var("OTC_CONFIG").ok());
+    if let Some(path) = explicit_path {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            anyhow::bail!("config {} not found", path.display());
+        }
+        return Ok(Some(path));
+    }
+
+    Ok(std::iter::once(PathBuf::from(CONFIG_FILE_NAME))
+        .chain(default_paths())
+        .find(|path| path.is_file()))
+}
+
+/// `$XDG_CONFIG_HOME/off-the-cloud/config.yaml`, then every directory of
+/// `$XDG_CONFIG_DIRS`.
+fn default_paths() -> Vec<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let config_dirs = env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+
+    config_home
+        .into_iter()
+        .chain(config_dirs.split(':').map(PathBuf::from))
+        .map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(CONFIG_FILE_NAME))
+        .collect()
+}
+
+/// Merges `overrides` into `base`, mappings key by key, other values replaced.
+fn merge(base: &mut Mapping, overrides: Value) {
+    let Value::Mapping(overrides) = overrides else {
+        return;
+    };
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Mapping(base_value)), Value::Mapping(value)) => {
+                merge(base_value, Value::Mapping(value))
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Sets `imap.{pull,push}` fields from variables like `OTC_IMAP_PULL_PORT=993`
+/// or `OTC_IMAP_PUSH_RATE_LIMIT_DAILY_BYTES="2 GB"`. Values are parsed as YAML,
+/// so mappings can be given inline. Returns `true` if any was set.
+fn apply_env_overrides(config: &mut Mapping) -> anyhow::Result<bool> {
+    let mut overridden = false;
+    for (name, raw_value) in env::vars() {
+        let Some(field_path) = name.strip_prefix(IMAP_ENV_PREFIX) else {
+            continue;
+        };
+        let keys = imap_field_keys(&field_path.to_lowercase()).context(format!(
+            "unknown config variable {name}, expected OTC_IMAP_PULL_<FIELD> or OTC_IMAP_PUSH_<FIELD>"
+        ))?;
+        let value = field_value(&keys, &raw_value).context(format!("{name} parse error"))?;
+        log::debug!("Config imap.{} set by {}", keys.join("."), name);
+
+        let imap = config
+            .entry(Value::from("imap"))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+        set_value(imap, &keys, value);
+        overridden = true;
+    }
+
+    Ok(overridden)
+}
+
+/// Splits names like `push_rate_limit_daily_bytes` into the keys of an
+/// `imap` field, `["push", "rate_limit", "daily_bytes"]`.
+fn imap_field_keys(name: &str) -> Option<Vec<String>> {
+    let (role, field) = name.split_once('_')?;
+    if role != "pull" && role != "push" {
+        return None;
+    }
+
+    let mut keys = vec![role.to_string()];
+    match field.strip_prefix("rate_limit_") {
+        Some(rate_limit_field) if RATE_LIMIT_FIELDS.contains(&rate_limit_field) => {
+            keys.push("rate_limit".to_string());
+            keys.push(rate_limit_field.to_string());
+        }
+        _ if IMAP_SERVER_FIELDS.contains(&field) => keys.push(field.to_string()),
+        _ => return None,
+    }
+
+    Some(keys)
+}
+
+/// Parses a value given as text for the `imap` field at `keys`.
+fn field_value(keys: &[String], raw_value: &str) -> anyhow::Result<Value> {
+    if keys.len() == 2 && IMAP_TEXT_FIELDS.contains(&keys[1].as_str()) {
+        return Ok(Value::from(raw_value));
+    }
+
+    Ok(serde_yaml::from_str(raw_value)?)
+}
+
+/// Sets the value at `keys` below `target`, creating mappings on the way.
+fn set_value(target: &mut Value, keys: &[String], value: Value) {
+    let Some((first_key, other_keys)) = keys.split_first() else {
+        *target = value;
+        return;
+    };
+    if !target.is_mapping() {
+        *target = Value::Mapping(Mapping::new());
+    }
+    let mapping = target.as_mapping_mut().expect("target is a mapping");
+    let entry = mapping
+        .entry(Value::from(first_key.as_str()))
+        .or_insert(Value::Null);
+    set_value(entry, other_keys, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_rule(yaml: &str) -> FolderRule {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn pattern_rename_with_groups() {
+        let rule = parse_rule(r#"{ match: '^INBOX\.(.*)$', rename: '$1' }"#);
+        assert_eq!(rule.apply("INBOX.Work"), Some(Some("Work".to_string())));
+        assert_eq!(rule.apply("INBOX"), None);
+        assert_eq!(rule.apply("Sent"), None);
+
+        // Only the matched part is replaced
+        let rule = parse_rule(r#"{ match: 'Archive/(\d{4})', rename: 'Archives/$1' }"#);
+        assert_eq!(
+            rule.apply("Archive/2021"),
+            Some(Some("Archives/2021".to_string()))
+        );
+        assert_eq!(
+            rule.apply("Old/Archive/2021/Q1"),
+            Some(Some("Old/Archives/2021/Q1".to_string()))
+        );
+        assert_eq!(rule.apply("Archive/Misc"), None);
+    }
+
+    #[test]
+    fn drop_rules() {
+        let rule = parse_rule(r#"{ match: '^Spam$', drop: true }"#);
+        assert_eq!(rule.apply("Spam"), Some(None));
+        assert_eq!(rule.apply("Spam/Old"), None);
+
+        let rule = parse_rule("{ prefix: Notes/, drop: true }");
+        assert_eq!(rule.apply("Notes"), Some(None));
+        assert_eq!(rule.apply("Notes/2020"), Some(None));
+        assert_eq!(rule.apply("Notebook"), None);
+    }
+
+    #[test]
+    fn flatten_rules() {
+        let rule = parse_rule("{ prefix: Projects, flatten: true }");
+        assert_eq!(rule.apply("Projects"), Some(Some("Projects".to_string())));
+        assert_eq!(
+            rule.apply("Projects/A/B"),
+            Some(Some("Projects".to_string()))
+        );
+        assert_eq!(rule.apply("ProjectsOld"), None);
+
+        let rule = parse_rule("{ prefix: Projects, flatten: true, rename: Work }");
+        assert_eq!(rule.apply("Projects/A"), Some(Some("Work".to_string())));
+    }
+
+    #[test]
+    fn prefix_rename_nests_and_unnests() {
+        let rule = parse_rule("{ prefix: '', rename: Imported/ }");
+        assert_eq!(
+            rule.apply("INBOX"),
+            Some(Some("Imported/INBOX".to_string()))
+        );
+        assert_eq!(
+            rule.apply("Work/A"),
+            Some(Some("Imported/Work/A".to_string()))
+        );
+
+        let rule = parse_rule("{ prefix: Old, rename: Archive }");
+        assert_eq!(rule.apply("Old"), Some(Some("Archive".to_string())));
+        assert_eq!(rule.apply("Old/A"), Some(Some("Archive/A".to_string())));
+
+        let rule = parse_rule("{ prefix: Old, rename: '' }");
+        assert_eq!(rule.apply("Old/A"), Some(Some("A".to_string())));
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for yaml in [
+            "{ rename: A }",
+            "{ match: a, prefix: b, drop: true }",
+            "{ match: '(', rename: A }",
+            "{ prefix: A }",
+            "{ prefix: A, rename: B, drop: true }",
+            "{ match: a, flatten: true }",
+            "{ prefix: '', flatten: true }",
+        ] {
+            assert!(serde_yaml::from_str::<FolderRule>(yaml).is_err(), "{yaml}");
+        }
+    }
+
+    #[test]
+    fn map_folder_name_tries_mappings_then_rules_in_order() {
+        let config: ImapServerConfig = serde_yaml::from_str(
+            r#"
+server: imap.example.com
+folder_name_mappings:
+  INBOX.Spam: Junk
+folder_rules:
+  - { match: '^INBOX\.Trash$', drop: true }
+  - { match: '^INBOX\.(.*)$', rename: '$1' }
+  - { prefix: '', rename: Imported }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.map_folder_name("INBOX.Spam"),
+            Some("Junk".to_string())
+        );
+        assert_eq!(config.map_folder_name("INBOX.Trash"), None);
+        assert_eq!(
+            config.map_folder_name("INBOX.Work"),
+            Some("Work".to_string())
+        );
+        assert_eq!(
+            config.map_folder_name("INBOX"),
+            Some("Imported/INBOX".to_string())
+        );
+    }
+}
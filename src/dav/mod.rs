@@ -0,0 +1,486 @@
+use anyhow::Context;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{header, Method, StatusCode, Url};
+use std::{collections::HashMap, fs::File, io::Write, path::Path};
+
+pub const DAV_NS: &str = "DAV:";
+pub const CALDAV_NS: &str = "urn:ietf:params:xml:ns:caldav";
+pub const CARDDAV_NS: &str = "urn:ietf:params:xml:ns:carddav";
+
+/// Characters escaped in a path segment, everything but RFC 3986 unreserved ones.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Single `response` element of a `multistatus` body. Properties are keyed by
+/// local name; `resourcetype` holds the space separated names of its children,
+/// other properties their trimmed text content.
+#[derive(Debug, Clone)]
+pub struct DavResource {
+    pub url: Url,
+    pub props: HashMap<String, String>,
+}
+
+impl DavResource {
+    pub fn prop(&self, name: &str) -> Option<&str> {
+        self.props.get(name).map(|value| value.as_str())
+    }
+
+    pub fn is(&self, resource_type: &str) -> bool {
+        self.prop("resourcetype")
+            .unwrap_or_default()
+            .split_whitespace()
+            .any(|t| t == resource_type)
+    }
+
+    /// Decoded last path segment, e.g. `event.ics` or `Work`.
+    pub fn name(&self) -> String {
+        resource_name(&self.url)
+    }
+}
+
+/// Minimal WebDAV client (RFC 4918) shared by CalDAV, CardDAV and WebDAV commands.
+pub struct DavClient {
+    http: reqwest::Client,
+    email: String,
+    password: String,
+}
+
+impl DavClient {
+    pub fn new(email: &str, password: &str) -> DavClient {
+        DavClient {
+            http: reqwest::Client::new(),
+            email: email.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    fn request(&self, method: &str, url: &Url) -> reqwest::RequestBuilder {
+        self.http
+            .request(Method::from_bytes(method.as_bytes()).unwrap(), url.clone())
+            .basic_auth(&self.email, Some(&self.password))
+    }
+
+    pub async fn propfind(
+        &self,
+        url: &Url,
+        depth: &str,
+        body: &str,
+    ) -> anyhow::Result<Vec<DavResource>> {
+        self.multistatus("PROPFIND", url, depth, body).await
+    }
+
+    /// Runs `sync-collection` REPORT (RFC 6578) and returns changed resources
+    /// with the new sync token. Removed resources are not reported.
+    pub async fn sync_collection(
+        &self,
+        url: &Url,
+        sync_token: &str,
+        prop: &str,
+    ) -> anyhow::Result<(Vec<DavResource>, String)> {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<d:sync-collection xmlns:d="DAV:"><d:sync-token>{}</d:sync-token><d:sync-level>1</d:sync-level><d:prop>{prop}</d:prop></d:sync-collection>"#,
+            xml_escape(sync_token)
+        );
+        let text = self.multistatus_text("REPORT", url, "1", &body).await?;
+
+        let resources = parse_multistatus(url, &text)
+            .context(format!("malformed REPORT response from {url}"))?
+            .into_iter()
+            .filter(|resource| !resource.props.is_empty())
+            .collect();
+        let document = roxmltree::Document::parse(&text)?;
+        let sync_token = document
+            .root_element()
+            .children()
+            .find(|node| node.has_tag_name((DAV_NS, "sync-token")))
+            .and_then(|node| node.text())
+            .context("sync-collection response without sync-token")?
+            .trim()
+            .to_string();
+
+        Ok((resources, sync_token))
+    }
+
+    async fn multistatus(
+        &self,
+        method: &str,
+        url: &Url,
+        depth: &str,
+        body: &str,
+    ) -> anyhow::Result<Vec<DavResource>> {
+        let text = self.multistatus_text(method, url, depth, body).await?;
+
+        parse_multistatus(url, &text).context(format!("malformed {method} response from {url}"))
+    }
+
+    async fn multistatus_text(
+        &self,
+        method: &str,
+        url: &Url,
+        depth: &str,
+        body: &str,
+    ) -> anyhow::Result<String> {
+        let response = self
+            .request(method, url)
+            .header("Depth", depth)
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(body.to_string())
+            .send()
+            .await
+            .context(format!("{method} {url} failed"))?
+            .error_for_status()
+            .context(format!("{method} {url} failed"))?;
+
+        Ok(response.text().await?)
+    }
+
+    /// Returns body and ETag of the resource.
+    pub async fn get(&self, url: &Url) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+        let response = self
+            .request("GET", url)
+            .send()
+            .await
+            .context(format!("GET {url} failed"))?
+            .error_for_status()
+            .context(format!("GET {url} failed"))?;
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_string());
+        let data = response.bytes().await?;
+
+        Ok((data.to_vec(), etag))
+    }
+
+    /// Streams the resource into a file and returns its ETag.
+    pub async fn download(&self, url: &Url, path: &Path) -> anyhow::Result<Option<String>> {
+        let mut response = self
+            .request("GET", url)
+            .send()
+            .await
+            .context(format!("GET {url} failed"))?
+            .error_for_status()
+            .context(format!("GET {url} failed"))?;
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_string());
+
+        let mut file =
+            File::create(path).context(format!("unable to create {}", path.display()))?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk)
+                .context(format!("error writing {}", path.display()))?;
+        }
+        file.flush().context("error flushing file")?;
+
+        Ok(etag)
+    }
+
+    /// Uploads a file passing its modification time in `X-OC-Mtime`, which
+    /// Nextcloud and ownCloud apply to the stored file.
+    pub async fn upload(&self, url: &Url, data: Vec<u8>, mtime: i64) -> anyhow::Result<()> {
+        self.request("PUT", url)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header("X-OC-Mtime", mtime.to_string())
+            .body(data)
+            .send()
+            .await
+            .context(format!("PUT {url} failed"))?
+            .error_for_status()
+            .context(format!("PUT {url} failed"))?;
+
+        Ok(())
+    }
+
+    /// Uploads the resource and returns its new ETag if the server reports one.
+    pub async fn put(
+        &self,
+        url: &Url,
+        data: Vec<u8>,
+        content_type: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let response = self
+            .request("PUT", url)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(data)
+            .send()
+            .await
+            .context(format!("PUT {url} failed"))?
+            .error_for_status()
+            .context(format!("PUT {url} failed"))?;
+
+        Ok(response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_string()))
+    }
+
+    /// Creates a collection with `MKCOL` or `MKCALENDAR`. An already existing
+    /// collection is not an error.
+    pub async fn mkcol(&self, method: &str, url: &Url, body: Option<String>) -> anyhow::Result<()> {
+        let mut request = self.request(method, url);
+        if let Some(body) = body {
+            request = request
+                .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+                .body(body);
+        }
+        let response = request
+            .send()
+            .await
+            .context(format!("{method} {url} failed"))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::METHOD_NOT_ALLOWED => {
+                log::debug!("{url} already exists");
+                Ok(())
+            }
+            status => anyhow::bail!("{method} {url} failed: {status}"),
+        }
+    }
+
+    /// Follows `current-user-principal` and returns the given home set of the
+    /// principal, e.g. `calendar-home-set` in the CalDAV namespace.
+    pub async fn home_set(&self, url: &Url, ns: &str, home_set: &str) -> anyhow::Result<Url> {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:current-user-principal/></d:prop></d:propfind>"#;
+        let resources = self.propfind(url, "0", body).await?;
+        let principal = resources
+            .iter()
+            .find_map(|resource| resource.prop("current-user-principal"))
+            .context("server did not report current-user-principal")?;
+        let principal_url = url.join(principal)?;
+        log::debug!("Principal {principal_url}");
+
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:h="{ns}"><d:prop><h:{home_set}/></d:prop></d:propfind>"#
+        );
+        let resources = self.propfind(&principal_url, "0", &body).await?;
+        let home = resources
+            .iter()
+            .find_map(|resource| resource.prop(home_set))
+            .context(format!("server did not report {home_set}"))?;
+        let home_url = url.join(home)?;
+        log::debug!("Home set {home_url}");
+
+        Ok(home_url)
+    }
+}
+
+pub fn parse_multistatus(base_url: &Url, text: &str) -> anyhow::Result<Vec<DavResource>> {
+    let document = roxmltree::Document::parse(text)?;
+    let mut resources = Vec::new();
+
+    for response in document
+        .descendants()
+        .filter(|node| node.has_tag_name((DAV_NS, "response")))
+    {
+        let href = response
+            .children()
+            .find(|node| node.has_tag_name((DAV_NS, "href")))
+            .and_then(|node| node.text())
+            .context("response without href")?;
+
+        let mut props = HashMap::new();
+
+        for propstat in response
+            .children()
+            .filter(|node| node.has_tag_name((DAV_NS, "propstat")))
+        {
+            let ok = propstat
+                .children()
+                .find(|node| node.has_tag_name((DAV_NS, "status")))
+                .and_then(|node| node.text())
+                .map(|status| status.contains(" 200 "))
+                .unwrap_or(true);
+            if !ok {
+                continue;
+            }
+
+            for prop in propstat
+                .children()
+                .filter(|node| node.has_tag_name((DAV_NS, "prop")))
+                .flat_map(|node| node.children())
+                .filter(|node| node.is_element())
+            {
+                let name = prop.tag_name().name().to_string();
+                let value = if name == "resourcetype" {
+                    prop.children()
+                        .filter(|node| node.is_element())
+                        .map(|node| node.tag_name().name())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                } else {
+                    prop.descendants()
+                        .filter(|node| node.is_text())
+                        .filter_map(|node| node.text())
+                        .collect::<String>()
+                        .trim()
+                        .to_string()
+                };
+                props.insert(name, value);
+            }
+        }
+
+        resources.push(DavResource {
+            url: base_url.join(href.trim())?,
+            props,
+        });
+    }
+
+    Ok(resources)
+}
+
+pub fn resource_name(url: &Url) -> String {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .unwrap_or_default();
+    percent_decode_str(name).decode_utf8_lossy().to_string()
+}
+
+/// Collection URL with a trailing slash, so joining a member name keeps the
+/// last segment of hrefs reported without one.
+pub fn collection_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    url
+}
+
+pub fn encode_name(name: &str) -> String {
+    utf8_percent_encode(name, PATH_SEGMENT).to_string()
+}
+
+/// Makes a collection or resource name safe to be used as a local file name.
+pub fn sanitize_name(name: &str) -> String {
+    let name = name.replace(['/', '\\'], "_");
+    match name.trim_start_matches('.') {
+        "" => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Local file name of a resource, with `extension` appended when the server
+/// named it otherwise, since only such files are pushed.
+pub fn resource_file_name(name: &str, extension: &str) -> String {
+    let file_name = sanitize_name(name);
+    if Path::new(&file_name).extension() == Some(extension.as_ref()) {
+        file_name
+    } else {
+        format!("{file_name}.{extension}")
+    }
+}
+
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_url() -> Url {
+        Url::parse("https://dav.example.com/calendars/user/").unwrap()
+    }
+
+    #[test]
+    fn parse_multistatus_reads_props_of_ok_propstats() {
+        let text = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/calendars/user/work/</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:displayname> Work </d:displayname>
+        <d:resourcetype><d:collection/><c:calendar/></d:resourcetype>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop><d:getetag/></d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>event%201.ics</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:getetag>"abc"</d:getetag>
+        <d:current-user-principal><d:href>/principals/user/</d:href></d:current-user-principal>
+      </d:prop>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+        let resources = parse_multistatus(&base_url(), text).unwrap();
+
+        assert_eq!(resources.len(), 2);
+        let calendar = &resources[0];
+        assert_eq!(
+            calendar.url.as_str(),
+            "https://dav.example.com/calendars/user/work/"
+        );
+        assert_eq!(calendar.prop("displayname"), Some("Work"));
+        assert_eq!(calendar.prop("resourcetype"), Some("collection calendar"));
+        assert!(calendar.is("calendar"));
+        assert!(!calendar.is("addressbook"));
+        assert_eq!(calendar.prop("getetag"), None);
+        assert_eq!(calendar.name(), "work");
+
+        let event = &resources[1];
+        assert_eq!(
+            event.url.as_str(),
+            "https://dav.example.com/calendars/user/event%201.ics"
+        );
+        assert_eq!(event.prop("getetag"), Some("\"abc\""));
+        assert_eq!(
+            event.prop("current-user-principal"),
+            Some("/principals/user/")
+        );
+        assert!(!event.is("collection"));
+        assert_eq!(event.name(), "event 1.ics");
+    }
+
+    #[test]
+    fn parse_multistatus_rejects_bad_responses() {
+        let missing_href = r#"<d:multistatus xmlns:d="DAV:"><d:response/></d:multistatus>"#;
+        assert!(parse_multistatus(&base_url(), missing_href).is_err());
+        assert!(parse_multistatus(&base_url(), "<d:multistatus").is_err());
+    }
+
+    #[test]
+    fn collection_url_keeps_last_segment() {
+        let url = Url::parse("https://dav.example.com/calendars/user/work").unwrap();
+        assert_eq!(
+            collection_url(&url).join("event.ics").unwrap().as_str(),
+            "https://dav.example.com/calendars/user/work/event.ics"
+        );
+        assert_eq!(
+            collection_url(&base_url()).join("work/").unwrap().as_str(),
+            "https://dav.example.com/calendars/user/work/"
+        );
+    }
+
+    #[test]
+    fn resource_file_name_appends_extension() {
+        assert_eq!(resource_file_name("event.ics", "ics"), "event.ics");
+        assert_eq!(resource_file_name("event", "ics"), "event.ics");
+        assert_eq!(resource_file_name("event.ICS", "ics"), "event.ICS.ics");
+        assert_eq!(resource_file_name(".hidden/x", "ics"), "hidden_x.ics");
+        assert_eq!(resource_file_name("..", "ics"), "_.ics");
+        assert_eq!(sanitize_name(".."), "_");
+    }
+}
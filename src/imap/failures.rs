@@ -0,0 +1,318 @@
+//! Messages the target refused during `imap push`, kept per account so they
+//! can be re-run with `--retry-failed` instead of failing on every run. Only
+//! failures of the message itself are kept, transient ones stay pending.
+
+use anyhow::Context;
+use async_imap::error::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+pub const FAILURES_FILE_NAME: &str = ".imap-push-failures";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    TooLarge,
+    QuotaExceeded,
+    RateLimited,
+    InvalidMessage,
+    ConnectionLost,
+}
+
+impl FailureKind {
+    /// Sorts an APPEND error by the response code (RFC 5530) or, for servers
+    /// without codes, by the wording of the response.
+    pub fn classify(err: &Error) -> FailureKind {
+        match err {
+            Error::Io(_) | Error::ConnectionLost => FailureKind::ConnectionLost,
+            Error::No(text) | Error::Bad(text) => FailureKind::classify_response(text),
+            _ => FailureKind::InvalidMessage,
+        }
+    }
+
+    /// Codes unknown to the parser, like `[OVERQUOTA]`, are kept in the text.
+    fn classify_response(text: &str) -> FailureKind {
+        let text = text.to_uppercase();
+        let matches = |patterns: &[&str]| patterns.iter().any(|pattern| text.contains(pattern));
+
+        if matches(&["[TOOBIG]"]) {
+            FailureKind::TooLarge
+        } else if matches(&["[OVERQUOTA]"]) {
+            FailureKind::QuotaExceeded
+        } else if matches(&["[LIMIT]", "[UNAVAILABLE]", "[INUSE]"]) {
+            FailureKind::RateLimited
+        } else if matches(&[
+            "TOO BIG",
+            "TOO LARGE",
+            "SIZE LIMIT",
+            "SIZE EXCEEDS",
+            "APPENDLIMIT",
+        ]) {
+            FailureKind::TooLarge
+        } else if matches(&["QUOTA"]) {
+            FailureKind::QuotaExceeded
+        } else if matches(&[
+            "RATE LIMIT",
+            "THROTTL",
+            "TOO MANY",
+            "TRY AGAIN",
+            "TRY LATER",
+            "TEMPORARILY UNAVAILABLE",
+        ]) {
+            FailureKind::RateLimited
+        } else {
+            FailureKind::InvalidMessage
+        }
+    }
+
+    /// Failures of the message itself, pushing it again without changes
+    /// fails the same way. Others leave the message pending.
+    pub fn is_recorded(&self) -> bool {
+        matches!(self, FailureKind::TooLarge | FailureKind::InvalidMessage)
+    }
+
+    /// Failures after which pushing the remaining messages is pointless.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            FailureKind::QuotaExceeded | FailureKind::ConnectionLost
+        )
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FailureKind::TooLarge => "too large",
+            FailureKind::QuotaExceeded => "quota exceeded",
+            FailureKind::RateLimited => "rate limited",
+            FailureKind::InvalidMessage => "invalid message",
+            FailureKind::ConnectionLost => "connection lost",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushFailure {
+    pub folder: String,
+    pub file_name: String,
+    pub kind: FailureKind,
+    pub reason: String,
+    pub failed_at: String,
+}
+
+/// Failure list of an account, saved after every change so an aborted run
+/// keeps it.
+#[derive(Debug)]
+pub struct FailureList {
+    path: PathBuf,
+    failures: Vec<PushFailure>,
+}
+
+impl FailureList {
+    pub fn load(account_path: &Path) -> anyhow::Result<FailureList> {
+        let path = account_path.join(FAILURES_FILE_NAME);
+        let failures: Vec<PushFailure> = if path.exists() {
+            let data = fs::read(&path)
+                .context(format!("unable to read failure list {}", path.display()))?;
+            serde_json::from_slice(&data)
+                .context(format!("malformed failure list {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(FailureList { path, failures })
+    }
+
+    pub fn len(&self) -> usize {
+        self.failures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn contains(&self, folder: &str, file_name: &str) -> bool {
+        self.failures
+            .iter()
+            .any(|failure| failure.folder == folder && failure.file_name == file_name)
+    }
+
+    /// Records a failure, transient kinds are not recorded.
+    pub fn insert(
+        &mut self,
+        folder: &str,
+        file_name: &str,
+        kind: FailureKind,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        if !kind.is_recorded() {
+            return Ok(());
+        }
+        self.failures
+            .retain(|failure| failure.folder != folder || failure.file_name != file_name);
+        self.failures.push(PushFailure {
+            folder: folder.to_string(),
+            file_name: file_name.to_string(),
+            kind,
+            reason: reason.to_string(),
+            failed_at: chrono::Utc::now().to_rfc3339(),
+        });
+        self.save()
+    }
+
+    pub fn remove(&mut self, folder: &str, file_name: &str) -> anyhow::Result<()> {
+        if !self.contains(folder, file_name) {
+            return Ok(());
+        }
+        self.failures
+            .retain(|failure| failure.folder != folder || failure.file_name != file_name);
+        self.save()
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if self.failures.is_empty() {
+            if self.path.exists() {
+                fs::remove_file(&self.path).context(format!(
+                    "unable to remove failure list {}",
+                    self.path.display()
+                ))?;
+            }
+            return Ok(());
+        }
+
+        fs::write(&self.path, serde_json::to_vec_pretty(&self.failures)?).context(format!(
+            "unable to save failure list {}",
+            self.path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// NO response as async-imap reports it
+    fn no(info: &str) -> Error {
+        Error::No(format!("code: None, info: Some({info:?})"))
+    }
+
+    #[test]
+    fn classify_response_codes() {
+        let cases = [
+            ("[TOOBIG] Message too big", FailureKind::TooLarge),
+            ("[OVERQUOTA] Mailbox is full", FailureKind::QuotaExceeded),
+            ("[LIMIT] Too many appends", FailureKind::RateLimited),
+            ("[UNAVAILABLE] Backend down", FailureKind::RateLimited),
+            ("[INUSE] Mailbox locked", FailureKind::RateLimited),
+        ];
+        for (info, kind) in cases {
+            assert_eq!(FailureKind::classify(&no(info)), kind, "{info}");
+        }
+    }
+
+    #[test]
+    fn classify_too_large_wording() {
+        for info in [
+            "Message too large",
+            "message is too big for this server",
+            "Message size exceeds fixed maximum message size",
+            "Maximum size limit reached",
+            "exceeds APPENDLIMIT",
+        ] {
+            assert_eq!(
+                FailureKind::classify(&no(info)),
+                FailureKind::TooLarge,
+                "{info}"
+            );
+        }
+    }
+
+    #[test]
+    fn classify_quota_wording() {
+        for info in ["Quota exceeded", "Over quota"] {
+            assert_eq!(
+                FailureKind::classify(&no(info)),
+                FailureKind::QuotaExceeded,
+                "{info}"
+            );
+        }
+    }
+
+    #[test]
+    fn classify_rate_limited_wording() {
+        for info in [
+            "Rate limit hit, slow down",
+            "Account throttled",
+            "Too many simultaneous connections",
+            "Server busy, try again later",
+            "Please try later",
+            "Service temporarily unavailable",
+        ] {
+            assert_eq!(
+                FailureKind::classify(&no(info)),
+                FailureKind::RateLimited,
+                "{info}"
+            );
+        }
+    }
+
+    #[test]
+    fn classify_other_responses_as_invalid() {
+        for info in [
+            "Message contains invalid header",
+            "Line length limit exceeded",
+            "Mailbox doesn't exist",
+        ] {
+            assert_eq!(
+                FailureKind::classify(&no(info)),
+                FailureKind::InvalidMessage,
+                "{info}"
+            );
+        }
+        assert_eq!(
+            FailureKind::classify(&Error::Bad(
+                "code: None, info: Some(\"Syntax error\")".into()
+            )),
+            FailureKind::InvalidMessage
+        );
+    }
+
+    #[test]
+    fn classify_connection_errors() {
+        assert_eq!(
+            FailureKind::classify(&Error::ConnectionLost),
+            FailureKind::ConnectionLost
+        );
+        assert_eq!(
+            FailureKind::classify(&Error::Io(io::Error::from(io::ErrorKind::BrokenPipe))),
+            FailureKind::ConnectionLost
+        );
+    }
+
+    #[test]
+    fn only_message_failures_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut failures = FailureList::load(dir.path()).unwrap();
+
+        for (file_name, kind) in [
+            ("00000001.eml", FailureKind::TooLarge),
+            ("00000002.eml", FailureKind::InvalidMessage),
+            ("00000003.eml", FailureKind::QuotaExceeded),
+            ("00000004.eml", FailureKind::RateLimited),
+            ("00000005.eml", FailureKind::ConnectionLost),
+        ] {
+            failures.insert("INBOX", file_name, kind, "error").unwrap();
+        }
+
+        let failures = FailureList::load(dir.path()).unwrap();
+        assert_eq!(failures.len(), 2);
+        assert!(failures.contains("INBOX", "00000001.eml"));
+        assert!(failures.contains("INBOX", "00000002.eml"));
+        assert!(!failures.contains("INBOX", "00000004.eml"));
+    }
+}
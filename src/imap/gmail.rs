@@ -0,0 +1,475 @@
+//! Gmail mode: messages are pulled once from `All Mail` and their labels are
+//! kept next to them, so `push` can file each message into every label folder
+//! instead of uploading one copy per label.
+
+use anyhow::Context;
+use async_native_tls::TlsStream;
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+pub const GMAIL_CAPABILITY: &str = "X-GM-EXT-1";
+pub const LABELS_STATE_FILE_NAME: &str = ".gmail-labels";
+
+/// Folders used for Gmail system labels unless overridden in `gmail_label_mappings`,
+/// `None` means the label doesn't produce a folder.
+const DEFAULT_LABEL_MAPPINGS: &[(&str, Option<&str>)] = &[
+    ("\\Inbox", Some("INBOX")),
+    ("\\Sent", Some("Sent")),
+    ("\\Draft", Some("Drafts")),
+    ("\\Trash", Some("Trash")),
+    ("\\Spam", Some("Junk")),
+    ("\\Important", None),
+    ("\\Starred", None),
+];
+
+#[derive(Debug, Clone)]
+pub struct GmailMetadata {
+    pub message_id: u64,
+    pub thread_id: u64,
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Atom(String),
+    String(Vec<u8>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn text(&self) -> String {
+        match self {
+            Value::Atom(atom) => atom.clone(),
+            Value::String(value) => String::from_utf8_lossy(value).to_string(),
+            Value::List(_) => String::new(),
+        }
+    }
+}
+
+/// Side connection fetching Gmail attributes the IMAP parser of `async-imap`
+/// doesn't understand (`X-GM-THRID`).
+pub struct GmailClient {
+    stream: BufStream<TlsStream<TcpStream>>,
+    tag: usize,
+}
+
+impl GmailClient {
+    pub async fn connect(server: &str, port: u16) -> anyhow::Result<GmailClient> {
+        let tcp_stream = TcpStream::connect((server, port)).await?;
+        let tls = async_native_tls::TlsConnector::new();
+        let tls_stream = tls.connect(server, tcp_stream).await?;
+
+        let mut client = GmailClient {
+            stream: BufStream::new(tls_stream),
+            tag: 0,
+        };
+        client.read_line().await.context("no IMAP greeting")?;
+
+        Ok(client)
+    }
+
+    pub async fn login(&mut self, user: &str, password: &str) -> anyhow::Result<()> {
+        self.command(&format!("LOGIN {} {}", quote(user), quote(password)))
+            .await
+            .context("authentication failed")?;
+        Ok(())
+    }
+
+    pub async fn examine(&mut self, mailbox_name: &str) -> anyhow::Result<()> {
+        self.command(&format!("EXAMINE {}", quote(mailbox_name)))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns labels and ids of messages in the UID set by UID.
+    pub async fn fetch_metadata(
+        &mut self,
+        uid_set: &str,
+    ) -> anyhow::Result<HashMap<u32, GmailMetadata>> {
+        let responses = self
+            .command(&format!(
+                "UID FETCH {uid_set} (UID X-GM-MSGID X-GM-THRID X-GM-LABELS)"
+            ))
+            .await?;
+
+        let mut metadata = HashMap::new();
+        for response in responses {
+            if let Some((uid, message_metadata)) = parse_metadata(&response)? {
+                metadata.insert(uid, message_metadata);
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    pub async fn logout(&mut self) -> anyhow::Result<()> {
+        self.command("LOGOUT").await?;
+        Ok(())
+    }
+
+    /// Sends a command and returns its untagged responses.
+    async fn command(&mut self, command: &str) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.tag += 1;
+        let tag = format!("G{}", self.tag);
+
+        if command.starts_with("LOGIN ") {
+            log::debug!("C: {tag} LOGIN ***");
+        } else {
+            log::debug!("C: {tag} {command}");
+        }
+        self.stream
+            .write_all(format!("{tag} {command}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+
+        let mut responses = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            match line.strip_prefix(format!("{tag} ").as_bytes()) {
+                Some(status) if status.starts_with(b"OK") => return Ok(responses),
+                Some(status) => {
+                    anyhow::bail!("server error: {}", String::from_utf8_lossy(status).trim())
+                }
+                None => responses.push(line),
+            }
+        }
+    }
+
+    /// Reads a response line together with literals it contains.
+    async fn read_line(&mut self) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        loop {
+            let start = data.len();
+            if self.stream.read_until(b'\n', &mut data).await? == 0 {
+                anyhow::bail!("connection closed by server");
+            }
+            log::trace!("S: {}", String::from_utf8_lossy(&data[start..]).trim_end());
+
+            match literal_size(&data[start..]) {
+                Some(size) => {
+                    let start = data.len();
+                    data.resize(start + size, 0);
+                    self.stream.read_exact(&mut data[start..]).await?;
+                }
+                None => return Ok(data),
+            }
+        }
+    }
+}
+
+/// Appends labels of a stored message to the state file of the folder.
+pub fn save_metadata(
+    folder_path: &Path,
+    file_name: &str,
+    metadata: &GmailMetadata,
+) -> anyhow::Result<()> {
+    let mut state_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(folder_path.join(LABELS_STATE_FILE_NAME))
+        .context("unable to open Gmail labels file")?;
+
+    let mut line = format!(
+        "{file_name}\t{}\t{}",
+        metadata.message_id, metadata.thread_id
+    );
+    for label in metadata.labels.iter() {
+        line.push('\t');
+        line.push_str(label);
+    }
+    writeln!(state_file, "{line}").context("unable to update Gmail labels file")?;
+
+    Ok(())
+}
+
+/// Loads labels of stored messages by file name (`00000001.eml`), returns
+/// `None` for folders not pulled in Gmail mode.
+pub fn load_metadata(folder_path: &Path) -> anyhow::Result<Option<HashMap<String, GmailMetadata>>> {
+    let state_file_path = folder_path.join(LABELS_STATE_FILE_NAME);
+    if !state_file_path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read_to_string(&state_file_path).context(format!(
+        "unable to read Gmail labels file {}",
+        state_file_path.display()
+    ))?;
+
+    let mut metadata = HashMap::new();
+    for line in data.lines() {
+        let mut fields = line.split('\t');
+        let (Some(file_name), Some(message_id), Some(thread_id)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        metadata.insert(
+            file_name.to_string(),
+            GmailMetadata {
+                message_id: message_id.parse().unwrap_or_default(),
+                thread_id: thread_id.parse().unwrap_or_default(),
+                labels: fields.map(|label| label.to_string()).collect(),
+            },
+        );
+    }
+
+    Ok(Some(metadata))
+}
+
+/// Resolves labels to target folder names, the inbox goes first. Labels
+/// missing from `label_mappings` and the defaults keep their name.
+pub fn label_folders(
+    labels: &[String],
+    label_mappings: &Option<HashMap<String, Option<String>>>,
+) -> Vec<String> {
+    let mut folders: Vec<String> = Vec::new();
+
+    for label in labels {
+        let configured = label_mappings
+            .as_ref()
+            .and_then(|label_mappings| label_mappings.get(label));
+        let folder = match configured {
+            Some(folder) => folder.clone(),
+            None => match DEFAULT_LABEL_MAPPINGS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(label))
+            {
+                Some((_, folder)) => folder.map(|folder| folder.to_string()),
+                // Other system labels don't have folders
+                None if label.starts_with('\\') => None,
+                None => Some(label.clone()),
+            },
+        };
+
+        if let Some(folder) = folder {
+            if !folders.contains(&folder) {
+                folders.push(folder);
+            }
+        }
+    }
+
+    if let Some(inbox) = folders.iter().position(|folder| folder == "INBOX") {
+        let inbox = folders.remove(inbox);
+        folders.insert(0, inbox);
+    }
+
+    folders
+}
+
+fn decode_label(label: &str) -> String {
+    if label.starts_with('\\') {
+        label.to_string()
+    } else {
+        utf7_imap::decode_utf7_imap(label.to_string())
+    }
+}
+
+fn literal_size(line: &[u8]) -> Option<usize> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let line = line.strip_suffix(b"}")?;
+    let start = line.iter().rposition(|c| *c == b'{')?;
+    std::str::from_utf8(&line[start + 1..]).ok()?.parse().ok()
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Parses an untagged `FETCH` response into the UID and Gmail attributes,
+/// other responses are skipped.
+fn parse_metadata(response: &[u8]) -> anyhow::Result<Option<(u32, GmailMetadata)>> {
+    let values = parse_values(response)?;
+    let [_, Value::Atom(_), Value::Atom(kind), Value::List(attributes)] = values.as_slice() else {
+        return Ok(None);
+    };
+    if !kind.eq_ignore_ascii_case("FETCH") {
+        return Ok(None);
+    }
+
+    let mut uid = None;
+    let mut metadata = GmailMetadata {
+        message_id: 0,
+        thread_id: 0,
+        labels: Vec::new(),
+    };
+    for pair in attributes.chunks(2) {
+        match (pair[0].text().to_uppercase().as_str(), pair.get(1)) {
+            ("UID", Some(value)) => uid = Some(value.text().parse()?),
+            ("X-GM-MSGID", Some(value)) => metadata.message_id = value.text().parse()?,
+            ("X-GM-THRID", Some(value)) => metadata.thread_id = value.text().parse()?,
+            ("X-GM-LABELS", Some(Value::List(labels))) => {
+                metadata.labels = labels
+                    .iter()
+                    .map(|label| decode_label(&label.text()))
+                    .collect()
+            }
+            _ => {}
+        }
+    }
+
+    Ok(uid.map(|uid| (uid, metadata)))
+}
+
+/// Parses a response into atoms, strings and parenthesized lists.
+fn parse_values(data: &[u8]) -> anyhow::Result<Vec<Value>> {
+    let mut position = 0;
+    let mut values = Vec::new();
+    while let Some(value) = parse_value(data, &mut position)? {
+        values.push(value);
+    }
+    Ok(values)
+}
+
+fn parse_value(data: &[u8], position: &mut usize) -> anyhow::Result<Option<Value>> {
+    while *position < data.len() && matches!(data[*position], b' ' | b'\r' | b'\n') {
+        *position += 1;
+    }
+    if *position >= data.len() {
+        return Ok(None);
+    }
+
+    match data[*position] {
+        b')' => Ok(None),
+        b'(' => {
+            *position += 1;
+            let mut values = Vec::new();
+            while let Some(value) = parse_value(data, position)? {
+                values.push(value);
+            }
+            *position += 1;
+            Ok(Some(Value::List(values)))
+        }
+        b'"' => {
+            *position += 1;
+            let mut value = Vec::new();
+            while *position < data.len() && data[*position] != b'"' {
+                if data[*position] == b'\\' {
+                    *position += 1;
+                }
+                if let Some(c) = data.get(*position) {
+                    value.push(*c);
+                }
+                *position += 1;
+            }
+            *position += 1;
+            Ok(Some(Value::String(value)))
+        }
+        b'{' => {
+            let end = data[*position..]
+                .iter()
+                .position(|c| *c == b'\n')
+                .context("malformed literal")?
+                + *position;
+            let size = literal_size(&data[*position..=end]).context("malformed literal")?;
+            let start = end + 1;
+            *position = start + size;
+            Ok(Some(Value::String(
+                data.get(start..start + size)
+                    .context("truncated literal")?
+                    .to_vec(),
+            )))
+        }
+        _ => {
+            let start = *position;
+            while *position < data.len()
+                && !matches!(data[*position], b' ' | b'(' | b')' | b'\r' | b'\n')
+            {
+                // Brackets of section specs like BODY[HEADER] may contain spaces
+                if data[*position] == b'[' {
+                    while *position < data.len() && data[*position] != b']' {
+                        *position += 1;
+                    }
+                }
+                *position += 1;
+            }
+            Ok(Some(Value::Atom(
+                String::from_utf8_lossy(&data[start..*position]).to_string(),
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_size_of_line_end() {
+        assert_eq!(literal_size(b"* 1 FETCH (X-GM-LABELS ({5}\r\n"), Some(5));
+        assert_eq!(literal_size(b"* 1 FETCH (X-GM-LABELS ({12}\n"), Some(12));
+        assert_eq!(literal_size(b"* 1 FETCH (X-GM-LABELS ({12}"), Some(12));
+        assert_eq!(literal_size(b"* 1 FETCH (UID 4)\r\n"), None);
+        assert_eq!(literal_size(b"* 1 FETCH {x}\r\n"), None);
+        assert_eq!(literal_size(b"}\r\n"), None);
+    }
+
+    #[test]
+    fn parse_values_reads_atoms_strings_literals_and_lists() {
+        let values = parse_values(
+            b"* 1 FETCH (UID 4 X-GM-LABELS (\"\\\\Inbox\" \"a \\\"b\\\"\" {5}\r\nc d)e) BODY[HEADER.FIELDS (TO)] NIL)\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(values.len(), 4);
+        assert!(matches!(&values[0], Value::Atom(atom) if atom == "*"));
+        assert!(matches!(&values[2], Value::Atom(atom) if atom == "FETCH"));
+        let Value::List(attributes) = &values[3] else {
+            panic!("attributes are not a list: {values:?}");
+        };
+        let texts: Vec<_> = attributes.iter().map(Value::text).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "UID",
+                "4",
+                "X-GM-LABELS",
+                "",
+                "BODY[HEADER.FIELDS (TO)]",
+                "NIL"
+            ]
+        );
+        let Value::List(labels) = &attributes[3] else {
+            panic!("labels are not a list: {attributes:?}");
+        };
+        let labels: Vec<_> = labels.iter().map(Value::text).collect();
+        assert_eq!(labels, vec!["\\Inbox", "a \"b\"", "c d)e"]);
+    }
+
+    #[test]
+    fn parse_values_rejects_truncated_literals() {
+        assert!(parse_values(b"* 1 FETCH (X-GM-LABELS ({10}\r\nabc))").is_err());
+        assert!(parse_values(b"* 1 FETCH ({3").is_err());
+    }
+
+    #[test]
+    fn parse_metadata_keys_by_uid() {
+        let (uid, metadata) = parse_metadata(
+            b"* 3 FETCH (X-GM-MSGID 1278455344230334865 X-GM-THRID 1278455344230334866 UID 17 X-GM-LABELS (\\Inbox \\Important Work/&ANw-bersicht))\r\n",
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(uid, 17);
+        assert_eq!(metadata.message_id, 1278455344230334865);
+        assert_eq!(metadata.thread_id, 1278455344230334866);
+        assert_eq!(
+            metadata.labels,
+            vec!["\\Inbox", "\\Important", "Work/Übersicht"]
+        );
+
+        assert!(parse_metadata(b"* 3 FETCH (X-GM-MSGID 1)\r\n")
+            .unwrap()
+            .is_none());
+        assert!(parse_metadata(b"* 3 EXPUNGE\r\n").unwrap().is_none());
+    }
+}
@@ -0,0 +1,321 @@
+use anyhow::Context;
+use async_imap::types::{Capability, NameAttribute, QuotaResourceName};
+use clap::ValueEnum;
+use futures_lite::stream::StreamExt;
+use human_bytes::human_bytes;
+use serde::Serialize;
+use tokio::time::Instant;
+
+use super::{connect, ImapSession};
+use crate::{args::OutputFormat, config::Config};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ServerRole {
+    Pull,
+    Push,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerSummary {
+    pub server: String,
+    pub port: u16,
+    pub capabilities: Vec<String>,
+    pub quota: Vec<QuotaUsage>,
+    pub messages: u32,
+    pub bytes: u64,
+    pub folders: Vec<FolderSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuotaUsage {
+    pub root: String,
+    pub resource: String,
+    pub usage: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FolderSummary {
+    pub name: String,
+    pub delimiter: Option<String>,
+    pub attributes: Vec<String>,
+    pub messages: Option<u32>,
+    pub unseen: Option<u32>,
+    pub uid_validity: Option<u32>,
+    pub bytes: Option<u64>,
+}
+
+pub async fn list(
+    config: &Config,
+    email: String,
+    password: Option<String>,
+    server: ServerRole,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let imap_config = config
+        .imap
+        .clone()
+        .context("IMAP config is not provided in config.yaml")?;
+    let imap_config = match server {
+        ServerRole::Pull => imap_config
+            .pull
+            .context("IMAP pull server config not provided")?,
+        ServerRole::Push => imap_config
+            .push
+            .context("IMAP push server config not provided")?,
+    };
+
+    let mut imap_session = connect(&imap_config, &email, password.as_deref()).await?;
+    log::info!("Logged in as {}", email);
+
+    let capabilities = imap_session
+        .capabilities()
+        .await
+        .context("error getting capabilities")?;
+    let mut capability_names: Vec<_> = capabilities
+        .iter()
+        .map(|capability| match capability {
+            Capability::Imap4rev1 => "IMAP4rev1".to_string(),
+            Capability::Auth(mechanism) => format!("AUTH={mechanism}"),
+            Capability::Atom(atom) => atom.clone(),
+        })
+        .collect();
+    capability_names.sort();
+
+    let mut quota = Vec::new();
+    if capabilities.has_str("QUOTA") {
+        match imap_session.get_quota_root("INBOX").await {
+            Ok((_, quotas)) => {
+                for root in quotas {
+                    for resource in root.resources {
+                        quota.push(QuotaUsage {
+                            root: root.root_name.clone(),
+                            resource: match resource.name {
+                                QuotaResourceName::Storage => "STORAGE".to_string(),
+                                QuotaResourceName::Message => "MESSAGE".to_string(),
+                                QuotaResourceName::Atom(atom) => atom,
+                            },
+                            usage: resource.usage,
+                            limit: resource.limit,
+                        });
+                    }
+                }
+            }
+            Err(err) => log::warn!("Unable to get quota: {}", err),
+        }
+    }
+
+    let mailbox_stream = imap_session
+        .list(None, Some("*"))
+        .await
+        .context("error getting mailbox list")?;
+    let mailboxes: Vec<_> = mailbox_stream.try_collect().await?;
+    log::info!("Loaded {} mailboxes", mailboxes.len());
+
+    let mut folders = Vec::new();
+    for mailbox in mailboxes.iter() {
+        let mut folder = FolderSummary {
+            name: utf7_imap::decode_utf7_imap(mailbox.name().to_string()),
+            delimiter: mailbox.delimiter().map(|delimiter| delimiter.to_string()),
+            attributes: mailbox.attributes().iter().map(attribute_name).collect(),
+            messages: None,
+            unseen: None,
+            uid_validity: None,
+            bytes: None,
+        };
+
+        if !is_selectable(mailbox.attributes()) {
+            folders.push(folder);
+            continue;
+        }
+
+        // A folder the server refuses to report is listed without counts
+        let status = match imap_session
+            .status(mailbox.name(), "(MESSAGES UNSEEN UIDVALIDITY)")
+            .await
+        {
+            Ok(status) => status,
+            Err(err) => {
+                log::warn!("Unable to get status of {}: {}", folder.name, err);
+                folders.push(folder);
+                continue;
+            }
+        };
+        folder.messages = Some(status.exists);
+        folder.unseen = status.unseen;
+        folder.uid_validity = status.uid_validity;
+        match folder_size(&mut imap_session, mailbox.name(), status.exists).await {
+            Ok(bytes) => folder.bytes = Some(bytes),
+            Err(err) => log::warn!("Unable to get size of {}: {:#}", folder.name, err),
+        }
+
+        folders.push(folder);
+    }
+
+    imap_session.logout().await?;
+
+    let summary = ServerSummary {
+        server: imap_config.server.clone(),
+        port: imap_config.port.unwrap_or(993),
+        capabilities: capability_names,
+        quota,
+        messages: folders.iter().filter_map(|folder| folder.messages).sum(),
+        bytes: folders.iter().filter_map(|folder| folder.bytes).sum(),
+        folders,
+    };
+
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+        OutputFormat::Table => print_table(&summary),
+    }
+
+    log::info!(
+        "Done in {:?}, {} folders listed ok.",
+        start.elapsed(),
+        summary.folders.len()
+    );
+
+    Ok(())
+}
+
+/// Sums `RFC822.SIZE` of the folder's messages. `STATUS (SIZE)` would be
+/// cheaper, but the IMAP parser doesn't understand it.
+async fn folder_size(
+    imap_session: &mut ImapSession,
+    mailbox_name: &str,
+    exists: u32,
+) -> anyhow::Result<u64> {
+    if exists == 0 {
+        return Ok(0);
+    }
+
+    imap_session
+        .examine(mailbox_name)
+        .await
+        .context(format!("unable to examine {mailbox_name}"))?;
+    let messages_stream = imap_session
+        .fetch("1:*", "RFC822.SIZE")
+        .await
+        .context("error getting message sizes")?;
+    let sizes: Vec<_> = messages_stream
+        .map(|message| message.map(|message| message.size.unwrap_or_default() as u64))
+        .try_collect()
+        .await?;
+
+    Ok(sizes.iter().sum())
+}
+
+/// `\NonExistent` (RFC 5258) folders are only listed for their children,
+/// like `\Noselect` ones.
+fn is_selectable(attributes: &[NameAttribute]) -> bool {
+    !attributes.iter().any(|attribute| match attribute {
+        NameAttribute::NoSelect => true,
+        NameAttribute::Extension(extension) => extension.eq_ignore_ascii_case("\\NonExistent"),
+        _ => false,
+    })
+}
+
+fn attribute_name(attribute: &NameAttribute) -> String {
+    match attribute {
+        NameAttribute::NoInferiors => "\\Noinferiors".to_string(),
+        NameAttribute::NoSelect => "\\Noselect".to_string(),
+        NameAttribute::Marked => "\\Marked".to_string(),
+        NameAttribute::Unmarked => "\\Unmarked".to_string(),
+        NameAttribute::All => "\\All".to_string(),
+        NameAttribute::Archive => "\\Archive".to_string(),
+        NameAttribute::Drafts => "\\Drafts".to_string(),
+        NameAttribute::Flagged => "\\Flagged".to_string(),
+        NameAttribute::Junk => "\\Junk".to_string(),
+        NameAttribute::Sent => "\\Sent".to_string(),
+        NameAttribute::Trash => "\\Trash".to_string(),
+        NameAttribute::Extension(extension) => extension.to_string(),
+        _ => format!("{attribute:?}"),
+    }
+}
+
+fn print_table(summary: &ServerSummary) {
+    println!("Server: {}:{}", summary.server, summary.port);
+    println!("Capabilities: {}", summary.capabilities.join(" "));
+    for quota in summary.quota.iter() {
+        // STORAGE is counted in units of 1024 octets
+        let (usage, limit) = if quota.resource == "STORAGE" {
+            (
+                human_bytes((quota.usage * 1024) as f64),
+                human_bytes((quota.limit * 1024) as f64),
+            )
+        } else {
+            (quota.usage.to_string(), quota.limit.to_string())
+        };
+        println!(
+            "Quota {:?} {}: {} of {}",
+            quota.root, quota.resource, usage, limit
+        );
+    }
+    println!();
+
+    let name_width = summary
+        .folders
+        .iter()
+        .map(|folder| folder.name.len())
+        .chain(std::iter::once("FOLDER".len()))
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:<name_width$}  DELIM  MESSAGES    UNSEEN        SIZE  UIDVALIDITY  ATTRIBUTES",
+        "FOLDER"
+    );
+    for folder in summary.folders.iter() {
+        println!(
+            "{:<name_width$}  {:<5}  {:>8}  {:>8}  {:>10}  {:>11}  {}",
+            folder.name,
+            folder.delimiter.as_deref().unwrap_or("-"),
+            optional(folder.messages),
+            optional(folder.unseen),
+            folder
+                .bytes
+                .map(|bytes| human_bytes(bytes as f64))
+                .unwrap_or_else(|| "-".to_string()),
+            optional(folder.uid_validity),
+            folder.attributes.join(" ")
+        );
+    }
+    println!(
+        "{:<name_width$}  {:<5}  {:>8}  {:>8}  {:>10}",
+        "TOTAL",
+        "",
+        summary.messages,
+        "",
+        human_bytes(summary.bytes as f64)
+    );
+}
+
+fn optional(value: Option<u32>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noselect_and_nonexistent_folders_are_not_selectable() {
+        assert!(is_selectable(&[]));
+        assert!(is_selectable(&[
+            NameAttribute::Sent,
+            NameAttribute::Extension("\\HasNoChildren".into())
+        ]));
+        assert!(!is_selectable(&[NameAttribute::NoSelect]));
+        assert!(!is_selectable(&[
+            NameAttribute::Extension("\\HasChildren".into()),
+            NameAttribute::Extension("\\NonExistent".into())
+        ]));
+        assert!(!is_selectable(&[NameAttribute::Extension(
+            "\\Nonexistent".into()
+        )]));
+    }
+}
@@ -1,2 +1,37 @@
+use async_native_tls::TlsStream;
+use tokio::net::TcpStream;
+
+use crate::config::ImapServerConfig;
+
+pub mod failures;
+pub mod gmail;
+pub mod list;
 pub mod pull;
-pub mod push;
\ No newline at end of file
+pub mod push;
+pub mod sync;
+pub mod throttle;
+pub mod watch;
+
+pub type ImapSession = async_imap::Session<TlsStream<TcpStream>>;
+
+/// Opens a TLS session to the server and logs in, `password` overrides the
+/// configured one.
+pub async fn connect(
+    imap_config: &ImapServerConfig,
+    email: &str,
+    password: Option<&str>,
+) -> anyhow::Result<ImapSession> {
+    let (login, password) = imap_config.credentials(email, password)?;
+    let imap_addr = (imap_config.server.clone(), imap_config.port.unwrap_or(993));
+
+    let tcp_stream = TcpStream::connect(imap_addr.clone()).await?;
+    let tls = async_native_tls::TlsConnector::new();
+    let tls_stream = tls.connect(&imap_addr.0, tcp_stream).await?;
+
+    let client = async_imap::Client::new(tls_stream);
+    log::debug!("Connected to IMAP server {}:{}", imap_addr.0, imap_addr.1);
+
+    let imap_session = client.login(login, password).await.map_err(|e| e.0)?;
+
+    Ok(imap_session)
+}
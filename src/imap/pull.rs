@@ -1,26 +1,83 @@
 use anyhow::Context;
 use async_walkdir::{Filtering, WalkDir};
 use futures_lite::stream::StreamExt;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env::current_dir,
     ffi::OsString,
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 use tokio::{net::TcpStream, time::Instant};
 
-use crate::config::Config;
+use super::{
+    gmail::{self, GmailClient, GMAIL_CAPABILITY},
+    sync::{flag_names, uid_set},
+    throttle::Throttle,
+    ImapSession,
+};
+use crate::{archive::absolute_path, config::Config, report::Report};
+
+pub const PULL_STATE_FILE_NAME: &str = ".imap-pull";
+const MAX_BATCH_SIZE: usize = 200;
+
+/// Checkpoint of a pulled folder, lets repeated runs skip unchanged folders.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PullState {
+    pub uid_validity: Option<u32>,
+    pub highest_modseq: Option<u64>,
+    /// Stored messages by UID
+    pub messages: BTreeMap<u32, StoredMessage>,
+    /// UID of the last message stored before UIDs were recorded, `imap watch`
+    /// forwards messages above it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_uid: Option<u32>,
+}
+
+impl PullState {
+    /// Highest UID stored in the folder, 0 when nothing is known.
+    pub fn last_uid(&self) -> u32 {
+        self.messages
+            .keys()
+            .next_back()
+            .copied()
+            .max(self.last_uid)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub file_name: String,
+    pub flags: BTreeSet<String>,
+}
+
+/// Options of `imap pull` besides the account and the output directory.
+#[derive(Debug)]
+pub struct PullOptions {
+    pub export_mbox: bool,
+    /// Size limit of a single mbox file
+    pub max_file_size: usize,
+    /// Total size of messages fetched in one batch
+    pub memory_limit: usize,
+}
 
 pub async fn pull(
     config: &Config,
     email: String,
-    password: String,
+    password: Option<String>,
     out_dir: String,
-    export_mbox: bool,
-    max_file_size: usize,
+    options: PullOptions,
+    report: &mut Report,
 ) -> anyhow::Result<()> {
+    let PullOptions {
+        export_mbox,
+        max_file_size,
+        memory_limit,
+    } = options;
     let start = Instant::now();
 
     let cancellation_token = tokio_util::sync::CancellationToken::new();
@@ -49,6 +106,8 @@ pub async fn pull(
     let imap_server = imap_config.server.clone();
     let imap_port = imap_config.port.unwrap_or(993);
     let imap_addr = (imap_server, imap_port);
+    let throttle = Throttle::for_server(&imap_config, &absolute_path(&out_dir))?;
+    let (login, password) = imap_config.credentials(&email, password.as_deref())?;
 
     log::debug!("Pulling IMAP for account {email}...");
     let tcp_stream = TcpStream::connect(imap_addr.clone()).await?;
@@ -58,24 +117,85 @@ pub async fn pull(
     let client = async_imap::Client::new(tls_stream);
     log::info!("Connected to IMAP server {}:{}", imap_addr.0, imap_addr.1);
 
-    let mut imap_session = client.login(&email, &password).await.map_err(|e| e.0)?;
+    let mut imap_session = client.login(&login, &password).await.map_err(|e| e.0)?;
     log::info!("Logged in as {}", email);
 
+    let capabilities = imap_session
+        .capabilities()
+        .await
+        .context("error getting capabilities")?;
+    let gmail_mode = capabilities.has_str(GMAIL_CAPABILITY);
+    // QRESYNC implies CONDSTORE
+    let condstore = capabilities.has_str("CONDSTORE") || capabilities.has_str("QRESYNC");
+
     let mailbox_stream = imap_session
         .list(None, Some("*"))
         .await
         .context("error getting mailbox listt")?;
-    let mailboxes: Vec<_> = mailbox_stream.try_collect().await?;
+    let mut mailboxes: Vec<_> = mailbox_stream.try_collect().await?;
 
     log::info!("Loaded {} mailboxes", mailboxes.len());
 
-    for mailbox in mailboxes {
+    // Every label of a Gmail account is a folder holding copies of messages
+    // from All Mail, so only All Mail is pulled and labels are kept aside
+    let mut gmail_client = None;
+    if gmail_mode {
+        match mailboxes.iter().position(|mailbox| {
+            mailbox
+                .attributes()
+                .contains(&async_imap::types::NameAttribute::All)
+        }) {
+            Some(all_mail) => {
+                let all_mail = mailboxes.swap_remove(all_mail);
+                log::info!("Gmail detected, pulling {} only", all_mail.name());
+
+                let mut client = GmailClient::connect(&imap_addr.0, imap_addr.1).await?;
+                client.login(&login, &password).await?;
+                gmail_client = Some(client);
+
+                mailboxes = vec![all_mail];
+            }
+            None => log::warn!("Gmail detected but All Mail is not listed, pulling every folder"),
+        }
+    }
+
+    // Message counts feed the progress display, with CONDSTORE the same
+    // command tells which folders are unchanged
+    let status_items = if condstore {
+        "(MESSAGES UIDVALIDITY HIGHESTMODSEQ)"
+    } else {
+        "(MESSAGES)"
+    };
+    let mut statuses = Vec::new();
+    for mailbox in mailboxes.iter() {
+        match imap_session.status(mailbox.name(), status_items).await {
+            Ok(status) => statuses.push(Some(status)),
+            Err(err) => {
+                log::debug!("Unable to get status of {}: {}", mailbox.name(), err);
+                statuses.push(None);
+            }
+        }
+    }
+    report.expect(
+        statuses
+            .iter()
+            .flatten()
+            .map(|status| status.exists as usize)
+            .sum(),
+    );
+
+    let mut unchanged_count = 0;
+    let mut paused = false;
+
+    for (mailbox, status) in mailboxes.into_iter().zip(statuses) {
+        if paused {
+            break;
+        }
+
         let mailbox_name = mailbox.name();
         let mailbox_readable_name = utf7_imap::decode_utf7_imap(mailbox_name.to_string());
         log::info!("Mailbox: {:?}", mailbox_readable_name);
-
-        imap_session.select(&mailbox_name).await?;
-        log::debug!("{mailbox_name} selected");
+        report.start_folder(&mailbox_readable_name, None);
 
         let folder_name = format!("{out_dir}/{domain}/{email}/{mailbox_readable_name}",);
         let folder_path = if folder_name.clone().starts_with("/") {
@@ -84,10 +204,79 @@ pub async fn pull(
             current_dir().unwrap().join(folder_name.clone())
         };
         let folder_path = folder_path.to_str().context("wrong in_dir path")?;
-        fs::create_dir_all(&folder_path)?;
+        fs::create_dir_all(folder_path)?;
+
+        // Mbox parts are rewritten on every run, only stored messages are tracked
+        let mut pull_state = if export_mbox {
+            None
+        } else {
+            Some(load_pull_state(Path::new(folder_path))?.unwrap_or_default())
+        };
+
+        // STATUS is much cheaper than selecting and walking the folder
+        if let (true, Some(status), Some(pull_state)) =
+            (condstore, status.as_ref(), pull_state.as_ref())
+        {
+            if pull_state.highest_modseq.is_some()
+                && status.uid_validity == pull_state.uid_validity
+                && status.highest_modseq == pull_state.highest_modseq
+            {
+                log::info!("Unchanged since the last run");
+                report.found(status.exists as usize);
+                report.skipped(status.exists as usize);
+                unchanged_count += 1;
+                continue;
+            }
+        }
+
+        let selected_mailbox = if condstore {
+            imap_session.select_condstore(&mailbox_name).await?
+        } else {
+            imap_session.select(&mailbox_name).await?
+        };
+        log::debug!("{mailbox_name} selected");
+
+        if let Some(ref mut gmail_client) = gmail_client {
+            gmail_client.examine(mailbox_name).await?;
+        }
 
         log::info!("Folder {folder_path}");
 
+        if let Some(ref mut pull_state) = pull_state {
+            if pull_state.uid_validity.is_some()
+                && pull_state.uid_validity != selected_mailbox.uid_validity
+            {
+                log::warn!("UIDVALIDITY changed, stored flags are dropped");
+                *pull_state = PullState::default();
+            }
+
+            let known_highest_modseq = pull_state
+                .highest_modseq
+                .filter(|_| condstore && selected_mailbox.exists > 0);
+            if let Some(highest_modseq) = known_highest_modseq {
+                let messages_stream = imap_session
+                    .uid_fetch("1:*", format!("(FLAGS) (CHANGEDSINCE {highest_modseq})"))
+                    .await
+                    .context("error getting flag changes")?;
+                let messages: Vec<_> = messages_stream.try_collect().await?;
+
+                let mut updated_count = 0;
+                for message in messages {
+                    let stored_message = message
+                        .uid
+                        .and_then(|uid| pull_state.messages.get_mut(&uid));
+                    if let Some(stored_message) = stored_message {
+                        let flags = flag_names(message.flags());
+                        if stored_message.flags != flags {
+                            stored_message.flags = flags;
+                            updated_count += 1;
+                        }
+                    }
+                }
+                log::info!("Flags of {updated_count} stored messages changed");
+            }
+        }
+
         let entries = WalkDir::new(folder_path).filter(|entry| async move {
             match entry.file_type().await {
                 Ok(file_type) => {
@@ -131,8 +320,9 @@ pub async fn pull(
         };
 
         log::info!("Starting message id: {}", starting_message_id);
+        report.found(selected_mailbox.exists as usize);
+        report.skipped((starting_message_id - 1).min(selected_mailbox.exists as usize));
 
-        let batch_size = 200;
         let mut message_id = starting_message_id;
 
         if export_mbox {
@@ -155,135 +345,52 @@ pub async fn pull(
             log::debug!("Creating part {}", file_path.to_string_lossy());
             let mut out_file = File::create(file_path).context("Unable to open file")?;
 
-            while !pull_cancellation_token.is_cancelled() {
-                let sequence_set = format!("{message_id}:{}", message_id + batch_size - 1);
-                log::info!("Querying {sequence_set}");
-
-                let messages_stream = imap_session
-                    .fetch(sequence_set, "RFC822")
-                    .await
-                    .context("error getting messages")?;
-                let messages: Vec<_> = messages_stream.try_collect().await?;
-
-                if messages.len() == 0 {
-                    log::debug!("No more messages");
-                    break;
-                } else {
-                    let mut current_message_id = message_id - 1;
-
-                    for message in messages {
-                        current_message_id += 1;
-                        let body = message.body().context("message did not have a body!")?;
-                        let body_string = std::str::from_utf8(body).ok();
-                        if body_string.is_none() {
-                            let bin_file_name = format!("{:0>8}.bin", current_message_id);
-                            let bin_file_path = if folder_name.clone().starts_with("/") {
-                                PathBuf::from_str("/")
-                                    .unwrap()
-                                    .join(folder_name.clone())
-                                    .join(bin_file_name.clone())
-                            } else {
-                                current_dir()
-                                    .unwrap()
-                                    .join(folder_name.clone())
-                                    .join(bin_file_name.clone())
-                            };
-                            fs::write(bin_file_path, body)
-                                .context("unable to write file")
-                                .context("unable to save *.bin file")?;
-                            log::debug!("{} bytes bin data stored", body.len());
-
-                            log::warn!(
-                                "Message {} had invalid UTF-8. Storing as binary in {}.",
-                                current_message_id,
-                                bin_file_name
-                            );
-                            continue;
-                        }
-                        let body = body_string.unwrap().as_bytes();
-
-                        let dt = chrono::Utc::now();
-                        let timestamp = dt.format("%a %b %e %T %Y").to_string();
-                        let prefix_string = format!("From MAILER-DAEMON {timestamp}\n");
-                        let prefix = prefix_string.as_bytes();
-
-                        let suffix_string = format!("\n\n");
-                        let suffix = suffix_string.as_bytes();
-
-                        if bytes_written + prefix.len() + body.len() + suffix.len() > max_file_size
-                        {
-                            log::debug!(
-                                "File size exceed limit {} > {}",
-                                prefix.len() + body.len() + suffix.len(),
-                                max_file_size
-                            );
-                            out_file.flush().context("error flushing file")?;
-
-                            part_id += 1;
-
-                            let file_name = format!("part-{:0>4}.mbox", part_id);
-                            let file_path = if folder_name.clone().starts_with("/") {
-                                PathBuf::from_str("/")
-                                    .unwrap()
-                                    .join(folder_name.clone())
-                                    .join(file_name.clone())
-                            } else {
-                                current_dir()
-                                    .unwrap()
-                                    .join(folder_name.clone())
-                                    .join(file_name.clone())
-                            };
-
-                            log::debug!("Creating part {}", file_path.to_string_lossy());
-                            out_file = File::create(file_path).context("Unable to open file")?;
-
-                            bytes_written = 0;
-                        }
-
-                        out_file
-                            .write(prefix)
-                            .context("unable to write file prefix")?;
-                        out_file.write(body).context("error writing data to file")?;
-                        out_file
-                            .write(suffix)
-                            .context("unable to write file suffix")?;
-                        log::debug!(
-                            "{} bytes message added",
-                            prefix.len() + body.len() + suffix.len()
-                        );
-
-                        bytes_written += prefix.len() + body.len() + suffix.len();
+            while !pull_cancellation_token.is_cancelled()
+                && message_id <= selected_mailbox.exists as usize
+            {
+                let daily_remaining = match throttle {
+                    Some(ref throttle) => throttle.remaining_today()?,
+                    None => None,
+                };
+                let Some((batch_end, batch_size)) = batch_end(
+                    &mut imap_session,
+                    message_id,
+                    selected_mailbox.exists as usize,
+                    memory_limit,
+                    daily_remaining,
+                )
+                .await?
+                else {
+                    if let Some(ref throttle) = throttle {
+                        throttle.warn_daily_limit();
                     }
+                    paused = true;
+                    break;
+                };
+                // Paces the requests the server sees, not the local writes
+                if let Some(ref throttle) = throttle {
+                    throttle
+                        .wait(batch_end + 1 - message_id, batch_size)
+                        .await?;
                 }
-
-                message_id += batch_size;
-            }
-
-            out_file.flush().context("error flushing file")?;
-        } else {
-            while !pull_cancellation_token.is_cancelled() {
-                let sequence_set = format!("{message_id}:{}", message_id + batch_size - 1);
+                let sequence_set = format!("{message_id}:{batch_end}");
                 log::info!("Querying {sequence_set}");
 
-                let messages_stream = imap_session
-                    .fetch(sequence_set, "RFC822")
+                let mut messages_stream = imap_session
+                    .fetch(sequence_set, "BODY.PEEK[]")
                     .await
                     .context("error getting messages")?;
-                let messages: Vec<_> = messages_stream.try_collect().await?;
-
-                if messages.len() == 0 {
-                    log::debug!("No more messages");
-                    break;
-                } else {
-                    log::debug!("Fetching {} messages", messages.len());
 
-                    let mut current_message_id = message_id - 1;
+                let mut current_message_id = message_id - 1;
 
-                    for message in messages {
-                        current_message_id += 1;
-                        let body = message.body().context("message did not have a body!")?;
-
-                        let bin_file_name = format!(".{:0>8}.eml", current_message_id);
+                // Messages are written as they arrive instead of collecting the batch
+                while let Some(message) = messages_stream.next().await {
+                    let message = message?;
+                    current_message_id += 1;
+                    let body = message.body().context("message did not have a body!")?;
+                    let body_string = std::str::from_utf8(body).ok();
+                    if body_string.is_none() {
+                        let bin_file_name = format!("{:0>8}.bin", current_message_id);
                         let bin_file_path = if folder_name.clone().starts_with("/") {
                             PathBuf::from_str("/")
                                 .unwrap()
@@ -298,18 +405,213 @@ pub async fn pull(
                         fs::write(bin_file_path, body)
                             .context("unable to write file")
                             .context("unable to save *.bin file")?;
+                        log::debug!("{} bytes bin data stored", body.len());
+                        report.transferred(&bin_file_name, body.len());
+
+                        log::warn!(
+                            "Message {} had invalid UTF-8. Storing as binary in {}.",
+                            current_message_id,
+                            bin_file_name
+                        );
+                        continue;
+                    }
+                    let body = body_string.unwrap().as_bytes();
+
+                    let dt = chrono::Utc::now();
+                    let timestamp = dt.format("%a %b %e %T %Y").to_string();
+                    let prefix_string = format!("From MAILER-DAEMON {timestamp}\n");
+                    let prefix = prefix_string.as_bytes();
+
+                    let suffix_string = "\n\n".to_string();
+                    let suffix = suffix_string.as_bytes();
+
+                    if bytes_written + prefix.len() + body.len() + suffix.len() > max_file_size {
+                        log::debug!(
+                            "File size exceed limit {} > {}",
+                            prefix.len() + body.len() + suffix.len(),
+                            max_file_size
+                        );
+                        out_file.flush().context("error flushing file")?;
+
+                        part_id += 1;
+
+                        let file_name = format!("part-{:0>4}.mbox", part_id);
+                        let file_path = if folder_name.clone().starts_with("/") {
+                            PathBuf::from_str("/")
+                                .unwrap()
+                                .join(folder_name.clone())
+                                .join(file_name.clone())
+                        } else {
+                            current_dir()
+                                .unwrap()
+                                .join(folder_name.clone())
+                                .join(file_name.clone())
+                        };
 
-                        total_pulled_count += 1;
+                        log::debug!("Creating part {}", file_path.to_string_lossy());
+                        out_file = File::create(file_path).context("Unable to open file")?;
 
-                        log::debug!("{} bytes eml message added", body.len());
+                        bytes_written = 0;
                     }
+
+                    out_file
+                        .write(prefix)
+                        .context("unable to write file prefix")?;
+                    out_file.write(body).context("error writing data to file")?;
+                    out_file
+                        .write(suffix)
+                        .context("unable to write file suffix")?;
+                    log::debug!(
+                        "{} bytes message added",
+                        prefix.len() + body.len() + suffix.len()
+                    );
+
+                    bytes_written += prefix.len() + body.len() + suffix.len();
+                    report.transferred(&format!("{:0>8}.eml", current_message_id), body.len());
                 }
 
-                message_id += batch_size;
+                message_id = batch_end + 1;
             }
+
+            out_file.flush().context("error flushing file")?;
+        } else {
+            while !pull_cancellation_token.is_cancelled()
+                && message_id <= selected_mailbox.exists as usize
+            {
+                let daily_remaining = match throttle {
+                    Some(ref throttle) => throttle.remaining_today()?,
+                    None => None,
+                };
+                let Some((batch_end, batch_size)) = batch_end(
+                    &mut imap_session,
+                    message_id,
+                    selected_mailbox.exists as usize,
+                    memory_limit,
+                    daily_remaining,
+                )
+                .await?
+                else {
+                    if let Some(ref throttle) = throttle {
+                        throttle.warn_daily_limit();
+                    }
+                    paused = true;
+                    break;
+                };
+                // Paces the requests the server sees, not the local writes
+                if let Some(ref throttle) = throttle {
+                    throttle
+                        .wait(batch_end + 1 - message_id, batch_size)
+                        .await?;
+                }
+                let sequence_set = format!("{message_id}:{batch_end}");
+                log::info!("Querying {sequence_set}");
+
+                let mut messages_stream = imap_session
+                    .fetch(&sequence_set, "(UID FLAGS BODY.PEEK[])")
+                    .await
+                    .context("error getting messages")?;
+                let mut current_message_id = message_id - 1;
+                let mut stored_uids = Vec::new();
+
+                // Messages are written as they arrive instead of collecting the batch
+                while let Some(message) = messages_stream.next().await {
+                    let message = message?;
+                    current_message_id += 1;
+                    let body = message.body().context("message did not have a body!")?;
+
+                    let bin_file_name = format!(".{:0>8}.eml", current_message_id);
+                    let bin_file_path = if folder_name.clone().starts_with("/") {
+                        PathBuf::from_str("/")
+                            .unwrap()
+                            .join(folder_name.clone())
+                            .join(bin_file_name.clone())
+                    } else {
+                        current_dir()
+                            .unwrap()
+                            .join(folder_name.clone())
+                            .join(bin_file_name.clone())
+                    };
+                    fs::write(bin_file_path, body)
+                        .context("unable to write file")
+                        .context("unable to save *.bin file")?;
+
+                    if let (Some(pull_state), Some(uid)) = (pull_state.as_mut(), message.uid) {
+                        pull_state.messages.insert(
+                            uid,
+                            StoredMessage {
+                                file_name: format!("{:0>8}.eml", current_message_id),
+                                flags: flag_names(message.flags()),
+                            },
+                        );
+                    }
+
+                    if let Some(uid) = message.uid {
+                        stored_uids.push((uid, current_message_id));
+                    }
+
+                    total_pulled_count += 1;
+                    report.transferred(&format!("{:0>8}.eml", current_message_id), body.len());
+
+                    log::debug!("{} bytes eml message added", body.len());
+                }
+                drop(messages_stream);
+
+                // Labels are looked up by UID, sequence numbers of the side
+                // connection may differ once messages are expunged
+                if let (Some(ref mut gmail_client), false) =
+                    (gmail_client.as_mut(), stored_uids.is_empty())
+                {
+                    let uids: Vec<_> = stored_uids.iter().map(|(uid, _)| *uid).collect();
+                    let gmail_metadata = gmail_client.fetch_metadata(&uid_set(&uids)).await?;
+                    for (uid, stored_message_id) in stored_uids {
+                        if let Some(metadata) = gmail_metadata.get(&uid) {
+                            gmail::save_metadata(
+                                Path::new(folder_path),
+                                &format!("{:0>8}.eml", stored_message_id),
+                                metadata,
+                            )?;
+                        }
+                    }
+                }
+
+                // Checkpoint of the batch, the folder counts as unchanged only
+                // once it is complete
+                if let Some(ref mut pull_state) = pull_state {
+                    pull_state.uid_validity = selected_mailbox.uid_validity;
+                    pull_state.highest_modseq = None;
+                    save_pull_state(Path::new(folder_path), pull_state)?;
+                }
+
+                message_id = batch_end + 1;
+            }
+        }
+
+        if let Some(mut pull_state) = pull_state {
+            pull_state.uid_validity = selected_mailbox.uid_validity;
+            // An interrupted folder must not be skipped by the next run
+            pull_state.highest_modseq = if pull_cancellation_token.is_cancelled() || paused {
+                None
+            } else {
+                selected_mailbox.highest_modseq
+            };
+            save_pull_state(Path::new(folder_path), &pull_state)?;
         }
     }
 
+    if pull_cancellation_token.is_cancelled() {
+        report.cancel();
+    }
+    if paused {
+        report.pause();
+    }
+
+    if unchanged_count > 0 {
+        log::info!("{unchanged_count} mailboxes unchanged since the last run");
+    }
+
+    if let Some(ref mut gmail_client) = gmail_client {
+        gmail_client.logout().await?;
+    }
     imap_session.logout().await?;
 
     log::info!(
@@ -320,3 +622,75 @@ pub async fn pull(
 
     Ok(())
 }
+
+/// Picks the last message of the batch starting at `message_id`, so that
+/// messages of the batch fit into `memory_limit` by their `RFC822.SIZE`. The
+/// first message is always taken, however large it is, unless it exceeds
+/// `daily_remaining`; `None` is returned then. Returns the last message and
+/// the size of the batch.
+async fn batch_end(
+    imap_session: &mut ImapSession,
+    message_id: usize,
+    exists: usize,
+    memory_limit: usize,
+    daily_remaining: Option<u64>,
+) -> anyhow::Result<Option<(usize, usize)>> {
+    let last_message_id = (message_id + MAX_BATCH_SIZE - 1).min(exists);
+    let messages_stream = imap_session
+        .fetch(format!("{message_id}:{last_message_id}"), "RFC822.SIZE")
+        .await
+        .context("error getting message sizes")?;
+    let mut sizes: Vec<_> = messages_stream
+        .map(|message| message.map(|message| (message.message as usize, message.size)))
+        .try_collect()
+        .await?;
+    sizes.sort();
+
+    let mut batch_end = None;
+    let mut batch_size = 0;
+    for (current_message_id, size) in sizes {
+        let size = size.unwrap_or_default() as usize;
+        if daily_remaining.is_some_and(|remaining| (batch_size + size) as u64 > remaining) {
+            break;
+        }
+        if current_message_id > message_id && batch_size + size > memory_limit {
+            break;
+        }
+        batch_size += size;
+        batch_end = Some(current_message_id);
+    }
+
+    if batch_size > memory_limit {
+        log::debug!("Message {message_id} exceeds the memory limit, fetching it alone");
+    }
+
+    Ok(batch_end.map(|batch_end| (batch_end, batch_size)))
+}
+
+/// Loads the checkpoint of a folder, returns `None` for folders pulled before
+/// checkpoints were kept.
+pub fn load_pull_state(folder_path: &Path) -> anyhow::Result<Option<PullState>> {
+    let state_file_path = folder_path.join(PULL_STATE_FILE_NAME);
+    if !state_file_path.exists() {
+        return Ok(None);
+    }
+
+    let data = fs::read(&state_file_path).context(format!(
+        "unable to read pull state {}",
+        state_file_path.display()
+    ))?;
+    let pull_state = serde_json::from_slice(&data).context(format!(
+        "malformed pull state {}",
+        state_file_path.display()
+    ))?;
+
+    Ok(Some(pull_state))
+}
+
+pub(super) fn save_pull_state(folder_path: &Path, pull_state: &PullState) -> anyhow::Result<()> {
+    let state_file_path = folder_path.join(PULL_STATE_FILE_NAME);
+    fs::write(&state_file_path, serde_json::to_vec(pull_state)?).context(format!(
+        "unable to save pull state {}",
+        state_file_path.display()
+    ))
+}
@@ -1,18 +1,57 @@
 use anyhow::Context;
+use async_native_tls::TlsStream;
 use async_walkdir::{Filtering, WalkDir};
 use futures_lite::stream::StreamExt;
 use human_bytes::human_bytes;
-use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    env::current_dir,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 use tokio::{net::TcpStream, time::Instant};
 
-use crate::config::Config;
+use super::{
+    failures::{FailureKind, FailureList},
+    gmail,
+    pull::load_pull_state,
+    throttle::Throttle,
+};
+use crate::{
+    archive::absolute_path,
+    config::{Config, ImapServerConfig},
+    report::Report,
+};
+
+const MAX_APPEND_ATTEMPTS: u32 = 5;
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Options of `imap push` besides the account and the messages directory.
+#[derive(Debug)]
+pub struct PushOptions {
+    /// Account the messages were pulled from, the target account by default
+    pub from_account: Option<String>,
+    pub ignore_quota: bool,
+    pub retry_failed: bool,
+}
 
 pub async fn push(
     config: &Config,
     email: String,
-    password: String,
+    password: Option<String>,
     in_dir: String,
+    options: PushOptions,
+    report: &mut Report,
 ) -> anyhow::Result<()> {
+    let PushOptions {
+        from_account,
+        ignore_quota,
+        retry_failed,
+    } = options;
+
     let start = Instant::now();
 
     let cancellation_token = tokio_util::sync::CancellationToken::new();
@@ -24,15 +63,22 @@ pub async fn push(
         cancellation_token.cancel();
     });
 
-    let domain = email
+    // Messages are stored under the source account, which may differ from the target login
+    let source_email = from_account.unwrap_or_else(|| email.clone());
+    if source_email != email {
+        log::info!("Pushing messages of {source_email} to {email}");
+    }
+    let domain = source_email
         .split("@")
         .last()
-        .context("wrong email address {email}")?;
+        .context("wrong email address {source_email}")?;
     log::info!("Domain: {domain}");
 
     let mut total_pushed_count = 0;
+    let mut total_failed_count = 0;
+    let mut paused = false;
 
-    let folder_name = format!("{in_dir}/{domain}/{email}/",);
+    let folder_name = format!("{in_dir}/{domain}/{source_email}/",);
     let folder_path = if folder_name.clone().starts_with("/") {
         PathBuf::from_str("/").unwrap().join(folder_name.clone())
     } else {
@@ -40,6 +86,16 @@ pub async fn push(
     };
     let folder_path = folder_path.to_str().context("wrong in_dir path")?;
 
+    let mut failures = FailureList::load(Path::new(folder_path))?;
+    if retry_failed {
+        log::info!("Retrying {} failed messages", failures.len());
+    } else if !failures.is_empty() {
+        log::warn!(
+            "{} messages failed in earlier runs and are skipped, push them with --retry-failed",
+            failures.len()
+        );
+    }
+
     log::info!("Getting mailboxes in {}", folder_path);
 
     let mut mailboxes = Vec::<(String, String)>::new();
@@ -74,7 +130,29 @@ pub async fn push(
 
     log::info!("Found {} mailboxes", mailboxes.len());
 
-    if mailboxes.len() > 0 {
+    // Folders dropped by folder_rules are neither counted nor pushed
+    if let Some(push_config) = config.imap.as_ref().and_then(|imap| imap.push.as_ref()) {
+        mailboxes.retain(|(mailbox_name, _)| {
+            let dropped = push_config.map_folder_name(mailbox_name).is_none();
+            if dropped {
+                log::info!("Skipping mailbox {mailbox_name}, dropped by folder_rules");
+            }
+            !dropped
+        });
+    }
+
+    let mut message_counts = Vec::new();
+    for (_, mailbox_path) in mailboxes.iter() {
+        message_counts.push(count_messages(Path::new(mailbox_path))?);
+    }
+    report.expect(
+        message_counts
+            .iter()
+            .map(|(pending_count, already_pushed_count)| pending_count + already_pushed_count)
+            .sum(),
+    );
+
+    if !mailboxes.is_empty() {
         let imap_config = config
             .imap
             .clone()
@@ -85,6 +163,8 @@ pub async fn push(
         let imap_port = imap_config.port.unwrap_or(993);
         let imap_addr = (imap_server, imap_port);
         let folder_delimiter = &imap_config.folder_delimiter.unwrap_or('/').to_string();
+        let throttle = Throttle::for_server(&imap_config, &absolute_path(&in_dir))?;
+        let (login, password) = imap_config.credentials(&email, password.as_deref())?;
 
         log::debug!("Pushing IMAP for account {email}...");
         let tcp_stream = TcpStream::connect(imap_addr.clone()).await?;
@@ -94,35 +174,49 @@ pub async fn push(
         let client = async_imap::Client::new(tls_stream);
         log::info!("Connected to IMAP server {}:{}", imap_addr.0, imap_addr.1);
 
-        let mut imap_session = client.login(&email, &password).await.map_err(|e| e.0)?;
+        let mut imap_session = client.login(&login, &password).await.map_err(|e| e.0)?;
         log::info!("Logged in as {}", email);
 
-        for (mailbox_name, mailbox_path) in mailboxes {
-            let mailbox_mapped_name = match imap_config.folder_name_mappings {
-                Some(ref folder_name_mappings) => {
-                    if folder_name_mappings.contains_key(&mailbox_name) {
-                        folder_name_mappings.get(&mailbox_name).unwrap().clone()
-                    } else {
-                        mailbox_name.clone()
-                    }
-                }
-                None => mailbox_name.clone(),
-            };
-            let mailbox_utf7_name =
-                utf7_imap::encode_utf7_imap(mailbox_mapped_name.replace("/", folder_delimiter));
+        let append_limit = preflight(&mut imap_session, &mailboxes, ignore_quota).await?;
+
+        let mut created_mailboxes = HashSet::new();
+
+        for ((mailbox_name, mailbox_path), (pending_count, already_pushed_count)) in
+            mailboxes.into_iter().zip(message_counts)
+        {
+            if paused {
+                break;
+            }
+
+            let (mailbox_mapped_name, mailbox_utf7_name) =
+                map_mailbox_name(&imap_config, &mailbox_name, folder_delimiter)
+                    .context(format!("mailbox {mailbox_name} is dropped"))?;
 
             log::info!(
                 "Processing mailbox {} ({:?})",
                 mailbox_mapped_name,
                 &mailbox_utf7_name
             );
+            report.start_folder(&mailbox_name, Some(&mailbox_mapped_name));
+            report.found(pending_count + already_pushed_count);
+            report.skipped(already_pushed_count);
 
             if let Some(err) = imap_session.create(&mailbox_utf7_name).await.err() {
                 log::debug!("Unable to create folder: {}", err);
             }
+            created_mailboxes.insert(mailbox_utf7_name.clone());
 
             imap_session.select(&mailbox_utf7_name).await?;
             log::debug!("Mailbox {mailbox_name} selected");
+            let mut selected_mailbox = mailbox_utf7_name.clone();
+
+            // Messages pulled before flags were recorded are uploaded as seen
+            let message_flags = stored_flags(Path::new(&mailbox_path))?;
+
+            let gmail_metadata = gmail::load_metadata(Path::new(&mailbox_path))?;
+            if gmail_metadata.is_some() {
+                log::info!("Gmail labels found, messages are filed into label folders");
+            }
 
             let mut entries = WalkDir::new(&mailbox_path).filter(|entry| async move {
                 match entry.file_type().await {
@@ -160,6 +254,18 @@ pub async fn push(
                             .trim_start_matches('0')
                             .trim()
                             .trim_end_matches(".eml");
+                        let file_name = format!("{:0>8}.eml", message_id);
+
+                        // Either recorded failures or everything else is pushed
+                        let failed_before = failures.contains(&mailbox_name, &file_name);
+                        if failed_before && !retry_failed {
+                            report.failed(&file_name, "failed in an earlier run");
+                            continue;
+                        }
+                        if !failed_before && retry_failed {
+                            report.skipped(1);
+                            continue;
+                        }
 
                         log::debug!(
                             "Pushing message {} to {}...",
@@ -167,15 +273,131 @@ pub async fn push(
                             mailbox_mapped_name
                         );
 
-                        let eml_data = fs::read(&eml_file_path).ok();
+                        let eml_data = match fs::read(&eml_file_path) {
+                            Ok(data) => Some(data),
+                            Err(err) => {
+                                report.failed(&file_name, &format!("unable to read file: {err}"));
+                                total_failed_count += 1;
+                                None
+                            }
+                        };
                         if let Some(data) = eml_data {
                             let size = data.len() as u32;
 
-                            match imap_session
-                                .append(&mailbox_utf7_name, Some(r"(\Seen)"), None, data)
-                                .await
+                            if let Some(append_limit) =
+                                append_limit.filter(|limit| data.len() as u64 > *limit)
                             {
+                                log::debug!(
+                                    "Message {} in {} exceeds APPENDLIMIT, skipped",
+                                    eml_file_name,
+                                    mailbox_mapped_name
+                                );
+                                let reason = format!("exceeds APPENDLIMIT of {append_limit} bytes");
+                                report.failed(&file_name, &reason);
+                                failures.insert(
+                                    &mailbox_name,
+                                    &file_name,
+                                    FailureKind::TooLarge,
+                                    &reason,
+                                )?;
+                                total_failed_count += 1;
+                                continue;
+                            }
+
+                            let label_folders = gmail_metadata
+                                .as_ref()
+                                .and_then(|gmail_metadata| {
+                                    gmail_metadata.get(&format!("{:0>8}.eml", message_id))
+                                })
+                                .map(|metadata| {
+                                    gmail::label_folders(
+                                        &metadata.labels,
+                                        &imap_config.gmail_label_mappings,
+                                    )
+                                })
+                                .unwrap_or_default();
+                            // Messages whose labels are all dropped stay in the pushed folder
+                            let mut target_mailboxes: Vec<_> = label_folders
+                                .iter()
+                                .filter_map(|label_folder| {
+                                    map_mailbox_name(&imap_config, label_folder, folder_delimiter)
+                                })
+                                .map(|(_, label_utf7_name)| label_utf7_name)
+                                .collect();
+                            if target_mailboxes.is_empty() {
+                                target_mailboxes.push(mailbox_utf7_name.clone());
+                            }
+
+                            for target_mailbox in target_mailboxes.iter() {
+                                if created_mailboxes.insert(target_mailbox.clone()) {
+                                    if let Some(err) =
+                                        imap_session.create(target_mailbox).await.err()
+                                    {
+                                        log::debug!("Unable to create folder: {}", err);
+                                    }
+                                }
+                            }
+
+                            if let Some(ref throttle) = throttle {
+                                if throttle
+                                    .remaining_today()?
+                                    .is_some_and(|remaining| data.len() as u64 > remaining)
+                                {
+                                    throttle.warn_daily_limit();
+                                    paused = true;
+                                    break;
+                                }
+                                throttle.wait(1, data.len()).await?;
+                            }
+
+                            let flags = message_flags
+                                .get(&format!("{:0>8}.eml", message_id))
+                                .map(String::as_str)
+                                .unwrap_or(r"(\Seen)");
+
+                            let mut retry_delay = MIN_RETRY_DELAY;
+                            let mut attempt = 1;
+                            let result = loop {
+                                let result = imap_session
+                                    .append(&target_mailboxes[0], Some(flags), None, &data)
+                                    .await;
+                                match result {
+                                    Err(ref err)
+                                        if attempt < MAX_APPEND_ATTEMPTS
+                                            && FailureKind::classify(err)
+                                                == FailureKind::RateLimited
+                                            && !push_cancellation_token.is_cancelled() =>
+                                    {
+                                        log::warn!(
+                                            "Rate limited pushing {} ({}), retrying in {:?}",
+                                            file_name,
+                                            err,
+                                            retry_delay
+                                        );
+                                        tokio::select! {
+                                            _ = push_cancellation_token.cancelled() => {}
+                                            _ = tokio::time::sleep(retry_delay) => {}
+                                        }
+                                        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                                        attempt += 1;
+                                    }
+                                    result => break result,
+                                }
+                            };
+
+                            match result {
                                 Ok(_) => {
+                                    if target_mailboxes.len() > 1 {
+                                        copy_to_mailboxes(
+                                            &mut imap_session,
+                                            &data,
+                                            flags,
+                                            &target_mailboxes,
+                                            &mut selected_mailbox,
+                                        )
+                                        .await?;
+                                    }
+
                                     let eml_file_name = format!(".{:0>8}.eml", message_id);
                                     let new_eml_file_name = format!("{:0>8}.eml", message_id);
                                     let new_eml_file_path =
@@ -184,10 +406,33 @@ pub async fn push(
 
                                     pushed_count += 1;
                                     total_pushed_count += 1;
+                                    report.transferred(&new_eml_file_name, data.len());
+                                    failures.remove(&mailbox_name, &file_name)?;
 
                                     log::debug!("{} sent ok", human_bytes(size));
                                 }
-                                Err(err) => log::debug!("Error pushing message: {}", err),
+                                Err(err) => {
+                                    let kind = FailureKind::classify(&err);
+                                    log::warn!(
+                                        "Error pushing message {} to {} ({}): {}",
+                                        file_name,
+                                        mailbox_mapped_name,
+                                        kind,
+                                        err
+                                    );
+                                    report.failed(&file_name, &format!("{kind}: {err}"));
+                                    failures.insert(
+                                        &mailbox_name,
+                                        &file_name,
+                                        kind,
+                                        &err.to_string(),
+                                    )?;
+                                    total_failed_count += 1;
+
+                                    if kind.is_fatal() {
+                                        anyhow::bail!("push stopped, {kind}: {err}");
+                                    }
+                                }
                             };
                         }
                     }
@@ -204,11 +449,263 @@ pub async fn push(
         imap_session.logout().await?;
     }
 
+    if push_cancellation_token.is_cancelled() {
+        report.cancel();
+    }
+    if paused {
+        report.pause();
+    }
+
     log::info!(
-        "Done in {:?}, {} messages uploaded ok.",
+        "Done in {:?}, {} messages uploaded ok, {} failed.",
         start.elapsed(),
-        total_pushed_count
+        total_pushed_count,
+        total_failed_count
     );
 
     Ok(())
 }
+
+/// Flags of stored messages in `APPEND` syntax by file name (`00000001.eml`)
+/// as recorded by `imap pull` and `imap watch`.
+pub(super) fn stored_flags(folder_path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    Ok(load_pull_state(folder_path)?
+        .map(|pull_state| {
+            pull_state
+                .messages
+                .into_values()
+                .map(|stored_message| {
+                    let flags = stored_message.flags.into_iter().collect::<Vec<_>>();
+                    (stored_message.file_name, format!("({})", flags.join(" ")))
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Compares pending messages with the free quota of the target, fails when
+/// they don't fit unless `ignore_quota` is set. Lists messages larger than
+/// `APPENDLIMIT` (RFC 7889) and returns the limit.
+async fn preflight(
+    imap_session: &mut async_imap::Session<TlsStream<TcpStream>>,
+    mailboxes: &[(String, String)],
+    ignore_quota: bool,
+) -> anyhow::Result<Option<u64>> {
+    let capabilities = imap_session
+        .capabilities()
+        .await
+        .context("error getting capabilities")?;
+    // APPENDLIMIT without a value means the limit differs per mailbox
+    let append_limit = capabilities.iter().find_map(|capability| match capability {
+        async_imap::types::Capability::Atom(atom) => atom
+            .strip_prefix("APPENDLIMIT=")
+            .and_then(|limit| limit.parse::<u64>().ok()),
+        _ => None,
+    });
+
+    let mut pending_count = 0u64;
+    let mut pending_bytes = 0u64;
+    let mut oversized_count = 0;
+    for (mailbox_name, mailbox_path) in mailboxes {
+        let mut entries = fs::read_dir(mailbox_path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with('.') || !file_name.ends_with(".eml") {
+                continue;
+            }
+            let size = entry.metadata()?.len();
+            pending_count += 1;
+            pending_bytes += size;
+
+            if append_limit.is_some_and(|limit| size > limit) {
+                log::warn!(
+                    "Message {} in {} ({}) exceeds APPENDLIMIT and won't be pushed",
+                    file_name,
+                    mailbox_name,
+                    human_bytes(size as f64)
+                );
+                oversized_count += 1;
+            }
+        }
+    }
+    if let Some(append_limit) = append_limit {
+        log::info!(
+            "APPENDLIMIT {}, {} messages exceed it",
+            human_bytes(append_limit as f64),
+            oversized_count
+        );
+    }
+
+    if !capabilities.has_str("QUOTA") {
+        log::debug!("Server doesn't support QUOTA, skipping quota check");
+        return Ok(append_limit);
+    }
+
+    let quotas = match imap_session.get_quota_root("INBOX").await {
+        Ok((_, quotas)) => quotas,
+        Err(err) => {
+            log::warn!("Unable to get quota: {}", err);
+            return Ok(append_limit);
+        }
+    };
+
+    for quota in quotas {
+        for resource in quota.resources {
+            // STORAGE is counted in units of 1024 octets
+            let (free, needed, unit) = match resource.name {
+                async_imap::types::QuotaResourceName::Storage => (
+                    resource.limit.saturating_sub(resource.usage) * 1024,
+                    pending_bytes,
+                    "bytes",
+                ),
+                async_imap::types::QuotaResourceName::Message => (
+                    resource.limit.saturating_sub(resource.usage),
+                    pending_count,
+                    "messages",
+                ),
+                _ => continue,
+            };
+            log::info!(
+                "Quota {:?}: {} {} free, {} {} pending",
+                quota.root_name,
+                free,
+                unit,
+                needed,
+                unit
+            );
+
+            if needed > free {
+                if ignore_quota {
+                    log::warn!(
+                        "Pending messages exceed the free quota by {} {}",
+                        needed - free,
+                        unit
+                    );
+                } else {
+                    anyhow::bail!(
+                        "pending messages exceed the free quota {:?} by {} {}, use --ignore-quota to push anyway",
+                        quota.root_name,
+                        needed - free,
+                        unit
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(append_limit)
+}
+
+/// Counts pending and already pushed messages of a folder.
+fn count_messages(mailbox_path: &Path) -> anyhow::Result<(usize, usize)> {
+    let mut pending_count = 0;
+    let mut pushed_count = 0;
+
+    for entry in fs::read_dir(mailbox_path)? {
+        let path = entry?.path();
+        if path.extension() != Some("eml".as_ref()) {
+            continue;
+        }
+        if path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .starts_with('.')
+        {
+            pending_count += 1;
+        } else {
+            pushed_count += 1;
+        }
+    }
+
+    Ok((pending_count, pushed_count))
+}
+
+/// Applies folder name mappings and rules and the target delimiter, returns
+/// readable and UTF-7 encoded names, `None` for dropped folders.
+pub(super) fn map_mailbox_name(
+    imap_config: &ImapServerConfig,
+    mailbox_name: &str,
+    folder_delimiter: &str,
+) -> Option<(String, String)> {
+    let mailbox_mapped_name = imap_config.map_folder_name(mailbox_name)?;
+    let mailbox_utf7_name =
+        utf7_imap::encode_utf7_imap(mailbox_mapped_name.replace("/", folder_delimiter));
+
+    Some((mailbox_mapped_name, mailbox_utf7_name))
+}
+
+/// Copies a message appended to the first mailbox into the others. The copy
+/// is found by Message-ID, messages without one are uploaded again.
+async fn copy_to_mailboxes(
+    imap_session: &mut async_imap::Session<TlsStream<TcpStream>>,
+    data: &[u8],
+    flags: &str,
+    target_mailboxes: &[String],
+    selected_mailbox: &mut String,
+) -> anyhow::Result<()> {
+    let uid = match message_id_header(data) {
+        Some(message_id) => {
+            if *selected_mailbox != target_mailboxes[0] {
+                imap_session.select(&target_mailboxes[0]).await?;
+                *selected_mailbox = target_mailboxes[0].clone();
+            }
+            imap_session
+                .uid_search(format!(
+                    "HEADER Message-ID \"{}\"",
+                    message_id.replace('\\', "\\\\").replace('"', "\\\"")
+                ))
+                .await?
+                .into_iter()
+                .max()
+        }
+        None => None,
+    };
+
+    for target_mailbox in target_mailboxes.iter().skip(1) {
+        let result = match uid {
+            Some(uid) => imap_session
+                .uid_copy(uid.to_string(), target_mailbox)
+                .await
+                .map(|_| ()),
+            None => imap_session
+                .append(target_mailbox, Some(flags), None, data)
+                .await
+                .map(|_| ()),
+        };
+        match result {
+            Ok(_) => log::debug!("Copied to {target_mailbox}"),
+            Err(err) => log::warn!("Error copying message to {}: {}", target_mailbox, err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the unfolded Message-ID header value.
+pub(super) fn message_id_header(data: &[u8]) -> Option<String> {
+    let header_end = data
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .or_else(|| data.windows(2).position(|window| window == b"\n\n"))
+        .unwrap_or(data.len());
+    let header = String::from_utf8_lossy(&data[..header_end]);
+
+    let mut lines = header.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !name.eq_ignore_ascii_case("Message-ID") {
+            continue;
+        }
+        let mut value = value.trim().to_string();
+        while let Some(next_line) = lines.next_if(|line| line.starts_with([' ', '\t'])) {
+            value.push_str(next_line.trim());
+        }
+        return Some(value).filter(|value| !value.is_empty());
+    }
+
+    None
+}
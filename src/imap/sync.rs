@@ -0,0 +1,813 @@
+//! Stateful synchronization of flags and expunges between copies of the same
+//! message on both servers. Copies are paired by Message-ID once and tracked
+//! by UID afterwards, together with the flags they had after the last run.
+
+use anyhow::Context;
+use async_imap::types::{Flag, NameAttribute};
+use clap::ValueEnum;
+use futures_lite::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    env::current_dir,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+use tokio::time::Instant;
+
+use super::{
+    connect,
+    push::{map_mailbox_name, message_id_header},
+    throttle::Throttle,
+    ImapSession,
+};
+use crate::{
+    archive::absolute_path,
+    config::{Config, ImapServerConfig},
+};
+
+pub const SYNC_STATE_FILE_NAME: &str = ".imap-sync";
+/// UIDs per command, keeps command lines well below server limits
+const UID_BATCH_SIZE: usize = 500;
+
+/// Side whose change is kept when a message changed on both servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConflictWinner {
+    Source,
+    Target,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncState {
+    source_uid_validity: Option<u32>,
+    target_uid_validity: Option<u32>,
+    source_highest_modseq: Option<u64>,
+    target_highest_modseq: Option<u64>,
+    messages: Vec<SyncedMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SyncedMessage {
+    source_uid: u32,
+    target_uid: u32,
+    /// Flags of both copies after the last run
+    flags: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct SyncCounts {
+    paired: usize,
+    updated: usize,
+    expunged: usize,
+}
+
+/// Messages of a selected mailbox, `flags` holds only messages changed since
+/// the last run when the server supports CONDSTORE.
+struct Snapshot {
+    uid_validity: Option<u32>,
+    highest_modseq: Option<u64>,
+    uids: HashSet<u32>,
+    flags: HashMap<u32, BTreeSet<String>>,
+}
+
+struct Server {
+    session: ImapSession,
+    condstore: bool,
+    uidplus: bool,
+    throttle: Option<Arc<Throttle>>,
+}
+
+pub async fn sync(
+    config: &Config,
+    email: String,
+    password: Option<String>,
+    out_dir: String,
+    bidirectional: bool,
+    conflict_winner: ConflictWinner,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let sync_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let imap_config = config
+        .imap
+        .clone()
+        .context("IMAP config is not provided in config.yaml")?;
+    let pull_config = imap_config
+        .pull
+        .context("IMAP pull server config not provided")?;
+    let push_config = imap_config
+        .push
+        .context("IMAP push server config not provided")?;
+    let folder_delimiter = &push_config.folder_delimiter.unwrap_or('/').to_string();
+
+    let usage_dir = absolute_path(&out_dir);
+    let mut source = Server::connect(&pull_config, &email, password.as_deref(), &usage_dir).await?;
+    let mut target = Server::connect(&push_config, &email, password.as_deref(), &usage_dir).await?;
+    log::info!("Logged in as {} on both servers", email);
+
+    for (server, name) in [(&source, "Source"), (&target, "Target")] {
+        if !server.condstore {
+            log::info!("{name} server doesn't support CONDSTORE, comparing all flags");
+        }
+    }
+
+    let mailbox_stream = source
+        .session
+        .list(None, Some("*"))
+        .await
+        .context("error getting mailbox list")?;
+    let mailboxes: Vec<_> = mailbox_stream.try_collect().await?;
+
+    log::info!("Loaded {} mailboxes", mailboxes.len());
+
+    // `None` stands for one-way sync
+    let conflict_winner = bidirectional.then_some(conflict_winner);
+    let mut total_counts = SyncCounts::default();
+
+    for mailbox in mailboxes {
+        if sync_cancellation_token.is_cancelled() {
+            break;
+        }
+        if mailbox.attributes().contains(&NameAttribute::NoSelect) {
+            continue;
+        }
+
+        let mailbox_name = mailbox.name();
+        let mailbox_readable_name = utf7_imap::decode_utf7_imap(mailbox_name.to_string());
+        let Some((target_mapped_name, target_utf7_name)) =
+            map_mailbox_name(&push_config, &mailbox_readable_name, folder_delimiter)
+        else {
+            log::info!("Skipping mailbox {mailbox_readable_name}, dropped by folder_rules");
+            continue;
+        };
+        log::info!(
+            "Mailbox: {:?} -> {:?}",
+            mailbox_readable_name,
+            target_mapped_name
+        );
+
+        let folder_name = format!("{out_dir}/{domain}/{email}/{mailbox_readable_name}",);
+        let folder_path = if folder_name.clone().starts_with("/") {
+            PathBuf::from_str("/").unwrap().join(folder_name.clone())
+        } else {
+            current_dir().unwrap().join(folder_name.clone())
+        };
+        fs::create_dir_all(&folder_path)?;
+
+        match sync_mailbox(
+            &mut source,
+            &mut target,
+            mailbox_name,
+            &target_utf7_name,
+            &folder_path.join(SYNC_STATE_FILE_NAME),
+            conflict_winner,
+        )
+        .await
+        {
+            Ok(counts) => {
+                log::info!(
+                    "Paired {} messages, updated flags of {}, expunged {}",
+                    counts.paired,
+                    counts.updated,
+                    counts.expunged
+                );
+                total_counts.paired += counts.paired;
+                total_counts.updated += counts.updated;
+                total_counts.expunged += counts.expunged;
+            }
+            Err(err) => log::warn!("Unable to sync {}: {}", mailbox_readable_name, err),
+        }
+    }
+
+    source.session.logout().await?;
+    target.session.logout().await?;
+
+    log::info!(
+        "Done in {:?}, {} messages paired, {} flag updates, {} expunges.",
+        start.elapsed(),
+        total_counts.paired,
+        total_counts.updated,
+        total_counts.expunged
+    );
+
+    Ok(())
+}
+
+async fn sync_mailbox(
+    source: &mut Server,
+    target: &mut Server,
+    source_mailbox: &str,
+    target_mailbox: &str,
+    state_file_path: &Path,
+    conflict_winner: Option<ConflictWinner>,
+) -> anyhow::Result<SyncCounts> {
+    let mut state = load_state(state_file_path)?;
+    let mut counts = SyncCounts::default();
+
+    let source_snapshot = source
+        .snapshot(
+            source_mailbox,
+            state.source_uid_validity,
+            state.source_highest_modseq,
+        )
+        .await?;
+    let target_snapshot = target
+        .snapshot(
+            target_mailbox,
+            state.target_uid_validity,
+            state.target_highest_modseq,
+        )
+        .await?;
+
+    if (state.source_uid_validity.is_some()
+        && state.source_uid_validity != source_snapshot.uid_validity)
+        || (state.target_uid_validity.is_some()
+            && state.target_uid_validity != target_snapshot.uid_validity)
+    {
+        log::warn!("UIDVALIDITY changed, pairing messages again");
+        state.messages.clear();
+    }
+
+    let mut synced_messages = Vec::new();
+    let mut source_expunges = Vec::new();
+    let mut target_expunges = Vec::new();
+
+    for mut message in std::mem::take(&mut state.messages) {
+        let source_copy = source_snapshot
+            .uids
+            .contains(&message.source_uid)
+            .then(|| source_snapshot.flags.get(&message.source_uid));
+        let target_copy = target_snapshot
+            .uids
+            .contains(&message.target_uid)
+            .then(|| target_snapshot.flags.get(&message.target_uid));
+
+        match resolve(&message.flags, source_copy, target_copy, conflict_winner) {
+            Resolution::Unchanged => synced_messages.push(message),
+            Resolution::UpdateTarget { current, flags } => {
+                target
+                    .update_flags(message.target_uid, &current, &flags)
+                    .await?;
+                message.flags = flags;
+                counts.updated += 1;
+                synced_messages.push(message);
+            }
+            Resolution::UpdateSource { current, flags } => {
+                source
+                    .update_flags(message.source_uid, &current, &flags)
+                    .await?;
+                message.flags = flags;
+                counts.updated += 1;
+                synced_messages.push(message);
+            }
+            Resolution::ExpungeTarget => target_expunges.push(message.target_uid),
+            Resolution::ExpungeSource => source_expunges.push(message.source_uid),
+            Resolution::Forget => {
+                log::debug!(
+                    "Forgetting message {} / {}",
+                    message.source_uid,
+                    message.target_uid
+                );
+            }
+        }
+    }
+
+    counts.expunged += source.expunge(&source_expunges).await?;
+    counts.expunged += target.expunge(&target_expunges).await?;
+
+    // Messages copied since the last run are paired by Message-ID and take
+    // the flags of the source
+    let paired_source_uids: HashSet<_> = synced_messages.iter().map(|m| m.source_uid).collect();
+    let paired_target_uids: HashSet<_> = synced_messages.iter().map(|m| m.target_uid).collect();
+    let mut unpaired_source_uids: Vec<_> = source_snapshot
+        .uids
+        .difference(&paired_source_uids)
+        .copied()
+        .collect();
+    let unpaired_target_uids: Vec<_> = target_snapshot
+        .uids
+        .difference(&paired_target_uids)
+        .copied()
+        .collect();
+    unpaired_source_uids.sort();
+
+    if !unpaired_source_uids.is_empty() && !unpaired_target_uids.is_empty() {
+        let source_messages = source.fetch_unpaired(&unpaired_source_uids).await?;
+        let target_messages = target.fetch_unpaired(&unpaired_target_uids).await?;
+
+        let mut target_uids_by_message_id: HashMap<&str, Vec<u32>> = HashMap::new();
+        for (uid, (message_id, _)) in target_messages.iter() {
+            if let Some(message_id) = message_id {
+                target_uids_by_message_id
+                    .entry(message_id)
+                    .or_default()
+                    .push(*uid);
+            }
+        }
+        for target_uids in target_uids_by_message_id.values_mut() {
+            target_uids.sort_by(|a, b| b.cmp(a));
+        }
+
+        for source_uid in unpaired_source_uids {
+            let Some((Some(message_id), source_flags)) = source_messages.get(&source_uid) else {
+                continue;
+            };
+            let Some(target_uid) = target_uids_by_message_id
+                .get_mut(message_id.as_str())
+                .and_then(|target_uids| target_uids.pop())
+            else {
+                continue;
+            };
+
+            let target_flags = &target_messages[&target_uid].1;
+            if target_flags != source_flags {
+                target
+                    .update_flags(target_uid, target_flags, source_flags)
+                    .await?;
+                counts.updated += 1;
+            }
+
+            synced_messages.push(SyncedMessage {
+                source_uid,
+                target_uid,
+                flags: source_flags.clone(),
+            });
+            counts.paired += 1;
+        }
+    }
+
+    // Taken before the updates above, so the next run sees them again, but
+    // as copies already in sync. One-way runs keep changes made on the target
+    // visible for a later bidirectional run.
+    state.source_highest_modseq = source_snapshot.highest_modseq;
+    if conflict_winner.is_some()
+        || state.target_highest_modseq.is_none()
+        || state.target_uid_validity != target_snapshot.uid_validity
+    {
+        state.target_highest_modseq = target_snapshot.highest_modseq;
+    }
+    state.source_uid_validity = source_snapshot.uid_validity;
+    state.target_uid_validity = target_snapshot.uid_validity;
+    state.messages = synced_messages;
+    save_state(state_file_path, &state)?;
+
+    Ok(counts)
+}
+
+impl Server {
+    async fn connect(
+        imap_config: &ImapServerConfig,
+        email: &str,
+        password: Option<&str>,
+        usage_dir: &Path,
+    ) -> anyhow::Result<Server> {
+        let mut session = connect(imap_config, email, password).await?;
+        let capabilities = session
+            .capabilities()
+            .await
+            .context("error getting capabilities")?;
+
+        Ok(Server {
+            condstore: capabilities.has_str("CONDSTORE"),
+            uidplus: capabilities.has_str("UIDPLUS"),
+            throttle: Throttle::for_server(imap_config, usage_dir)?,
+            session,
+        })
+    }
+
+    /// Selects the mailbox and reads its UIDs and flags, incrementally when
+    /// the mailbox is unchanged since `highest_modseq`.
+    async fn snapshot(
+        &mut self,
+        mailbox_name: &str,
+        uid_validity: Option<u32>,
+        highest_modseq: Option<u64>,
+    ) -> anyhow::Result<Snapshot> {
+        let mailbox = if self.condstore {
+            self.session.select_condstore(mailbox_name).await
+        } else {
+            self.session.select(mailbox_name).await
+        }
+        .context(format!("unable to select {mailbox_name}"))?;
+        while self.session.unsolicited_responses.try_recv().is_ok() {}
+
+        let uids = self.session.uid_search("ALL").await?;
+
+        let changed_since = match highest_modseq {
+            Some(highest_modseq) if self.condstore && mailbox.uid_validity == uid_validity => {
+                Some(highest_modseq)
+            }
+            _ => None,
+        };
+        let mut flags = HashMap::new();
+        if changed_since.is_some() && changed_since == mailbox.highest_modseq {
+            log::debug!("{mailbox_name} unchanged since the last run");
+        } else if !uids.is_empty() {
+            let query = match changed_since {
+                Some(modseq) => format!("(FLAGS) (CHANGEDSINCE {modseq})"),
+                None => "FLAGS".to_string(),
+            };
+            let messages_stream = self
+                .session
+                .uid_fetch("1:*", query)
+                .await
+                .context("error getting flags")?;
+            let messages: Vec<_> = messages_stream.try_collect().await?;
+
+            for message in messages {
+                if let Some(uid) = message.uid {
+                    flags.insert(uid, flag_names(message.flags()));
+                }
+            }
+        }
+
+        Ok(Snapshot {
+            uid_validity: mailbox.uid_validity,
+            highest_modseq: mailbox.highest_modseq,
+            uids: uids.into_iter().collect(),
+            flags,
+        })
+    }
+
+    /// Returns Message-IDs and flags of messages by UID.
+    async fn fetch_unpaired(
+        &mut self,
+        uids: &[u32],
+    ) -> anyhow::Result<HashMap<u32, (Option<String>, BTreeSet<String>)>> {
+        let mut sorted_uids = uids.to_vec();
+        sorted_uids.sort();
+
+        let mut messages = HashMap::new();
+        for uids in sorted_uids.chunks(UID_BATCH_SIZE) {
+            let messages_stream = self
+                .session
+                .uid_fetch(uid_set(uids), "(FLAGS BODY.PEEK[HEADER])")
+                .await
+                .context("error getting headers")?;
+            let fetched: Vec<_> = messages_stream.try_collect().await?;
+
+            for message in fetched {
+                if let Some(uid) = message.uid {
+                    let message_id = message.header().and_then(message_id_header);
+                    messages.insert(uid, (message_id, flag_names(message.flags())));
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Changes flags of a message from `current` to `flags`, other flags of
+    /// the message are left as they are.
+    async fn update_flags(
+        &mut self,
+        uid: u32,
+        current: &BTreeSet<String>,
+        flags: &BTreeSet<String>,
+    ) -> anyhow::Result<()> {
+        let added: Vec<_> = flags.difference(current).cloned().collect();
+        let removed: Vec<_> = current.difference(flags).cloned().collect();
+
+        for (operation, flags) in [("+FLAGS.SILENT", added), ("-FLAGS.SILENT", removed)] {
+            if flags.is_empty() {
+                continue;
+            }
+            log::debug!("UID {uid} {operation} ({})", flags.join(" "));
+            if let Some(ref throttle) = self.throttle {
+                throttle.wait(1, 0).await?;
+            }
+            let responses = self
+                .session
+                .uid_store(
+                    uid.to_string(),
+                    format!("{operation} ({})", flags.join(" ")),
+                )
+                .await?;
+            let _: Vec<_> = responses.try_collect().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks messages as deleted and expunges them, returns the number of
+    /// expunged messages. A plain EXPUNGE would remove every message marked
+    /// as deleted, so without UIDPLUS the messages are only marked.
+    async fn expunge(&mut self, uids: &[u32]) -> anyhow::Result<usize> {
+        for uids in uids.chunks(UID_BATCH_SIZE) {
+            log::debug!("Expunging {} messages", uids.len());
+            if let Some(ref throttle) = self.throttle {
+                throttle.wait(uids.len(), 0).await?;
+            }
+            let responses = self
+                .session
+                .uid_store(uid_set(uids), r"+FLAGS.SILENT (\Deleted)")
+                .await?;
+            let _: Vec<_> = responses.try_collect().await?;
+
+            if self.uidplus {
+                let responses = self.session.uid_expunge(uid_set(uids)).await?;
+                let _: Vec<_> = responses.try_collect().await?;
+            }
+        }
+
+        if self.uidplus || uids.is_empty() {
+            Ok(uids.len())
+        } else {
+            log::warn!(
+                "Server doesn't support UIDPLUS, messages {} are marked as deleted but not expunged",
+                uid_set(uids)
+            );
+            Ok(0)
+        }
+    }
+}
+
+/// Change of a synced message, decided from the flags both copies had after
+/// the last run
+#[derive(Debug, PartialEq, Eq)]
+enum Resolution {
+    Unchanged,
+    UpdateTarget {
+        current: BTreeSet<String>,
+        flags: BTreeSet<String>,
+    },
+    UpdateSource {
+        current: BTreeSet<String>,
+        flags: BTreeSet<String>,
+    },
+    ExpungeTarget,
+    ExpungeSource,
+    /// The pair is dropped from the state, both copies are left alone
+    Forget,
+}
+
+/// Decides how to sync a message. A copy is `None` when it was expunged,
+/// `Some(None)` when its flags are unchanged since the last run.
+fn resolve(
+    last_flags: &BTreeSet<String>,
+    source_copy: Option<Option<&BTreeSet<String>>>,
+    target_copy: Option<Option<&BTreeSet<String>>>,
+    conflict_winner: Option<ConflictWinner>,
+) -> Resolution {
+    let bidirectional = conflict_winner.is_some();
+    let prefer_source = conflict_winner != Some(ConflictWinner::Target);
+    let changed = |copy: Option<Option<&BTreeSet<String>>>| {
+        copy.flatten().is_some_and(|flags| flags != last_flags)
+    };
+    let source_changed = changed(source_copy);
+    let target_changed = changed(target_copy);
+
+    match (source_copy, target_copy) {
+        (Some(source_flags), Some(target_flags)) => {
+            let source_flags = source_flags.unwrap_or(last_flags).clone();
+            let target_flags = target_flags.unwrap_or(last_flags).clone();
+            if source_changed && (!target_changed || prefer_source) {
+                Resolution::UpdateTarget {
+                    current: target_flags,
+                    flags: source_flags,
+                }
+            } else if bidirectional && target_changed {
+                Resolution::UpdateSource {
+                    current: source_flags,
+                    flags: target_flags,
+                }
+            } else {
+                Resolution::Unchanged
+            }
+        }
+        // Expunged on the source
+        (None, Some(_)) => {
+            if bidirectional && target_changed && !prefer_source {
+                Resolution::Forget
+            } else {
+                Resolution::ExpungeTarget
+            }
+        }
+        // Expunged on the target, one-way runs leave the source alone
+        (Some(_), None) => {
+            if bidirectional && !(source_changed && prefer_source) {
+                Resolution::ExpungeSource
+            } else {
+                Resolution::Forget
+            }
+        }
+        (None, None) => Resolution::Forget,
+    }
+}
+
+/// Keeps flags worth syncing: `\Deleted` turns into an expunge, `\Recent`
+/// belongs to the session.
+pub(super) fn flag_names<'a>(flags: impl Iterator<Item = Flag<'a>>) -> BTreeSet<String> {
+    flags
+        .filter_map(|flag| match flag {
+            Flag::Seen => Some(r"\Seen".to_string()),
+            Flag::Answered => Some(r"\Answered".to_string()),
+            Flag::Flagged => Some(r"\Flagged".to_string()),
+            Flag::Draft => Some(r"\Draft".to_string()),
+            Flag::Custom(keyword) if !keyword.starts_with('\\') => Some(keyword.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Builds a compact UID set like `1:3,7`.
+pub(super) fn uid_set(uids: &[u32]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for uid in uids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *uid => *end = *uid,
+            _ => ranges.push((*uid, *uid)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}:{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn load_state(state_file_path: &Path) -> anyhow::Result<SyncState> {
+    if !state_file_path.exists() {
+        return Ok(SyncState::default());
+    }
+
+    let data = fs::read(state_file_path).context(format!(
+        "unable to read sync state {}",
+        state_file_path.display()
+    ))?;
+    serde_json::from_slice(&data).context(format!(
+        "malformed sync state {}",
+        state_file_path.display()
+    ))
+}
+
+fn save_state(state_file_path: &Path, state: &SyncState) -> anyhow::Result<()> {
+    fs::write(state_file_path, serde_json::to_vec(state)?).context(format!(
+        "unable to save sync state {}",
+        state_file_path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn flags(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn uid_set_merges_consecutive_uids() {
+        assert_eq!(uid_set(&[]), "");
+        assert_eq!(uid_set(&[4]), "4");
+        assert_eq!(uid_set(&[1, 2, 3, 7]), "1:3,7");
+        assert_eq!(uid_set(&[1, 3, 4, 5, 9, 10]), "1,3:5,9:10");
+    }
+
+    #[test]
+    fn flag_names_skip_session_flags() {
+        let names = flag_names(
+            [
+                Flag::Seen,
+                Flag::Answered,
+                Flag::Flagged,
+                Flag::Draft,
+                Flag::Deleted,
+                Flag::Recent,
+                Flag::MayCreate,
+                Flag::Custom(Cow::Borrowed("$Forwarded")),
+                Flag::Custom(Cow::Borrowed(r"\Unknown")),
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(
+            names,
+            flags(&[r"\Seen", r"\Answered", r"\Flagged", r"\Draft", "$Forwarded"])
+        );
+    }
+
+    #[test]
+    fn resolve_copies_source_changes() {
+        let last = flags(&[]);
+        let seen = flags(&[r"\Seen"]);
+
+        assert_eq!(
+            resolve(&last, Some(Some(&seen)), Some(None), None),
+            Resolution::UpdateTarget {
+                current: last.clone(),
+                flags: seen.clone()
+            }
+        );
+        assert_eq!(
+            resolve(&last, Some(None), Some(None), None),
+            Resolution::Unchanged
+        );
+        assert_eq!(
+            resolve(&last, Some(Some(&last)), Some(None), None),
+            Resolution::Unchanged
+        );
+    }
+
+    #[test]
+    fn resolve_one_way_ignores_target_changes() {
+        let last = flags(&[]);
+        let flagged = flags(&[r"\Flagged"]);
+
+        assert_eq!(
+            resolve(&last, Some(None), Some(Some(&flagged)), None),
+            Resolution::Unchanged
+        );
+        assert_eq!(resolve(&last, Some(None), None, None), Resolution::Forget);
+        assert_eq!(
+            resolve(&last, None, Some(Some(&flagged)), None),
+            Resolution::ExpungeTarget
+        );
+    }
+
+    #[test]
+    fn resolve_bidirectional_conflicts() {
+        let last = flags(&[]);
+        let seen = flags(&[r"\Seen"]);
+        let flagged = flags(&[r"\Flagged"]);
+        let source = Some(ConflictWinner::Source);
+        let target = Some(ConflictWinner::Target);
+
+        assert_eq!(
+            resolve(&last, Some(None), Some(Some(&flagged)), source),
+            Resolution::UpdateSource {
+                current: last.clone(),
+                flags: flagged.clone()
+            }
+        );
+        assert_eq!(
+            resolve(&last, Some(Some(&seen)), Some(Some(&flagged)), source),
+            Resolution::UpdateTarget {
+                current: flagged.clone(),
+                flags: seen.clone()
+            }
+        );
+        assert_eq!(
+            resolve(&last, Some(Some(&seen)), Some(Some(&flagged)), target),
+            Resolution::UpdateSource {
+                current: seen.clone(),
+                flags: flagged.clone()
+            }
+        );
+    }
+
+    #[test]
+    fn resolve_bidirectional_expunges() {
+        let last = flags(&[]);
+        let seen = flags(&[r"\Seen"]);
+        let source = Some(ConflictWinner::Source);
+        let target = Some(ConflictWinner::Target);
+
+        assert_eq!(
+            resolve(&last, Some(None), None, source),
+            Resolution::ExpungeSource
+        );
+        // A change wins over an expunge on the other side
+        assert_eq!(
+            resolve(&last, Some(Some(&seen)), None, source),
+            Resolution::Forget
+        );
+        assert_eq!(
+            resolve(&last, Some(Some(&seen)), None, target),
+            Resolution::ExpungeSource
+        );
+        assert_eq!(
+            resolve(&last, None, Some(Some(&seen)), target),
+            Resolution::Forget
+        );
+        assert_eq!(
+            resolve(&last, None, Some(Some(&seen)), source),
+            Resolution::ExpungeTarget
+        );
+        assert_eq!(resolve(&last, None, None, source), Resolution::Forget);
+    }
+}
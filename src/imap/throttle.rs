@@ -0,0 +1,241 @@
+//! `rate_limit` of an IMAP server. Rates are shared by all connections of the
+//! process to the server, the bytes of the day are kept in a locked file next
+//! to the accounts so parallel and later runs add up.
+
+use anyhow::Context;
+use human_bytes::human_bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::config::ImapServerConfig;
+
+pub const USAGE_FILE_NAME: &str = ".imap-usage";
+
+static THROTTLES: OnceLock<Mutex<HashMap<String, Arc<Throttle>>>> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct Throttle {
+    server: String,
+    message_interval: Option<Duration>,
+    bytes_per_second: Option<u64>,
+    daily_bytes: Option<u64>,
+    usage_path: PathBuf,
+    schedule: tokio::sync::Mutex<Schedule>,
+}
+
+/// Earliest start of the next transfer by each rate.
+#[derive(Debug)]
+struct Schedule {
+    next_message: Instant,
+    next_bytes: Instant,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DailyUsage {
+    date: String,
+    bytes: u64,
+}
+
+impl Throttle {
+    /// Returns the throttle of the server, `None` when it has no `rate_limit`.
+    /// The daily usage is kept in `dir`.
+    pub fn for_server(
+        imap_config: &ImapServerConfig,
+        dir: &Path,
+    ) -> anyhow::Result<Option<Arc<Throttle>>> {
+        let Some(ref rate_limit) = imap_config.rate_limit else {
+            return Ok(None);
+        };
+        let server = format!("{}:{}", imap_config.server, imap_config.port.unwrap_or(993));
+
+        let mut throttles = THROTTLES
+            .get_or_init(Default::default)
+            .lock()
+            .expect("throttles are not poisoned");
+        if let Some(throttle) = throttles.get(&server) {
+            return Ok(Some(throttle.clone()));
+        }
+
+        let message_interval = match rate_limit.messages_per_second {
+            Some(messages_per_second) if messages_per_second <= 0.0 => {
+                anyhow::bail!("messages_per_second of {server} must be positive")
+            }
+            Some(messages_per_second) => Some(Duration::from_secs_f64(1.0 / messages_per_second)),
+            None => None,
+        };
+        let bytes_per_second = rate_limit.bytes_per_second.map(|size| size.0);
+        if bytes_per_second == Some(0) {
+            anyhow::bail!("bytes_per_second of {server} must be positive");
+        }
+        let daily_bytes = rate_limit.daily_bytes.map(|size| size.0);
+
+        log::info!(
+            "Rate limit of {}: {} messages/s, {}/s, {} a day",
+            server,
+            rate_limit
+                .messages_per_second
+                .map(|rate| rate.to_string())
+                .unwrap_or_else(|| "unlimited".to_string()),
+            bytes_per_second
+                .map(|rate| human_bytes(rate as f64))
+                .unwrap_or_else(|| "unlimited".to_string()),
+            daily_bytes
+                .map(|limit| human_bytes(limit as f64))
+                .unwrap_or_else(|| "unlimited".to_string()),
+        );
+
+        let throttle = Arc::new(Throttle {
+            server: server.clone(),
+            message_interval,
+            bytes_per_second,
+            daily_bytes,
+            usage_path: dir.join(USAGE_FILE_NAME),
+            schedule: tokio::sync::Mutex::new(Schedule {
+                next_message: Instant::now(),
+                next_bytes: Instant::now(),
+            }),
+        });
+        throttles.insert(server, throttle.clone());
+
+        Ok(Some(throttle))
+    }
+
+    /// Daily limits are reset at midnight UTC.
+    pub fn warn_daily_limit(&self) {
+        log::warn!(
+            "Daily limit of {} for {} reached, run again after midnight UTC to resume",
+            human_bytes(self.daily_bytes.unwrap_or_default() as f64),
+            self.server
+        );
+    }
+
+    /// Bytes left of the daily limit, `None` without one.
+    pub fn remaining_today(&self) -> anyhow::Result<Option<u64>> {
+        let Some(daily_bytes) = self.daily_bytes else {
+            return Ok(None);
+        };
+        let used = self
+            .load_usage()?
+            .get(&self.server)
+            .filter(|usage| usage.date == today())
+            .map(|usage| usage.bytes)
+            .unwrap_or_default();
+
+        Ok(Some(daily_bytes.saturating_sub(used)))
+    }
+
+    /// Counts a transfer of `messages` with `bytes` in total towards the daily
+    /// limit and waits until the rates allow it.
+    pub async fn wait(&self, messages: usize, bytes: usize) -> anyhow::Result<()> {
+        if self.daily_bytes.is_some() {
+            self.record(bytes as u64)?;
+        }
+
+        let ready_at = {
+            let mut schedule = self.schedule.lock().await;
+            let now = Instant::now();
+            let mut ready_at = now;
+            if let Some(message_interval) = self.message_interval {
+                ready_at = ready_at.max(schedule.next_message);
+                schedule.next_message =
+                    schedule.next_message.max(now) + message_interval * messages as u32;
+            }
+            if let Some(bytes_per_second) = self.bytes_per_second {
+                ready_at = ready_at.max(schedule.next_bytes);
+                schedule.next_bytes = schedule.next_bytes.max(now)
+                    + Duration::from_secs_f64(bytes as f64 / bytes_per_second as f64);
+            }
+            ready_at
+        };
+        tokio::time::sleep_until(ready_at).await;
+
+        Ok(())
+    }
+
+    /// Adds to the usage stored by this and other processes, the file stays
+    /// locked from reading to writing.
+    fn record(&self, bytes: u64) -> anyhow::Result<()> {
+        if let Some(dir) = self.usage_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.usage_path)
+            .context(format!(
+                "unable to open usage {}",
+                self.usage_path.display()
+            ))?;
+        file.lock().context(format!(
+            "unable to lock usage {}",
+            self.usage_path.display()
+        ))?;
+
+        let mut usages = self.read_usage(&mut file)?;
+        let today = today();
+        let usage = usages
+            .entry(self.server.clone())
+            .or_insert_with(|| DailyUsage {
+                date: today.clone(),
+                bytes: 0,
+            });
+        if usage.date != today {
+            usage.date = today;
+            usage.bytes = 0;
+        }
+        usage.bytes += bytes;
+
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(&serde_json::to_vec_pretty(&usages)?))
+            .context(format!(
+                "unable to save usage {}",
+                self.usage_path.display()
+            ))
+    }
+
+    fn load_usage(&self) -> anyhow::Result<BTreeMap<String, DailyUsage>> {
+        if !self.usage_path.exists() {
+            return Ok(BTreeMap::new());
+        }
+
+        let mut file = File::open(&self.usage_path).context(format!(
+            "unable to read usage {}",
+            self.usage_path.display()
+        ))?;
+        file.lock_shared().context(format!(
+            "unable to lock usage {}",
+            self.usage_path.display()
+        ))?;
+        self.read_usage(&mut file)
+    }
+
+    /// A file created by a run that hasn't written it yet is empty.
+    fn read_usage(&self, file: &mut File) -> anyhow::Result<BTreeMap<String, DailyUsage>> {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).context(format!(
+            "unable to read usage {}",
+            self.usage_path.display()
+        ))?;
+        if data.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        serde_json::from_slice(&data)
+            .context(format!("malformed usage {}", self.usage_path.display()))
+    }
+}
+
+fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
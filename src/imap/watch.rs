@@ -0,0 +1,490 @@
+use anyhow::Context;
+use async_imap::{extensions::idle::IdleResponse, types::Mailbox};
+use futures_lite::stream::StreamExt;
+use human_bytes::human_bytes;
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::{task::JoinSet, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    connect,
+    pull::{load_pull_state, save_pull_state, PullState, StoredMessage},
+    push::{map_mailbox_name, stored_flags},
+    sync::{flag_names, uid_set},
+    throttle::Throttle,
+    ImapSession,
+};
+use crate::{
+    archive::absolute_path,
+    config::{Config, ImapServerConfig},
+    storage::last_message_id,
+};
+
+/// Servers may log out clients idling for 30 minutes (RFC 2177)
+const IDLE_TIMEOUT: Duration = Duration::from_secs(29 * 60);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+const BATCH_SIZE: usize = 200;
+
+pub async fn watch(
+    config: &Config,
+    email: String,
+    password: Option<String>,
+    out_dir: String,
+    mut mailboxes: Vec<String>,
+    poll_interval: u64,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let watch_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let imap_config = config
+        .imap
+        .clone()
+        .context("IMAP config is not provided in config.yaml")?;
+    let pull_config = imap_config
+        .pull
+        .context("IMAP pull server config not provided")?;
+    let push_config = imap_config
+        .push
+        .context("IMAP push server config not provided")?;
+
+    mailboxes.retain(|mailbox_name| {
+        let dropped = push_config.map_folder_name(mailbox_name).is_none();
+        if dropped {
+            log::warn!("Not watching {mailbox_name}, dropped by folder_rules");
+        }
+        !dropped
+    });
+    log::info!("Watching {} mailboxes", mailboxes.len());
+
+    let pull_throttle = Throttle::for_server(&pull_config, &absolute_path(&out_dir))?;
+    let push_throttle = Throttle::for_server(&push_config, &absolute_path(&out_dir))?;
+
+    // IDLE reports changes of the selected mailbox only, so every mailbox
+    // gets its own pair of sessions
+    let mut watchers = JoinSet::new();
+    for mailbox_name in mailboxes {
+        let folder_name = format!("{out_dir}/{domain}/{email}/{mailbox_name}");
+        let folder_path = if folder_name.clone().starts_with("/") {
+            PathBuf::from_str("/").unwrap().join(folder_name.clone())
+        } else {
+            current_dir().unwrap().join(folder_name.clone())
+        };
+
+        let watcher = MailboxWatcher {
+            pull_config: pull_config.clone(),
+            push_config: push_config.clone(),
+            pull_throttle: pull_throttle.clone(),
+            push_throttle: push_throttle.clone(),
+            email: email.clone(),
+            password: password.clone(),
+            mailbox_name,
+            folder_path,
+            poll_interval: Duration::from_secs(poll_interval),
+            cancellation_token: watch_cancellation_token.clone(),
+        };
+        watchers.spawn(watcher.run());
+    }
+
+    let mut total_forwarded_count = 0;
+    while let Some(forwarded_count) = watchers.join_next().await {
+        total_forwarded_count += forwarded_count?;
+    }
+
+    log::info!(
+        "Done in {:?}, {} new messages forwarded ok.",
+        start.elapsed(),
+        total_forwarded_count
+    );
+
+    Ok(())
+}
+
+struct MailboxWatcher {
+    pull_config: ImapServerConfig,
+    push_config: ImapServerConfig,
+    pull_throttle: Option<Arc<Throttle>>,
+    push_throttle: Option<Arc<Throttle>>,
+    email: String,
+    password: Option<String>,
+    mailbox_name: String,
+    folder_path: PathBuf,
+    poll_interval: Duration,
+    cancellation_token: CancellationToken,
+}
+
+impl MailboxWatcher {
+    /// Watches the mailbox until cancelled, reconnecting with a growing delay
+    /// after failures. Returns the number of forwarded messages.
+    async fn run(self) -> usize {
+        let mut forwarded_count = 0;
+        let mut reconnect_delay = MIN_RECONNECT_DELAY;
+
+        while !self.cancellation_token.is_cancelled() {
+            match self.watch(&mut forwarded_count, &mut reconnect_delay).await {
+                Ok(_) => break,
+                Err(err) => {
+                    log::warn!(
+                        "Watching {} failed: {}, reconnecting in {:?}",
+                        self.mailbox_name,
+                        err,
+                        reconnect_delay
+                    );
+                    tokio::select! {
+                        _ = self.cancellation_token.cancelled() => break,
+                        _ = tokio::time::sleep(reconnect_delay) => {}
+                    }
+                    reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+
+        log::info!(
+            "Forwarded {} messages from {}",
+            forwarded_count,
+            self.mailbox_name
+        );
+
+        forwarded_count
+    }
+
+    async fn watch(
+        &self,
+        forwarded_count: &mut usize,
+        reconnect_delay: &mut Duration,
+    ) -> anyhow::Result<()> {
+        let mailbox_utf7_name = utf7_imap::encode_utf7_imap(self.mailbox_name.clone());
+        let folder_delimiter = &self.push_config.folder_delimiter.unwrap_or('/').to_string();
+        let (target_mapped_name, target_utf7_name) =
+            map_mailbox_name(&self.push_config, &self.mailbox_name, folder_delimiter)
+                .context(format!("mailbox {} is dropped", self.mailbox_name))?;
+
+        let mut source_session =
+            connect(&self.pull_config, &self.email, self.password.as_deref()).await?;
+        let idle_supported = source_session
+            .capabilities()
+            .await
+            .context("error getting capabilities")?
+            .has_str("IDLE");
+
+        let mut target_session =
+            connect(&self.push_config, &self.email, self.password.as_deref()).await?;
+        if let Some(err) = target_session.create(&target_utf7_name).await.err() {
+            log::debug!("Unable to create folder: {}", err);
+        }
+
+        if idle_supported {
+            log::info!(
+                "Watching {} with IDLE, forwarding to {}",
+                self.mailbox_name,
+                target_mapped_name
+            );
+        } else {
+            log::info!(
+                "Server doesn't support IDLE, polling {} every {:?}, forwarding to {}",
+                self.mailbox_name,
+                self.poll_interval,
+                target_mapped_name
+            );
+        }
+        *reconnect_delay = MIN_RECONNECT_DELAY;
+
+        while !self.cancellation_token.is_cancelled() {
+            // Read-only, so watching doesn't mark messages on the source as seen
+            let mailbox = source_session
+                .examine(&mailbox_utf7_name)
+                .await
+                .context(format!("unable to examine {}", self.mailbox_name))?;
+            // Changes are re-read with EXAMINE, unread notifications would
+            // eventually block the session
+            while source_session.unsolicited_responses.try_recv().is_ok() {}
+
+            self.store_new_messages(&mut source_session, &mailbox)
+                .await?;
+            *forwarded_count += self
+                .forward_pending(&mut target_session, &target_utf7_name)
+                .await?;
+
+            if idle_supported {
+                let mut idle = source_session.idle();
+                idle.init().await?;
+                let response = {
+                    let (wait, _interrupt) = idle.wait_with_timeout(IDLE_TIMEOUT);
+                    tokio::select! {
+                        response = wait => Some(response?),
+                        _ = self.cancellation_token.cancelled() => None,
+                    }
+                };
+                source_session = idle.done().await?;
+
+                match response {
+                    Some(IdleResponse::NewData(_)) => {
+                        log::debug!("{} changed", self.mailbox_name)
+                    }
+                    Some(_) => log::debug!("Restarting IDLE on {}", self.mailbox_name),
+                    None => break,
+                }
+            } else {
+                tokio::select! {
+                    _ = self.cancellation_token.cancelled() => break,
+                    _ = tokio::time::sleep(self.poll_interval) => {}
+                }
+            }
+        }
+
+        source_session.logout().await?;
+        target_session.logout().await?;
+
+        Ok(())
+    }
+
+    /// Stores messages with UIDs above the last stored one as pending `.eml`
+    /// files, numbered and recorded in the pull state the same way `imap pull`
+    /// does. UIDs stay valid when messages are expunged on the source.
+    async fn store_new_messages(
+        &self,
+        source_session: &mut ImapSession,
+        mailbox: &Mailbox,
+    ) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.folder_path)?;
+
+        let mut pull_state = load_pull_state(&self.folder_path)?.unwrap_or_default();
+        if pull_state.uid_validity.is_some() && pull_state.uid_validity != mailbox.uid_validity {
+            log::warn!(
+                "UIDVALIDITY of {} changed, forwarding only messages arriving from now on",
+                self.mailbox_name
+            );
+            pull_state = PullState {
+                uid_validity: mailbox.uid_validity,
+                last_uid: mailbox.uid_next.map(|uid_next| uid_next.saturating_sub(1)),
+                ..Default::default()
+            };
+            save_pull_state(&self.folder_path, &pull_state)?;
+        }
+        pull_state.uid_validity = mailbox.uid_validity;
+        if pull_state.last_uid() == 0 {
+            self.record_last_stored_message(source_session, mailbox, &mut pull_state)
+                .await?;
+            save_pull_state(&self.folder_path, &pull_state)?;
+        }
+        let last_uid = pull_state.last_uid();
+
+        // `n:*` always includes the last message, even when its UID is lower
+        let mut uids: Vec<_> = source_session
+            .uid_search(format!("UID {}:*", last_uid + 1))
+            .await
+            .context("error searching new messages")?
+            .into_iter()
+            .filter(|uid| *uid > last_uid)
+            .collect();
+        uids.sort();
+
+        let mut message_id = last_message_id(&self.folder_path)?;
+        for uids in uids.chunks(BATCH_SIZE) {
+            if self.cancellation_token.is_cancelled() {
+                break;
+            }
+            let mut uids = uids.to_vec();
+            let mut paused = false;
+            if let Some(ref throttle) = self.pull_throttle {
+                let (fitting_uids, size, limit_reached) =
+                    within_daily_limit(source_session, throttle, &uids).await?;
+                paused = limit_reached;
+                if fitting_uids.is_empty() {
+                    if paused {
+                        throttle.warn_daily_limit();
+                        break;
+                    }
+                    continue;
+                }
+                throttle.wait(fitting_uids.len(), size).await?;
+                uids = fitting_uids;
+            }
+            log::info!("{} new messages in {}", uids.len(), self.mailbox_name);
+
+            let messages_stream = source_session
+                .uid_fetch(uid_set(&uids), "(UID FLAGS BODY.PEEK[])")
+                .await
+                .context("error getting messages")?;
+            let mut messages: Vec<_> = messages_stream.try_collect().await?;
+            messages.sort_by_key(|message| message.uid);
+
+            for message in messages {
+                let uid = message.uid.context("message did not have a UID!")?;
+                let body = message.body().context("message did not have a body!")?;
+                message_id += 1;
+                let file_name = format!("{:0>8}.eml", message_id);
+                fs::write(self.folder_path.join(format!(".{file_name}")), body)
+                    .context("unable to save *.eml file")?;
+                pull_state.messages.insert(
+                    uid,
+                    StoredMessage {
+                        file_name,
+                        flags: flag_names(message.flags()),
+                    },
+                );
+
+                log::debug!("{} bytes eml message added", body.len());
+            }
+            save_pull_state(&self.folder_path, &pull_state)?;
+
+            if paused {
+                if let Some(ref throttle) = self.pull_throttle {
+                    throttle.warn_daily_limit();
+                }
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Folders stored without UIDs were numbered by sequence number, the UID of
+    /// the last stored message is recorded once so later expunges can't shift
+    /// it.
+    async fn record_last_stored_message(
+        &self,
+        source_session: &mut ImapSession,
+        mailbox: &Mailbox,
+        pull_state: &mut PullState,
+    ) -> anyhow::Result<()> {
+        let message_id = last_message_id(&self.folder_path)?.min(mailbox.exists as usize);
+        if message_id == 0 {
+            return Ok(());
+        }
+
+        let messages_stream = source_session
+            .fetch(message_id.to_string(), "UID")
+            .await
+            .context("error getting UID of the last stored message")?;
+        let messages: Vec<_> = messages_stream.try_collect().await?;
+        pull_state.last_uid = messages.first().and_then(|message| message.uid);
+
+        Ok(())
+    }
+
+    /// Uploads pending messages of the folder and marks them pushed. Messages
+    /// rejected by the server stay pending.
+    async fn forward_pending(
+        &self,
+        target_session: &mut ImapSession,
+        target_mailbox: &str,
+    ) -> anyhow::Result<usize> {
+        let mut eml_files = Vec::new();
+        for entry in fs::read_dir(&self.folder_path)? {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.extension() == Some("eml".as_ref()) && file_name.starts_with('.') {
+                eml_files.push(path);
+            }
+        }
+        eml_files.sort();
+
+        // Messages stored before flags were recorded are uploaded as seen
+        let message_flags = stored_flags(&self.folder_path)?;
+        let mut forwarded_count = 0;
+
+        for eml_file_path in eml_files {
+            if self.cancellation_token.is_cancelled() {
+                break;
+            }
+
+            let eml_file_name = eml_file_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let new_eml_file_name = eml_file_name.trim_start_matches('.');
+
+            let data = fs::read(&eml_file_path)?;
+            if let Some(ref throttle) = self.push_throttle {
+                if throttle
+                    .remaining_today()?
+                    .is_some_and(|remaining| data.len() as u64 > remaining)
+                {
+                    throttle.warn_daily_limit();
+                    break;
+                }
+                throttle.wait(1, data.len()).await?;
+            }
+            let flags = message_flags
+                .get(new_eml_file_name)
+                .map(String::as_str)
+                .unwrap_or(r"(\Seen)");
+            match target_session
+                .append(target_mailbox, Some(flags), None, &data)
+                .await
+            {
+                Ok(_) => {
+                    fs::rename(
+                        &eml_file_path,
+                        eml_file_path.with_file_name(new_eml_file_name),
+                    )?;
+                    forwarded_count += 1;
+
+                    log::info!(
+                        "Forwarded message {} ({}) from {}",
+                        new_eml_file_name,
+                        human_bytes(data.len() as f64),
+                        self.mailbox_name
+                    );
+                }
+                Err(err @ (async_imap::error::Error::No(_) | async_imap::error::Error::Bad(_))) => {
+                    log::warn!("Error forwarding message {}: {}", new_eml_file_name, err)
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(forwarded_count)
+    }
+}
+
+/// The first of `uids` whose messages fit in what is left of the daily limit,
+/// with their total size and whether the limit left out the others.
+async fn within_daily_limit(
+    source_session: &mut ImapSession,
+    throttle: &Throttle,
+    uids: &[u32],
+) -> anyhow::Result<(Vec<u32>, usize, bool)> {
+    let daily_remaining = throttle.remaining_today()?;
+    let messages_stream = source_session
+        .uid_fetch(uid_set(uids), "(UID RFC822.SIZE)")
+        .await
+        .context("error getting message sizes")?;
+    let mut sizes: Vec<_> = messages_stream
+        .map(|message| message.map(|message| (message.uid, message.size)))
+        .try_collect()
+        .await?;
+    sizes.sort();
+
+    let mut fitting_uids = Vec::new();
+    let mut total_size = 0;
+    for (uid, size) in sizes {
+        let Some(uid) = uid else {
+            continue;
+        };
+        let size = size.unwrap_or_default() as usize;
+        if daily_remaining.is_some_and(|remaining| (total_size + size) as u64 > remaining) {
+            return Ok((fitting_uids, total_size, true));
+        }
+        total_size += size;
+        fitting_uids.push(uid);
+    }
+
+    Ok((fitting_uids, total_size, false))
+}
@@ -0,0 +1,427 @@
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::config::{JmapAuth, JmapServerConfig};
+
+pub mod pull;
+pub mod push;
+
+const JMAP_CORE: &str = "urn:ietf:params:jmap:core";
+const JMAP_MAIL: &str = "urn:ietf:params:jmap:mail";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapSession {
+    pub api_url: String,
+    pub download_url: String,
+    pub upload_url: String,
+    pub primary_accounts: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapMailbox {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub role: Option<String>,
+}
+
+/// Minimal JMAP client (RFC 8620, RFC 8621) covering mailbox listing, blob
+/// transfer and email import.
+pub struct JmapClient {
+    http: reqwest::Client,
+    email: String,
+    password: String,
+    auth: JmapAuth,
+    pub session: JmapSession,
+    pub account_id: String,
+}
+
+impl JmapClient {
+    pub async fn connect(
+        config: &JmapServerConfig,
+        email: &str,
+        password: &str,
+    ) -> anyhow::Result<JmapClient> {
+        let http = reqwest::Client::new();
+        let auth = config.auth.clone().unwrap_or_default();
+
+        let request = http.get(&config.url);
+        let request = match auth {
+            JmapAuth::Basic => request.basic_auth(email, Some(password)),
+            JmapAuth::Bearer => request.bearer_auth(password),
+        };
+        let session: JmapSession = request
+            .send()
+            .await
+            .context("unable to connect to JMAP server")?
+            .error_for_status()
+            .context("unable to get JMAP session")?
+            .json()
+            .await
+            .context("malformed JMAP session")?;
+
+        let account_id = session
+            .primary_accounts
+            .get(JMAP_MAIL)
+            .context("JMAP server has no mail account")?
+            .clone();
+
+        Ok(JmapClient {
+            http,
+            email: email.to_string(),
+            password: password.to_string(),
+            auth,
+            session,
+            account_id,
+        })
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.auth {
+            JmapAuth::Basic => request.basic_auth(&self.email, Some(&self.password)),
+            JmapAuth::Bearer => request.bearer_auth(&self.password),
+        }
+    }
+
+    /// Performs a single method call and returns its response arguments.
+    pub async fn call(&self, method: &str, mut arguments: Value) -> anyhow::Result<Value> {
+        arguments["accountId"] = json!(self.account_id);
+        let body = json!({
+            "using": [JMAP_CORE, JMAP_MAIL],
+            "methodCalls": [[method, arguments, "0"]],
+        });
+
+        let response: Value = self
+            .authorize(self.http.post(&self.session.api_url))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()
+            .context(format!("{method} request failed"))?
+            .json()
+            .await
+            .context(format!("malformed {method} response"))?;
+
+        let method_response = response["methodResponses"]
+            .get(0)
+            .context(format!("empty {method} response"))?;
+        if method_response[0] == "error" {
+            anyhow::bail!(
+                "{method} failed: {}",
+                method_response[1]["type"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+            );
+        }
+
+        Ok(method_response[1].clone())
+    }
+
+    pub async fn mailboxes(&self) -> anyhow::Result<Vec<JmapMailbox>> {
+        let response = self
+            .call(
+                "Mailbox/get",
+                json!({ "ids": null, "properties": ["id", "name", "parentId", "role"] }),
+            )
+            .await?;
+        let mailboxes: Vec<JmapMailbox> =
+            serde_json::from_value(response["list"].clone()).context("malformed mailbox list")?;
+        Ok(mailboxes)
+    }
+
+    pub async fn download(&self, blob_id: &str) -> anyhow::Result<Vec<u8>> {
+        let url = self
+            .session
+            .download_url
+            .replace("{accountId}", &self.account_id)
+            .replace("{blobId}", blob_id)
+            .replace("{type}", "message%2Frfc822")
+            .replace("{name}", "message.eml");
+
+        let data = self
+            .authorize(self.http.get(url))
+            .send()
+            .await?
+            .error_for_status()
+            .context(format!("unable to download blob {blob_id}"))?
+            .bytes()
+            .await?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Uploads data and returns its blob id.
+    pub async fn upload(&self, data: Vec<u8>) -> anyhow::Result<String> {
+        let url = self
+            .session
+            .upload_url
+            .replace("{accountId}", &self.account_id);
+
+        let response: Value = self
+            .authorize(self.http.post(url))
+            .header(reqwest::header::CONTENT_TYPE, "message/rfc822")
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()
+            .context("unable to upload blob")?
+            .json()
+            .await
+            .context("malformed upload response")?;
+
+        Ok(response["blobId"]
+            .as_str()
+            .context("upload response has no blobId")?
+            .to_string())
+    }
+}
+
+/// Builds `/`-delimited folder paths matching the local layout. The mailbox
+/// with the `inbox` role is always named `INBOX` as in IMAP.
+pub fn mailbox_paths(mailboxes: &[JmapMailbox]) -> HashMap<String, String> {
+    let by_id: HashMap<&str, &JmapMailbox> = mailboxes
+        .iter()
+        .map(|mailbox| (mailbox.id.as_str(), mailbox))
+        .collect();
+
+    let mut paths = HashMap::new();
+
+    for mailbox in mailboxes {
+        let mut names = Vec::new();
+        let mut current = Some(mailbox);
+        while let Some(m) = current {
+            if m.role.as_deref() == Some("inbox") {
+                names.push("INBOX".to_string());
+            } else {
+                names.push(m.name.clone());
+            }
+            current = m
+                .parent_id
+                .as_deref()
+                .and_then(|parent_id| by_id.get(parent_id).copied());
+            if names.len() > mailboxes.len() {
+                break;
+            }
+        }
+        names.reverse();
+        paths.insert(mailbox.id.clone(), names.join("/"));
+    }
+
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    type Handler = dyn Fn(&str, &Value) -> Value + Send + Sync;
+
+    /// JMAP server on a local port answering method calls with `handler`,
+    /// results with a `type` are sent as method errors. Uploaded blobs get the
+    /// id `blob-{content}`, downloading a blob returns its id.
+    pub(super) struct MockServer {
+        pub url: String,
+        /// Method calls received with their arguments
+        pub calls: Arc<Mutex<Vec<(String, Value)>>>,
+    }
+
+    impl MockServer {
+        pub async fn start(
+            handler: impl Fn(&str, &Value) -> Value + Send + Sync + 'static,
+        ) -> MockServer {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let handler: Arc<Handler> = Arc::new(handler);
+
+            let server_url = base_url.clone();
+            let server_calls = calls.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let base_url = server_url.clone();
+                    let calls = server_calls.clone();
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        serve(stream, &base_url, &calls, handler.as_ref()).await;
+                    });
+                }
+            });
+
+            MockServer {
+                url: format!("{base_url}/session"),
+                calls,
+            }
+        }
+
+        /// Arguments of the calls of the given method.
+        pub fn calls(&self, method: &str) -> Vec<Value> {
+            self.calls
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, _)| name == method)
+                .map(|(_, arguments)| arguments.clone())
+                .collect()
+        }
+
+        pub fn config(&self) -> JmapServerConfig {
+            JmapServerConfig {
+                url: self.url.clone(),
+                auth: None,
+                folder_name_mappings: None,
+                folder_rules: None,
+            }
+        }
+    }
+
+    async fn serve(
+        stream: tokio::net::TcpStream,
+        base_url: &str,
+        calls: &Mutex<Vec<(String, Value)>>,
+        handler: &Handler,
+    ) {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await.unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.unwrap();
+
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let response = if path == "/session" {
+            json!({
+                "apiUrl": format!("{base_url}/api"),
+                "downloadUrl": format!("{base_url}/download/{{accountId}}/{{blobId}}/{{name}}?type={{type}}"),
+                "uploadUrl": format!("{base_url}/upload/{{accountId}}/"),
+                "primaryAccounts": { JMAP_MAIL: "a1" },
+            })
+            .to_string()
+        } else if path == "/api" {
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            let call = &request["methodCalls"][0];
+            let method = call[0].as_str().unwrap().to_string();
+            let arguments = call[1].clone();
+            let result = handler(&method, &arguments);
+            calls.lock().unwrap().push((method.clone(), arguments));
+            let name = match result.get("type") {
+                Some(_) => "error".to_string(),
+                None => method,
+            };
+            json!({ "methodResponses": [[name, result, "0"]] }).to_string()
+        } else if path.starts_with("/upload/a1/") {
+            let blob_id = format!("blob-{}", String::from_utf8_lossy(&body).trim());
+            json!({ "blobId": blob_id }).to_string()
+        } else if let Some(rest) = path.strip_prefix("/download/a1/") {
+            rest.split('/').next().unwrap_or_default().to_string()
+        } else {
+            panic!("unexpected request {request_line}");
+        };
+
+        let stream = reader.get_mut();
+        stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        stream.shutdown().await.ok();
+    }
+
+    pub(super) fn mailbox(
+        id: &str,
+        name: &str,
+        parent_id: Option<&str>,
+        role: Option<&str>,
+    ) -> JmapMailbox {
+        JmapMailbox {
+            id: id.to_string(),
+            name: name.to_string(),
+            parent_id: parent_id.map(|parent_id| parent_id.to_string()),
+            role: role.map(|role| role.to_string()),
+        }
+    }
+
+    #[test]
+    fn mailbox_paths_follow_parents() {
+        let mailboxes = vec![
+            mailbox("1", "Posteingang", None, Some("inbox")),
+            mailbox("2", "Work", Some("1"), None),
+            mailbox("3", "Archive", None, Some("archive")),
+            mailbox("4", "2020", Some("3"), None),
+            mailbox("5", "Orphan", Some("missing"), None),
+        ];
+
+        let paths = mailbox_paths(&mailboxes);
+
+        assert_eq!(paths.len(), 5);
+        assert_eq!(paths["1"], "INBOX");
+        assert_eq!(paths["2"], "INBOX/Work");
+        assert_eq!(paths["3"], "Archive");
+        assert_eq!(paths["4"], "Archive/2020");
+        assert_eq!(paths["5"], "Orphan");
+    }
+
+    #[test]
+    fn mailbox_paths_stop_on_cycles() {
+        let mailboxes = vec![
+            mailbox("1", "A", Some("2"), None),
+            mailbox("2", "B", Some("1"), None),
+        ];
+
+        let paths = mailbox_paths(&mailboxes);
+
+        assert!(paths["1"].ends_with("B/A"));
+        assert!(paths["2"].ends_with("A/B"));
+    }
+
+    #[tokio::test]
+    async fn client_calls_methods_and_transfers_blobs() {
+        let server = MockServer::start(|method, _| match method {
+            "Mailbox/get" => json!({ "list": [{ "id": "1", "name": "Inbox", "role": "inbox" }] }),
+            _ => json!({ "type": "unknownMethod" }),
+        })
+        .await;
+        let client = JmapClient::connect(&server.config(), "a@b.c", "x")
+            .await
+            .unwrap();
+        assert_eq!(client.account_id, "a1");
+
+        let mailboxes = client.mailboxes().await.unwrap();
+        assert_eq!(mailboxes.len(), 1);
+        assert_eq!(mailboxes[0].role.as_deref(), Some("inbox"));
+        assert_eq!(mailboxes[0].parent_id, None);
+        assert_eq!(server.calls("Mailbox/get")[0]["accountId"], "a1");
+
+        let err = client.call("Email/foo", json!({})).await.unwrap_err();
+        assert_eq!(err.to_string(), "Email/foo failed: unknownMethod");
+
+        assert_eq!(client.upload(b"m1".to_vec()).await.unwrap(), "blob-m1");
+        assert_eq!(client.download("blob-m1").await.unwrap(), b"blob-m1");
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::Context;
+use serde_json::json;
+use std::{
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+};
+use tokio::time::Instant;
+
+use super::{mailbox_paths, JmapClient};
+use crate::{
+    config::Config,
+    storage::{last_message_id, load_pulled_ids},
+};
+
+/// Keeps JMAP ids of already downloaded messages, one `{id} {file_name}` per line.
+const IDS_STATE_FILE_NAME: &str = ".jmap-ids";
+
+pub async fn pull(
+    config: &Config,
+    email: String,
+    password: String,
+    out_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let pull_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pulled_count = 0;
+
+    let jmap_config = config
+        .jmap
+        .clone()
+        .context("JMAP config is not provided in config.yaml")?
+        .pull
+        .context("JMAP pull server config not provided")?;
+
+    log::debug!("Pulling JMAP for account {email}...");
+    let client = JmapClient::connect(&jmap_config, &email, &password).await?;
+    log::info!("Connected to JMAP server {} as {}", jmap_config.url, email);
+
+    let mailboxes = client.mailboxes().await?;
+    let paths = mailbox_paths(&mailboxes);
+
+    log::info!("Loaded {} mailboxes", mailboxes.len());
+
+    let batch_size = 200;
+
+    for mailbox in mailboxes {
+        if pull_cancellation_token.is_cancelled() {
+            break;
+        }
+
+        let mailbox_readable_name = &paths[&mailbox.id];
+        log::info!("Mailbox: {:?}", mailbox_readable_name);
+
+        let folder_name = format!("{out_dir}/{domain}/{email}/{mailbox_readable_name}");
+        let folder_path = if folder_name.clone().starts_with("/") {
+            PathBuf::from_str("/").unwrap().join(folder_name.clone())
+        } else {
+            current_dir().unwrap().join(folder_name.clone())
+        };
+        fs::create_dir_all(&folder_path)?;
+
+        log::info!("Folder {}", folder_path.display());
+
+        let state_file_path = folder_path.join(IDS_STATE_FILE_NAME);
+        let pulled_ids = load_pulled_ids(&state_file_path)?;
+        let mut state_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&state_file_path)
+            .context("unable to open JMAP state file")?;
+
+        let mut message_id = last_message_id(&folder_path)? + 1;
+        let mut position = 0;
+
+        while !pull_cancellation_token.is_cancelled() {
+            log::info!("Querying {position}:{}", position + batch_size - 1);
+
+            let response = client
+                .call(
+                    "Email/query",
+                    json!({
+                        "filter": { "inMailbox": mailbox.id },
+                        "sort": [{ "property": "receivedAt", "isAscending": true }],
+                        "position": position,
+                        "limit": batch_size,
+                    }),
+                )
+                .await
+                .context("error getting messages")?;
+            let ids: Vec<String> = serde_json::from_value(response["ids"].clone())
+                .context("malformed Email/query response")?;
+
+            if ids.is_empty() {
+                log::debug!("No more messages");
+                break;
+            }
+            position += ids.len();
+
+            let new_ids: Vec<_> = ids
+                .into_iter()
+                .filter(|id| !pulled_ids.contains(id))
+                .collect();
+            if new_ids.is_empty() {
+                continue;
+            }
+
+            let response = client
+                .call(
+                    "Email/get",
+                    json!({ "ids": new_ids, "properties": ["id", "blobId"] }),
+                )
+                .await
+                .context("error getting messages")?;
+            let emails = response["list"]
+                .as_array()
+                .context("malformed Email/get response")?;
+
+            log::debug!("Fetching {} messages", emails.len());
+
+            for email in emails {
+                if pull_cancellation_token.is_cancelled() {
+                    break;
+                }
+
+                let id = email["id"].as_str().context("message has no id")?;
+                let blob_id = email["blobId"].as_str().context("message has no blobId")?;
+                let body = client.download(blob_id).await?;
+
+                let eml_file_name = format!(".{:0>8}.eml", message_id);
+                fs::write(folder_path.join(&eml_file_name), &body)
+                    .context("unable to save *.eml file")?;
+                writeln!(state_file, "{id} {eml_file_name}")
+                    .context("unable to update JMAP state file")?;
+
+                message_id += 1;
+                total_pulled_count += 1;
+
+                log::debug!("{} bytes eml message added", body.len());
+            }
+        }
+
+        state_file.flush().context("error flushing file")?;
+    }
+
+    log::info!(
+        "Done in {:?}, {} new messages stored ok.",
+        start.elapsed(),
+        total_pulled_count
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmap::tests::MockServer;
+    use serde_json::Value;
+
+    const MESSAGE_COUNT: usize = 250;
+
+    fn handler(method: &str, arguments: &Value) -> Value {
+        match method {
+            "Mailbox/get" => json!({ "list": [
+                { "id": "1", "name": "Inbox", "role": "inbox" },
+                { "id": "2", "name": "Work", "parentId": "1" },
+            ] }),
+            "Email/query" => {
+                let position = arguments["position"].as_u64().unwrap() as usize;
+                let limit = arguments["limit"].as_u64().unwrap() as usize;
+                let count = match arguments["filter"]["inMailbox"].as_str() {
+                    Some("1") => MESSAGE_COUNT,
+                    _ => 0,
+                };
+                let ids: Vec<_> = (position..count.min(position + limit))
+                    .map(|i| format!("e{i}"))
+                    .collect();
+                json!({ "ids": ids })
+            }
+            "Email/get" => {
+                let list: Vec<_> = arguments["ids"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|id| json!({ "id": id, "blobId": format!("blob-{}", id.as_str().unwrap()) }))
+                    .collect();
+                json!({ "list": list })
+            }
+            _ => json!({ "type": "unknownMethod" }),
+        }
+    }
+
+    fn config(server: &MockServer) -> Config {
+        serde_yaml::from_str(&format!("jmap:\n  pull:\n    url: {}\n", server.url)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pull_pages_queries_and_skips_pulled_messages() {
+        let server = MockServer::start(handler).await;
+        let out_dir = tempfile::tempdir().unwrap();
+        let out = out_dir.path().to_str().unwrap().to_string();
+
+        pull(
+            &config(&server),
+            "a@b.c".to_string(),
+            "x".to_string(),
+            out.clone(),
+        )
+        .await
+        .unwrap();
+
+        let inbox_positions: Vec<_> = server
+            .calls("Email/query")
+            .iter()
+            .filter(|arguments| arguments["filter"]["inMailbox"] == "1")
+            .map(|arguments| arguments["position"].as_u64().unwrap())
+            .collect();
+        assert_eq!(inbox_positions, vec![0, 200, 250]);
+        let fetched: usize = server
+            .calls("Email/get")
+            .iter()
+            .map(|arguments| arguments["ids"].as_array().unwrap().len())
+            .sum();
+        assert_eq!(fetched, MESSAGE_COUNT);
+
+        let inbox_path = out_dir.path().join("b.c/a@b.c/INBOX");
+        assert!(inbox_path.join("Work").is_dir());
+        assert_eq!(
+            fs::read_to_string(inbox_path.join(".00000001.eml")).unwrap(),
+            "blob-e0"
+        );
+        assert_eq!(
+            fs::read_to_string(inbox_path.join(".00000250.eml")).unwrap(),
+            "blob-e249"
+        );
+        assert_eq!(
+            fs::read_to_string(inbox_path.join(IDS_STATE_FILE_NAME))
+                .unwrap()
+                .lines()
+                .next(),
+            Some("e0 .00000001.eml")
+        );
+
+        pull(&config(&server), "a@b.c".to_string(), "x".to_string(), out)
+            .await
+            .unwrap();
+
+        assert_eq!(server.calls("Email/get").len(), 2);
+        assert_eq!(last_message_id(&inbox_path).unwrap(), MESSAGE_COUNT);
+    }
+}
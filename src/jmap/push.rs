@@ -0,0 +1,442 @@
+use anyhow::Context;
+use async_walkdir::{Filtering, WalkDir};
+use futures_lite::stream::StreamExt;
+use human_bytes::human_bytes;
+use serde_json::{json, Map, Value};
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use tokio::time::Instant;
+
+use super::{mailbox_paths, JmapClient, JmapMailbox};
+use crate::config::Config;
+
+/// Top level folder names matched to native mailbox roles (RFC 8621, section 2).
+const ROLES: [&str; 6] = ["inbox", "sent", "drafts", "trash", "junk", "archive"];
+
+pub async fn push(
+    config: &Config,
+    email: String,
+    password: String,
+    in_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let push_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pushed_count = 0;
+
+    let folder_name = format!("{in_dir}/{domain}/{email}/",);
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+    let folder_path = folder_path.to_str().context("wrong in_dir path")?;
+
+    log::info!("Getting mailboxes in {}", folder_path);
+
+    let mut mailboxes = Vec::<(String, String)>::new();
+
+    let mut entries = WalkDir::new(folder_path).filter(|entry| async move {
+        match entry.file_type().await {
+            Ok(file_type) => {
+                if file_type.is_dir() {
+                    Filtering::Continue
+                } else {
+                    Filtering::Ignore
+                }
+            }
+            Err(_) => Filtering::Continue,
+        }
+    });
+
+    while !push_cancellation_token.is_cancelled() {
+        match entries.next().await {
+            Some(Ok(entry)) => {
+                let mailbox_path = entry.path().to_str().unwrap_or_default().to_string();
+                let mailbox_name = mailbox_path[folder_path.len()..].to_string();
+                mailboxes.push((mailbox_name, mailbox_path));
+            }
+            Some(Err(e)) => {
+                log::warn!("Error reading dir: {}", e);
+                break;
+            }
+            None => break,
+        }
+    }
+
+    log::info!("Found {} mailboxes", mailboxes.len());
+
+    if !mailboxes.is_empty() {
+        let jmap_config = config
+            .jmap
+            .clone()
+            .context("JMAP config is not provided in config.yaml")?
+            .push
+            .context("JMAP push server config not provided")?;
+
+        log::debug!("Pushing JMAP for account {email}...");
+        let client = JmapClient::connect(&jmap_config, &email, &password).await?;
+        log::info!("Connected to JMAP server {} as {}", jmap_config.url, email);
+
+        let mut target_mailboxes = client.mailboxes().await?;
+        let batch_size = 20;
+
+        for (mailbox_name, mailbox_path) in mailboxes {
+            let Some(mailbox_mapped_name) = jmap_config.map_folder_name(&mailbox_name) else {
+                log::info!("Skipping mailbox {mailbox_name}, dropped by folder_rules");
+                continue;
+            };
+
+            log::info!("Processing mailbox {}", mailbox_mapped_name);
+
+            let mailbox_id =
+                resolve_mailbox(&client, &mut target_mailboxes, &mailbox_mapped_name).await?;
+            log::debug!("Mailbox {mailbox_name} resolved to {mailbox_id}");
+
+            let mut entries = WalkDir::new(&mailbox_path).filter(|entry| async move {
+                match entry.file_type().await {
+                    Ok(file_type) => {
+                        if file_type.is_file() {
+                            if entry.path().extension() == Some("eml".as_ref())
+                                && entry
+                                    .path()
+                                    .file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                                    .starts_with('.')
+                            {
+                                Filtering::Continue
+                            } else {
+                                Filtering::Ignore
+                            }
+                        } else {
+                            Filtering::IgnoreDir
+                        }
+                    }
+                    Err(_) => Filtering::Continue,
+                }
+            });
+
+            let mut pushed_count = 0;
+            let mut batch = Vec::<(String, String, usize)>::new();
+
+            loop {
+                let entry = if push_cancellation_token.is_cancelled() {
+                    None
+                } else {
+                    match entries.next().await {
+                        Some(Ok(entry)) => Some(entry),
+                        Some(Err(e)) => {
+                            log::warn!("Error reading dir: {}", e);
+                            None
+                        }
+                        None => None,
+                    }
+                };
+
+                let done = entry.is_none();
+
+                if let Some(entry) = entry {
+                    let eml_file_path = entry.path().to_str().unwrap_or_default().to_string();
+                    log::debug!("Uploading {} to {}...", eml_file_path, mailbox_mapped_name);
+
+                    if let Ok(data) = fs::read(&eml_file_path) {
+                        let size = data.len();
+                        match client.upload(data).await {
+                            Ok(blob_id) => batch.push((eml_file_path, blob_id, size)),
+                            Err(err) => log::debug!("Error uploading message: {}", err),
+                        }
+                    }
+                }
+
+                if batch.len() >= batch_size || (done && !batch.is_empty()) {
+                    let imported = import_batch(&client, &mailbox_id, &batch).await?;
+                    for (eml_file_path, _, size) in batch.drain(..) {
+                        if !imported.contains(&eml_file_path) {
+                            continue;
+                        }
+
+                        let eml_file = PathBuf::from(&eml_file_path);
+                        let eml_file_name = eml_file
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string();
+                        fs::rename(
+                            &eml_file,
+                            eml_file.with_file_name(eml_file_name.trim_start_matches('.')),
+                        )?;
+
+                        pushed_count += 1;
+                        total_pushed_count += 1;
+
+                        log::debug!("{} sent ok", human_bytes(size as f64));
+                    }
+                }
+
+                if done {
+                    break;
+                }
+            }
+
+            log::info!("Uploaded {pushed_count} messages to {mailbox_mapped_name}");
+        }
+    }
+
+    log::info!(
+        "Done in {:?}, {} messages uploaded ok.",
+        start.elapsed(),
+        total_pushed_count
+    );
+
+    Ok(())
+}
+
+/// Imports uploaded blobs and returns the file paths of messages created.
+async fn import_batch(
+    client: &JmapClient,
+    mailbox_id: &str,
+    batch: &[(String, String, usize)],
+) -> anyhow::Result<Vec<String>> {
+    let mut emails = Map::new();
+    for (i, (_, blob_id, _)) in batch.iter().enumerate() {
+        emails.insert(
+            format!("m{i}"),
+            json!({
+                "blobId": blob_id,
+                "mailboxIds": { mailbox_id: true },
+                "keywords": { "$seen": true },
+            }),
+        );
+    }
+
+    let response = client
+        .call("Email/import", json!({ "emails": Value::Object(emails) }))
+        .await?;
+
+    let mut imported = Vec::new();
+    for (i, (eml_file_path, _, _)) in batch.iter().enumerate() {
+        let key = format!("m{i}");
+        if response["created"].get(&key).is_some() {
+            imported.push(eml_file_path.clone());
+        } else {
+            log::debug!(
+                "Error pushing message {}: {}",
+                eml_file_path,
+                response["notCreated"][&key]["type"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+            );
+        }
+    }
+
+    Ok(imported)
+}
+
+/// Finds the target mailbox by path or by role and creates missing mailboxes.
+async fn resolve_mailbox(
+    client: &JmapClient,
+    mailboxes: &mut Vec<JmapMailbox>,
+    name: &str,
+) -> anyhow::Result<String> {
+    let role = name.to_lowercase();
+    if ROLES.contains(&role.as_str()) {
+        if let Some(mailbox) = mailboxes
+            .iter()
+            .find(|mailbox| mailbox.role.as_deref() == Some(role.as_str()))
+        {
+            return Ok(mailbox.id.clone());
+        }
+    }
+
+    let mut parent_id: Option<String> = None;
+    let mut path = String::new();
+
+    for component in name.split('/').filter(|component| !component.is_empty()) {
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(component);
+
+        let paths = mailbox_paths(mailboxes);
+        let existing = paths
+            .iter()
+            .find(|(_, mailbox_path)| mailbox_path.as_str() == path)
+            .map(|(id, _)| id.clone());
+
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                log::debug!("Creating mailbox {path}");
+                let response = client
+                    .call(
+                        "Mailbox/set",
+                        json!({
+                            "create": { "new": { "name": component, "parentId": parent_id } }
+                        }),
+                    )
+                    .await?;
+                let id = response["created"]["new"]["id"]
+                    .as_str()
+                    .context(format!("unable to create mailbox {path}"))?
+                    .to_string();
+                mailboxes.push(JmapMailbox {
+                    id: id.clone(),
+                    name: component.to_string(),
+                    parent_id: parent_id.clone(),
+                    role: None,
+                });
+                id
+            }
+        };
+
+        parent_id = Some(id);
+    }
+
+    parent_id.context(format!("wrong mailbox name {name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jmap::tests::MockServer;
+    use std::path::Path;
+
+    fn handler(method: &str, arguments: &Value) -> Value {
+        match method {
+            "Mailbox/get" => json!({ "list": [
+                { "id": "1", "name": "Inbox", "role": "inbox" },
+                { "id": "2", "name": "Sent Items", "role": "sent" },
+                { "id": "3", "name": "Projects" },
+            ] }),
+            "Mailbox/set" => json!({ "created": { "new": { "id": "10" } } }),
+            "Email/import" => {
+                let mut created = Map::new();
+                let mut not_created = Map::new();
+                for (key, email) in arguments["emails"].as_object().unwrap() {
+                    if email["blobId"] == "blob-bad" {
+                        not_created.insert(key.clone(), json!({ "type": "invalidEmail" }));
+                    } else {
+                        created.insert(key.clone(), json!({ "id": key }));
+                    }
+                }
+                json!({ "created": created, "notCreated": not_created })
+            }
+            _ => json!({ "type": "unknownMethod" }),
+        }
+    }
+
+    fn write_message(folder_path: &Path, content: &str) {
+        fs::create_dir_all(folder_path).unwrap();
+        fs::write(folder_path.join(format!(".{content}.eml")), content).unwrap();
+    }
+
+    /// Mailbox ids and email counts of the `Email/import` calls.
+    fn imports(server: &MockServer) -> Vec<(String, usize)> {
+        server
+            .calls("Email/import")
+            .iter()
+            .map(|arguments| {
+                let emails = arguments["emails"].as_object().unwrap();
+                let mailbox_id = emails["m0"]["mailboxIds"]
+                    .as_object()
+                    .unwrap()
+                    .keys()
+                    .next()
+                    .unwrap()
+                    .clone();
+                (mailbox_id, emails.len())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn push_imports_batches_into_resolved_mailboxes() {
+        let server = MockServer::start(handler).await;
+        let in_dir = tempfile::tempdir().unwrap();
+        let account_path = in_dir.path().join("b.c/a@b.c");
+        for i in 0..45 {
+            write_message(&account_path.join("INBOX"), &format!("m{i}"));
+        }
+        write_message(&account_path.join("INBOX"), "bad");
+        write_message(&account_path.join("Sent"), "s1");
+        write_message(&account_path.join("Projects/Alpha"), "p1");
+        let config: Config =
+            serde_yaml::from_str(&format!("jmap:\n  push:\n    url: {}\n", server.url)).unwrap();
+
+        push(
+            &config,
+            "a@b.c".to_string(),
+            "x".to_string(),
+            in_dir.path().to_str().unwrap().to_string(),
+        )
+        .await
+        .unwrap();
+
+        let mut imports = imports(&server);
+        imports.sort();
+        assert_eq!(
+            imports,
+            vec![
+                ("1".to_string(), 6),
+                ("1".to_string(), 20),
+                ("1".to_string(), 20),
+                ("10".to_string(), 1),
+                ("2".to_string(), 1),
+            ]
+        );
+        assert_eq!(
+            server.calls("Mailbox/set"),
+            vec![json!({
+                "accountId": "a1",
+                "create": { "new": { "name": "Alpha", "parentId": "3" } },
+            })]
+        );
+
+        assert!(account_path.join("INBOX/m0.eml").exists());
+        assert!(account_path.join("INBOX/m44.eml").exists());
+        assert!(account_path.join("INBOX/.bad.eml").exists());
+        assert!(account_path.join("Sent/s1.eml").exists());
+        assert!(account_path.join("Projects/Alpha/p1.eml").exists());
+    }
+
+    #[tokio::test]
+    async fn push_skips_mailboxes_dropped_by_folder_rules() {
+        let server = MockServer::start(handler).await;
+        let in_dir = tempfile::tempdir().unwrap();
+        let account_path = in_dir.path().join("b.c/a@b.c");
+        write_message(&account_path.join("INBOX"), "m1");
+        write_message(&account_path.join("Spam"), "s1");
+        let config: Config = serde_yaml::from_str(&format!(
+            "jmap:\n  push:\n    url: {}\n    folder_rules:\n      - {{ prefix: Spam, drop: true }}\n",
+            server.url
+        ))
+        .unwrap();
+
+        push(
+            &config,
+            "a@b.c".to_string(),
+            "x".to_string(),
+            in_dir.path().to_str().unwrap().to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(imports(&server), vec![("1".to_string(), 1)]);
+        assert!(account_path.join("INBOX/m1.eml").exists());
+        assert!(account_path.join("Spam/.s1.eml").exists());
+    }
+}
@@ -1,63 +1,375 @@
 use anyhow::Context;
+use archive::{create::create, restore::restore, verify::verify};
 use args::{CommandType, OffTheCloudArgs};
 use clap::Parser;
 use config::Config;
-use imap::{pull::pull, push::push};
+use imap::{
+    list::list,
+    pull::{pull, PullOptions},
+    push::{push, PushOptions},
+    sync::sync,
+    watch::watch,
+};
+use report::Report;
+use std::process::ExitCode;
 
+pub mod archive;
 pub mod args;
+pub mod caldav;
+pub mod carddav;
 pub mod config;
+pub mod dav;
 pub mod imap;
+pub mod jmap;
+pub mod net;
+pub mod pop3;
+pub mod progress;
+pub mod report;
+pub mod sieve;
+pub mod smtp;
+pub mod status;
+pub mod storage;
+pub mod webdav;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let res = run().await;
     match res {
-        Err(err) => log::error!("Error: {}", err),
-        Ok(_) => log::info!("Done"),
+        Err(err) => {
+            log::error!("Error: {}", err);
+            ExitCode::from(report::EXIT_FAILED)
+        }
+        Ok(exit_code) => {
+            log::info!("Done");
+            ExitCode::from(exit_code)
+        }
     }
 }
 
-async fn run() -> anyhow::Result<()> {
+/// Returns the exit code, non-zero when some messages failed.
+async fn run() -> anyhow::Result<u8> {
     dotenv::dotenv().ok();
 
-    pretty_env_logger::env_logger::builder().init();
+    progress::init_logger();
 
     let args = OffTheCloudArgs::parse();
     log::debug!("Args: {:?}", args);
 
-    let f = std::fs::File::open("config.yaml").context("config.yaml not found")?;
-    let config: Config = serde_yaml::from_reader(f).context("config.yaml parse error")?;
-    log::debug!("Config: {:?}", config);
+    let mut exit_code = 0;
+    let load_config = || Config::load(args.config.as_deref(), args.profile.as_deref(), None);
 
     match args.command {
-        CommandType::Imap(imap_command) => match imap_command.subcommand {
-            args::ImapSubcommand::Pull(imap_pull_subcommand) => {
-                pull(
-                    &config,
-                    imap_pull_subcommand.email,
-                    imap_pull_subcommand.password,
-                    imap_pull_subcommand.out_dir,
-                    imap_pull_subcommand.export_mbox,
-                    parse_size::parse_size(&imap_pull_subcommand.max_file_size).context(
-                        format!(
-                            "malformed file size {:?}",
-                            imap_pull_subcommand.max_file_size
-                        ),
-                    )? as usize,
+        CommandType::Imap(imap_command) => {
+            let email = match imap_command.subcommand {
+                args::ImapSubcommand::Pull(ref subcommand) => &subcommand.email,
+                args::ImapSubcommand::Push(ref subcommand) => &subcommand.email,
+                args::ImapSubcommand::Watch(ref subcommand) => &subcommand.email,
+                args::ImapSubcommand::Sync(ref subcommand) => &subcommand.email,
+                args::ImapSubcommand::List(ref subcommand) => &subcommand.email,
+            };
+            let account = config::accounts::find(args.accounts.as_deref(), email)?;
+            let config = Config::load(
+                args.config.as_deref(),
+                args.profile.as_deref(),
+                account.as_ref(),
+            )?;
+
+            match imap_command.subcommand {
+                args::ImapSubcommand::Pull(imap_pull_subcommand) => {
+                    let mut report = Report::new(
+                        "imap pull",
+                        &imap_pull_subcommand.email,
+                        imap_pull_subcommand.events,
+                    );
+                    let result = pull(
+                        &config,
+                        imap_pull_subcommand.email,
+                        imap_pull_subcommand.password,
+                        imap_pull_subcommand.out_dir,
+                        PullOptions {
+                            export_mbox: imap_pull_subcommand.export_mbox,
+                            max_file_size: parse_size::parse_size(
+                                &imap_pull_subcommand.max_file_size,
+                            )
+                            .context(format!(
+                                "malformed file size {:?}",
+                                imap_pull_subcommand.max_file_size
+                            ))? as usize,
+                            memory_limit: parse_size::parse_size(&imap_pull_subcommand.memory_limit)
+                                .context(format!(
+                                    "malformed memory limit {:?}",
+                                    imap_pull_subcommand.memory_limit
+                                ))? as usize,
+                        },
+                        &mut report,
+                    )
+                    .await;
+                    report.finish(&result, imap_pull_subcommand.report.as_deref())?;
+                    result?;
+                    exit_code = report.exit_code;
+                }
+                args::ImapSubcommand::Push(imap_push_subcommand) => {
+                    let mut report = Report::new(
+                        "imap push",
+                        &imap_push_subcommand.email,
+                        imap_push_subcommand.events,
+                    );
+                    let from_account = imap_push_subcommand
+                        .from_account
+                        .or(account.and_then(|account| account.from_account));
+                    let result = push(
+                        &config,
+                        imap_push_subcommand.email,
+                        imap_push_subcommand.password,
+                        imap_push_subcommand.in_dir,
+                        PushOptions {
+                            from_account,
+                            ignore_quota: imap_push_subcommand.ignore_quota,
+                            retry_failed: imap_push_subcommand.retry_failed,
+                        },
+                        &mut report,
+                    )
+                    .await;
+                    report.finish(&result, imap_push_subcommand.report.as_deref())?;
+                    result?;
+                    exit_code = report.exit_code;
+                }
+                args::ImapSubcommand::Watch(imap_watch_subcommand) => {
+                    watch(
+                        &config,
+                        imap_watch_subcommand.email,
+                        imap_watch_subcommand.password,
+                        imap_watch_subcommand.out_dir,
+                        imap_watch_subcommand.mailboxes,
+                        imap_watch_subcommand.poll_interval,
+                    )
+                    .await?
+                }
+                args::ImapSubcommand::Sync(imap_sync_subcommand) => {
+                    sync(
+                        &config,
+                        imap_sync_subcommand.email,
+                        imap_sync_subcommand.password,
+                        imap_sync_subcommand.out_dir,
+                        imap_sync_subcommand.bidirectional,
+                        imap_sync_subcommand.conflict_winner,
+                    )
+                    .await?
+                }
+                args::ImapSubcommand::List(imap_list_subcommand) => {
+                    list(
+                        &config,
+                        imap_list_subcommand.email,
+                        imap_list_subcommand.password,
+                        imap_list_subcommand.server,
+                        imap_list_subcommand.format,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Pop3(pop3_command) => {
+            let config = load_config()?;
+
+            match pop3_command.subcommand {
+                args::Pop3Subcommand::Pull(pop3_pull_subcommand) => {
+                    pop3::pull::pull(
+                        &config,
+                        pop3_pull_subcommand.email,
+                        pop3_pull_subcommand.password,
+                        pop3_pull_subcommand.out_dir,
+                        pop3_pull_subcommand.list_headers,
+                        pop3_pull_subcommand.delete,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Jmap(jmap_command) => {
+            let config = load_config()?;
+
+            match jmap_command.subcommand {
+                args::JmapSubcommand::Pull(jmap_pull_subcommand) => {
+                    jmap::pull::pull(
+                        &config,
+                        jmap_pull_subcommand.email,
+                        jmap_pull_subcommand.password,
+                        jmap_pull_subcommand.out_dir,
+                    )
+                    .await?
+                }
+                args::JmapSubcommand::Push(jmap_push_subcommand) => {
+                    jmap::push::push(
+                        &config,
+                        jmap_push_subcommand.email,
+                        jmap_push_subcommand.password,
+                        jmap_push_subcommand.in_dir,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Caldav(caldav_command) => {
+            let config = load_config()?;
+
+            match caldav_command.subcommand {
+                args::CaldavSubcommand::Pull(caldav_pull_subcommand) => {
+                    caldav::pull::pull(
+                        &config,
+                        caldav_pull_subcommand.email,
+                        caldav_pull_subcommand.password,
+                        caldav_pull_subcommand.out_dir,
+                    )
+                    .await?
+                }
+                args::CaldavSubcommand::Push(caldav_push_subcommand) => {
+                    caldav::push::push(
+                        &config,
+                        caldav_push_subcommand.email,
+                        caldav_push_subcommand.password,
+                        caldav_push_subcommand.in_dir,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Carddav(carddav_command) => {
+            let config = load_config()?;
+
+            match carddav_command.subcommand {
+                args::CarddavSubcommand::Pull(carddav_pull_subcommand) => {
+                    carddav::pull::pull(
+                        &config,
+                        carddav_pull_subcommand.email,
+                        carddav_pull_subcommand.password,
+                        carddav_pull_subcommand.out_dir,
+                    )
+                    .await?
+                }
+                args::CarddavSubcommand::Push(carddav_push_subcommand) => {
+                    carddav::push::push(
+                        &config,
+                        carddav_push_subcommand.email,
+                        carddav_push_subcommand.password,
+                        carddav_push_subcommand.in_dir,
+                        carddav_push_subcommand.vcard_version,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Webdav(webdav_command) => {
+            let config = load_config()?;
+
+            match webdav_command.subcommand {
+                args::WebdavSubcommand::Pull(webdav_pull_subcommand) => {
+                    webdav::pull::pull(
+                        &config,
+                        webdav_pull_subcommand.email,
+                        webdav_pull_subcommand.password,
+                        webdav_pull_subcommand.out_dir,
+                    )
+                    .await?
+                }
+                args::WebdavSubcommand::Push(webdav_push_subcommand) => {
+                    webdav::push::push(
+                        &config,
+                        webdav_push_subcommand.email,
+                        webdav_push_subcommand.password,
+                        webdav_push_subcommand.in_dir,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Sieve(sieve_command) => {
+            let config = load_config()?;
+
+            match sieve_command.subcommand {
+                args::SieveSubcommand::Pull(sieve_pull_subcommand) => {
+                    sieve::pull::pull(
+                        &config,
+                        sieve_pull_subcommand.email,
+                        sieve_pull_subcommand.password,
+                        sieve_pull_subcommand.out_dir,
+                    )
+                    .await?
+                }
+                args::SieveSubcommand::Push(sieve_push_subcommand) => {
+                    sieve::push::push(
+                        &config,
+                        sieve_push_subcommand.email,
+                        sieve_push_subcommand.password,
+                        sieve_push_subcommand.in_dir,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Smtp(smtp_command) => {
+            let config = load_config()?;
+
+            match smtp_command.subcommand {
+                args::SmtpSubcommand::Push(smtp_push_subcommand) => {
+                    smtp::push::push(
+                        &config,
+                        smtp_push_subcommand.email,
+                        smtp_push_subcommand.in_dir,
+                    )
+                    .await?
+                }
+            }
+        }
+        CommandType::Archive(archive_command) => match archive_command.subcommand {
+            args::ArchiveSubcommand::Create(archive_create_subcommand) => {
+                let scope = archive_create_subcommand
+                    .scope
+                    .email
+                    .or(archive_create_subcommand.scope.domain)
+                    .context("either --email or --domain must be set")?;
+                create(
+                    archive_create_subcommand.in_dir,
+                    archive_create_subcommand.out_dir,
+                    scope,
+                    archive_create_subcommand.incremental,
+                    archive_create_subcommand.compression_level,
                 )
                 .await?
             }
-            args::ImapSubcommand::Push(imap_push_subcommand) => {
-                push(
-                    &config,
-                    imap_push_subcommand.email,
-                    imap_push_subcommand.password,
-                    imap_push_subcommand.in_dir,
+            args::ArchiveSubcommand::Restore(archive_restore_subcommand) => {
+                restore(
+                    archive_restore_subcommand.archive,
+                    archive_restore_subcommand.out_dir,
+                    archive_restore_subcommand.overwrite,
+                )
+                .await?
+            }
+            args::ArchiveSubcommand::Verify(archive_verify_subcommand) => {
+                verify(archive_verify_subcommand.archive).await?
+            }
+        },
+        CommandType::Status(status_command) => {
+            status::status(
+                status_command.in_dir,
+                status_command.domain,
+                status_command.format,
+            )
+            .await?
+        }
+        CommandType::Config(config_command) => match config_command.subcommand {
+            args::ConfigSubcommand::Check(config_check_subcommand) => {
+                config::check::check(
+                    args.config.as_deref(),
+                    args.profile.as_deref(),
+                    args.accounts.as_deref(),
+                    config_check_subcommand.email,
+                    config_check_subcommand.password,
                 )
                 .await?
             }
+            args::ConfigSubcommand::Schema => config::check::schema()?,
         },
     }
 
-    Ok(())
+    Ok(exit_code)
 }
@@ -0,0 +1,8 @@
+//! Connections of the line based clients (POP3, ManageSieve, SMTP), which may
+//! be upgraded from plain TCP to TLS.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
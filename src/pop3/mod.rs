@@ -0,0 +1,237 @@
+use anyhow::Context;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+use crate::{config::Pop3Security, net::Stream};
+
+pub mod pull;
+
+/// Minimal POP3 client (RFC 1939) with POP3S and STLS (RFC 2595) support.
+pub struct Pop3Client {
+    stream: BufStream<Box<dyn Stream>>,
+}
+
+impl Pop3Client {
+    pub async fn connect(
+        server: &str,
+        port: u16,
+        security: &Pop3Security,
+    ) -> anyhow::Result<Pop3Client> {
+        let tcp_stream = TcpStream::connect((server, port)).await?;
+        let tls = async_native_tls::TlsConnector::new();
+
+        let mut client = match security {
+            Pop3Security::Tls => {
+                let tls_stream = tls.connect(server, tcp_stream).await?;
+                Pop3Client::new(Box::new(tls_stream))
+            }
+            Pop3Security::Starttls => {
+                let mut client = Pop3Client::new(Box::new(tcp_stream));
+                client.read_response().await?;
+                client.command("STLS").await?;
+                // Nothing is sent by the server until the handshake is done,
+                // so the buffer is empty here
+                let tcp_stream = client.stream.into_inner();
+                let tls_stream = tls.connect(server, tcp_stream).await?;
+                return Ok(Pop3Client::new(Box::new(tls_stream)));
+            }
+            Pop3Security::Plain => Pop3Client::new(Box::new(tcp_stream)),
+        };
+
+        client.read_response().await?;
+
+        Ok(client)
+    }
+
+    fn new(stream: Box<dyn Stream>) -> Pop3Client {
+        Pop3Client {
+            stream: BufStream::new(stream),
+        }
+    }
+
+    pub async fn login(&mut self, user: &str, password: &str) -> anyhow::Result<()> {
+        self.command(&format!("USER {user}")).await?;
+        self.command(&format!("PASS {password}"))
+            .await
+            .context("authentication failed")?;
+        Ok(())
+    }
+
+    /// Returns message count and mailbox size in bytes.
+    pub async fn stat(&mut self) -> anyhow::Result<(usize, usize)> {
+        let response = self.command("STAT").await?;
+        let mut parts = response.split_whitespace();
+        let count = parts.next().context("malformed STAT response")?.parse()?;
+        let size = parts.next().context("malformed STAT response")?.parse()?;
+        Ok((count, size))
+    }
+
+    /// Returns message numbers with their unique ids.
+    pub async fn uidl(&mut self) -> anyhow::Result<Vec<(usize, String)>> {
+        self.command("UIDL").await?;
+        let data = self.read_multiline().await?;
+
+        let mut uids = Vec::new();
+        for line in String::from_utf8_lossy(&data).lines() {
+            let mut parts = line.split_whitespace();
+            let message_number = parts.next().context("malformed UIDL response")?.parse()?;
+            let uid = parts.next().context("malformed UIDL response")?.to_string();
+            uids.push((message_number, uid));
+        }
+
+        Ok(uids)
+    }
+
+    pub async fn retr(&mut self, message_number: usize) -> anyhow::Result<Vec<u8>> {
+        self.command(&format!("RETR {message_number}")).await?;
+        self.read_multiline().await
+    }
+
+    pub async fn top(&mut self, message_number: usize, lines: usize) -> anyhow::Result<Vec<u8>> {
+        self.command(&format!("TOP {message_number} {lines}"))
+            .await?;
+        self.read_multiline().await
+    }
+
+    pub async fn dele(&mut self, message_number: usize) -> anyhow::Result<()> {
+        self.command(&format!("DELE {message_number}")).await?;
+        Ok(())
+    }
+
+    pub async fn quit(&mut self) -> anyhow::Result<()> {
+        self.command("QUIT").await?;
+        Ok(())
+    }
+
+    /// Sends a command and returns the text following `+OK`.
+    async fn command(&mut self, command: &str) -> anyhow::Result<String> {
+        if command.starts_with("PASS ") {
+            log::debug!("C: PASS ***");
+        } else {
+            log::debug!("C: {command}");
+        }
+        self.stream.write_all(command.as_bytes()).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        self.read_response().await
+    }
+
+    async fn read_response(&mut self) -> anyhow::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            anyhow::bail!("connection closed by server");
+        }
+        let line = line.trim_end();
+        log::debug!("S: {line}");
+
+        match line.strip_prefix("+OK") {
+            Some(text) => Ok(text.trim().to_string()),
+            None => anyhow::bail!("server error: {}", line.trim_start_matches("-ERR").trim()),
+        }
+    }
+
+    /// Reads a dot-terminated multi-line response removing dot-stuffing.
+    async fn read_multiline(&mut self) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            if self.stream.read_until(b'\n', &mut line).await? == 0 {
+                anyhow::bail!("connection closed by server");
+            }
+            if line == b".\r\n" || line == b".\n" {
+                break;
+            }
+            if line.starts_with(b"..") {
+                data.extend_from_slice(&line[1..]);
+            } else {
+                data.extend_from_slice(&line);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+
+    /// Client reading the given responses, the server end receives commands.
+    async fn mock_client(responses: &str) -> (Pop3Client, DuplexStream) {
+        let (client_stream, mut server_stream) = duplex(4096);
+        server_stream.write_all(responses.as_bytes()).await.unwrap();
+        (Pop3Client::new(Box::new(client_stream)), server_stream)
+    }
+
+    async fn sent_commands(client: Pop3Client, mut server_stream: DuplexStream) -> String {
+        drop(client);
+        let mut commands = String::new();
+        server_stream.read_to_string(&mut commands).await.unwrap();
+        commands
+    }
+
+    #[tokio::test]
+    async fn stat() {
+        let (mut client, server_stream) = mock_client("+OK 2 320\r\n").await;
+        assert_eq!(client.stat().await.unwrap(), (2, 320));
+        assert_eq!(sent_commands(client, server_stream).await, "STAT\r\n");
+
+        let (mut client, _server_stream) = mock_client("+OK 2\r\n").await;
+        assert!(client.stat().await.is_err());
+        let (mut client, _server_stream) = mock_client("-ERR locked\r\n").await;
+        let err = client.stat().await.unwrap_err();
+        assert_eq!(err.to_string(), "server error: locked");
+    }
+
+    #[tokio::test]
+    async fn uidl() {
+        let (mut client, server_stream) =
+            mock_client("+OK\r\n1 whqtswO00WBw418f9t5JxYwZ\r\n2 QhdPYR:00WBw1Ph7x7\r\n.\r\n").await;
+        assert_eq!(
+            client.uidl().await.unwrap(),
+            vec![
+                (1, "whqtswO00WBw418f9t5JxYwZ".to_string()),
+                (2, "QhdPYR:00WBw1Ph7x7".to_string())
+            ]
+        );
+        assert_eq!(sent_commands(client, server_stream).await, "UIDL\r\n");
+
+        let (mut client, _server_stream) = mock_client("+OK\r\nx uid\r\n.\r\n").await;
+        assert!(client.uidl().await.is_err());
+        let (mut client, _server_stream) = mock_client("+OK\r\n1\r\n.\r\n").await;
+        assert!(client.uidl().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn retr_removes_dot_stuffing() {
+        let (mut client, server_stream) = mock_client(
+            "+OK 52 octets\r\nSubject: dots\r\n\r\n..leading dot\r\n...\r\nmiddle . dot\r\n.\r\n",
+        )
+        .await;
+        assert_eq!(
+            client.retr(1).await.unwrap(),
+            b"Subject: dots\r\n\r\n.leading dot\r\n..\r\nmiddle . dot\r\n"
+        );
+        assert_eq!(sent_commands(client, server_stream).await, "RETR 1\r\n");
+    }
+
+    #[tokio::test]
+    async fn read_multiline_accepts_bare_newlines() {
+        let (mut client, _server_stream) = mock_client("a\n..b\n.\n+OK\r\n").await;
+        assert_eq!(client.read_multiline().await.unwrap(), b"a\n.b\n");
+        assert_eq!(client.read_response().await.unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn read_multiline_fails_on_closed_connection() {
+        let (mut client, server_stream) = mock_client("line\r\n").await;
+        drop(server_stream);
+        let err = client.read_multiline().await.unwrap_err();
+        assert_eq!(err.to_string(), "connection closed by server");
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Context;
+use human_bytes::human_bytes;
+use std::{
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+};
+use tokio::time::Instant;
+
+use super::Pop3Client;
+use crate::{
+    config::{Config, Pop3Security},
+    storage::{last_message_id, load_pulled_ids},
+};
+
+/// Keeps `UIDL` ids of already downloaded messages, one `{uid} {file_name}` per line.
+const UIDL_STATE_FILE_NAME: &str = ".pop3-uidl";
+
+pub async fn pull(
+    config: &Config,
+    email: String,
+    password: String,
+    out_dir: String,
+    list_headers: bool,
+    delete: bool,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let pull_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pulled_count = 0;
+
+    let pop3_config = config
+        .pop3
+        .clone()
+        .context("POP3 config is not provided in config.yaml")?
+        .pull
+        .context("POP3 pull server config not provided")?;
+    let pop3_security = pop3_config.security.unwrap_or_default();
+    let pop3_port = pop3_config.port.unwrap_or(match pop3_security {
+        Pop3Security::Tls => 995,
+        _ => 110,
+    });
+
+    log::debug!("Pulling POP3 for account {email}...");
+    let mut client = Pop3Client::connect(&pop3_config.server, pop3_port, &pop3_security).await?;
+    log::info!(
+        "Connected to POP3 server {}:{}",
+        pop3_config.server,
+        pop3_port
+    );
+
+    client.login(&email, &password).await?;
+    log::info!("Logged in as {}", email);
+
+    let (message_count, mailbox_size) = client.stat().await?;
+    log::info!(
+        "Mailbox has {} messages ({})",
+        message_count,
+        human_bytes(mailbox_size as f64)
+    );
+
+    let uids = client.uidl().await.context("server doesn't support UIDL")?;
+
+    if list_headers {
+        for (message_number, uid) in uids {
+            if pull_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            let headers = client.top(message_number, 0).await?;
+            let headers = String::from_utf8_lossy(&headers);
+            log::info!(
+                "{message_number} {uid} | {} | {} | {}",
+                header_value(&headers, "Date").unwrap_or_default(),
+                header_value(&headers, "From").unwrap_or_default(),
+                header_value(&headers, "Subject").unwrap_or_default(),
+            );
+        }
+
+        client.quit().await?;
+
+        return Ok(());
+    }
+
+    let folder_name = format!("{out_dir}/{domain}/{email}/INBOX");
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+    fs::create_dir_all(&folder_path)?;
+
+    log::info!("Folder {}", folder_path.display());
+
+    let state_file_path = folder_path.join(UIDL_STATE_FILE_NAME);
+    let pulled_uids = load_pulled_ids(&state_file_path)?;
+    let mut state_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&state_file_path)
+        .context("unable to open UIDL state file")?;
+
+    let mut message_id = last_message_id(&folder_path)? + 1;
+    let mut deleted_count = 0;
+
+    for (message_number, uid) in uids {
+        if pull_cancellation_token.is_cancelled() {
+            break;
+        }
+
+        if !pulled_uids.contains(&uid) {
+            log::debug!("Retrieving message {message_number} ({uid})");
+            let body = client.retr(message_number).await?;
+
+            let eml_file_name = format!(".{:0>8}.eml", message_id);
+            fs::write(folder_path.join(&eml_file_name), &body)
+                .context("unable to save *.eml file")?;
+            writeln!(state_file, "{uid} {eml_file_name}")
+                .context("unable to update UIDL state file")?;
+
+            message_id += 1;
+            total_pulled_count += 1;
+
+            log::debug!("{} bytes eml message added", body.len());
+        }
+
+        if delete {
+            client.dele(message_number).await?;
+            deleted_count += 1;
+        }
+    }
+
+    state_file.flush().context("error flushing file")?;
+
+    // Messages marked with DELE are only removed once the session ends with QUIT
+    client.quit().await?;
+
+    if delete {
+        log::info!("{deleted_count} messages deleted from server");
+    }
+
+    log::info!(
+        "Done in {:?}, {} new messages stored ok.",
+        start.elapsed(),
+        total_pulled_count
+    );
+
+    Ok(())
+}
+
+fn header_value(headers: &str, name: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+
+    for line in headers.lines() {
+        match value {
+            Some(ref mut value) if line.starts_with([' ', '\t']) => {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            Some(_) => break,
+            None => {
+                if let Some((key, rest)) = line.split_once(':') {
+                    if key.eq_ignore_ascii_case(name) {
+                        value = Some(rest.trim().to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    value
+}
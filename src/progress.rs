@@ -0,0 +1,188 @@
+//! Progress of long transfers. Bars are drawn when stdout is a terminal,
+//! otherwise progress is logged every few seconds.
+
+use human_bytes::human_bytes;
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use std::{
+    io::IsTerminal,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+static MULTI_PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// Installs the logger. On a terminal log lines are printed above the
+/// progress bars instead of tearing them.
+pub fn init_logger() {
+    let mut builder = pretty_env_logger::env_logger::builder();
+    if !std::io::stdout().is_terminal() {
+        builder.init();
+        return;
+    }
+
+    let multi_progress = MultiProgress::new();
+    LogWrapper::new(multi_progress.clone(), builder.build())
+        .try_init()
+        .expect("logger is initialized once");
+    MULTI_PROGRESS.get_or_init(|| multi_progress);
+}
+
+#[derive(Debug)]
+pub struct Progress {
+    bars: Option<(ProgressBar, ProgressBar)>,
+    total: usize,
+    processed: usize,
+    transferred: usize,
+    bytes: u64,
+    folder_name: String,
+    folder_total: usize,
+    folder_processed: usize,
+    start: Instant,
+    last_log: Instant,
+}
+
+impl Progress {
+    /// `plain` forces log lines, e.g. when stdout carries JSON events.
+    pub fn new(plain: bool) -> Progress {
+        let bars = MULTI_PROGRESS
+            .get()
+            .filter(|_| !plain)
+            .map(|multi_progress| {
+                let style =
+                    ProgressStyle::with_template("{prefix:>12.bold} [{bar:30}] {pos}/{len} {msg}")
+                        .expect("valid template")
+                        .progress_chars("=> ");
+                let total_bar = multi_progress
+                    .add(ProgressBar::new(0).with_style(style.clone()))
+                    .with_prefix("Total");
+                let folder_bar = multi_progress.add(ProgressBar::new(0).with_style(style));
+                (total_bar, folder_bar)
+            });
+
+        Progress {
+            bars,
+            total: 0,
+            processed: 0,
+            transferred: 0,
+            bytes: 0,
+            folder_name: String::new(),
+            folder_total: 0,
+            folder_processed: 0,
+            start: Instant::now(),
+            last_log: Instant::now(),
+        }
+    }
+
+    /// Sets the number of messages of the whole run.
+    pub fn expect(&mut self, total: usize) {
+        self.total = total;
+        if let Some((total_bar, _)) = &self.bars {
+            total_bar.set_length(total as u64);
+        }
+    }
+
+    pub fn start_folder(&mut self, name: &str) {
+        self.folder_name = name.to_string();
+        self.folder_total = 0;
+        self.folder_processed = 0;
+        if let Some((_, folder_bar)) = &self.bars {
+            folder_bar.set_position(0);
+            folder_bar.set_length(0);
+            folder_bar.set_prefix(name.to_string());
+        }
+    }
+
+    pub fn found(&mut self, count: usize) {
+        self.folder_total += count;
+        // Folders created during the run aren't part of the expected total
+        let remaining = self.folder_total.saturating_sub(self.folder_processed);
+        self.total = self.total.max(self.processed + remaining);
+        if let Some((total_bar, folder_bar)) = &self.bars {
+            folder_bar.set_length(self.folder_total as u64);
+            total_bar.set_length(self.total as u64);
+        }
+    }
+
+    pub fn skipped(&mut self, count: usize) {
+        self.advance(count);
+    }
+
+    pub fn transferred(&mut self, bytes: usize) {
+        self.transferred += 1;
+        self.bytes += bytes as u64;
+        self.advance(1);
+    }
+
+    pub fn failed(&mut self) {
+        self.advance(1);
+    }
+
+    /// Prints the summary line of a completed folder.
+    pub fn finish_folder(&mut self, summary: &str) {
+        match &self.bars {
+            Some((_, folder_bar)) => folder_bar.println(format!("✔ {summary}")),
+            None => log::info!("Completed {summary}"),
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if let Some((total_bar, folder_bar)) = self.bars.take() {
+            folder_bar.finish_and_clear();
+            total_bar.finish_and_clear();
+        }
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.processed += count;
+        self.folder_processed += count;
+
+        match &self.bars {
+            Some((total_bar, folder_bar)) => {
+                total_bar.set_position(self.processed as u64);
+                total_bar.set_message(self.rate());
+                folder_bar.set_position(self.folder_processed as u64);
+            }
+            None if self.last_log.elapsed() >= LOG_INTERVAL => {
+                self.last_log = Instant::now();
+                log::info!(
+                    "Progress: {}/{} messages, {} {}/{} {}",
+                    self.processed,
+                    self.total,
+                    self.folder_name,
+                    self.folder_processed,
+                    self.folder_total,
+                    self.rate()
+                );
+            }
+            None => {}
+        }
+    }
+
+    /// Throughput and ETA, skipped messages don't count towards the rate.
+    fn rate(&self) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if self.transferred == 0 || elapsed == 0.0 {
+            return String::new();
+        }
+
+        let bytes_per_second = self.bytes as f64 / elapsed;
+        let messages_per_second = self.transferred as f64 / elapsed;
+        let remaining = self.total.saturating_sub(self.processed) as f64;
+        let eta = Duration::from_secs_f64(remaining / messages_per_second);
+
+        format!(
+            "({}/s, ETA {})",
+            human_bytes(bytes_per_second),
+            HumanDuration(eta)
+        )
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
@@ -0,0 +1,369 @@
+//! Machine-readable results of a run: the JSON report written with `--report`
+//! and JSON-lines progress events printed to stdout with `--events`. The same
+//! counters drive the progress display.
+
+use anyhow::Context;
+use human_bytes::human_bytes;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    fs,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use crate::progress::Progress;
+
+/// Bumped whenever a field is renamed or removed, new fields may be added
+/// without a bump.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+pub const EXIT_FAILED: u8 = 1;
+pub const EXIT_PARTIAL: u8 = 2;
+pub const EXIT_PAUSED: u8 = 3;
+pub const EXIT_CANCELLED: u8 = 130;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Every message was transferred or skipped
+    Ok,
+    /// The run completed, but some messages failed
+    Partial,
+    /// The run was interrupted with Ctrl-C
+    Cancelled,
+    /// The daily limit of the server was reached, the next run resumes
+    Paused,
+    /// The run stopped with an error
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub command: String,
+    pub account: String,
+    pub started_at: String,
+    pub duration_secs: f64,
+    pub status: RunStatus,
+    pub exit_code: u8,
+    pub error: Option<String>,
+    pub folders: Vec<FolderReport>,
+    #[serde(skip)]
+    events: bool,
+    #[serde(skip)]
+    cancelled: bool,
+    #[serde(skip)]
+    paused: bool,
+    #[serde(skip)]
+    start: Instant,
+    #[serde(skip)]
+    progress: Progress,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FolderReport {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub found: usize,
+    pub transferred: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub bytes: u64,
+    pub duration_secs: f64,
+    pub failures: Vec<MessageFailure>,
+    #[serde(skip)]
+    start: Instant,
+    #[serde(skip)]
+    finished: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageFailure {
+    pub message: String,
+    pub reason: String,
+}
+
+impl Report {
+    pub fn new(command: &str, account: &str, events: bool) -> Report {
+        let report = Report {
+            schema_version: REPORT_SCHEMA_VERSION,
+            command: command.to_string(),
+            account: account.to_string(),
+            started_at: chrono::Utc::now().to_rfc3339(),
+            duration_secs: 0.0,
+            status: RunStatus::Ok,
+            exit_code: 0,
+            error: None,
+            folders: Vec::new(),
+            events,
+            cancelled: false,
+            paused: false,
+            start: Instant::now(),
+            progress: Progress::new(events),
+        };
+        report.event(json!({
+            "event": "started",
+            "command": report.command,
+            "account": report.account,
+        }));
+
+        report
+    }
+
+    /// Sets the number of messages in all folders, used for the ETA.
+    pub fn expect(&mut self, count: usize) {
+        self.progress.expect(count);
+    }
+
+    /// Starts collecting counters of a folder, following calls apply to it.
+    pub fn start_folder(&mut self, name: &str, target: Option<&str>) {
+        self.finish_folder();
+        self.progress.start_folder(name);
+        self.event(json!({ "event": "folder_started", "folder": name, "target": target }));
+        self.folders.push(FolderReport {
+            name: name.to_string(),
+            target: target.map(|target| target.to_string()),
+            found: 0,
+            transferred: 0,
+            skipped: 0,
+            failed: 0,
+            bytes: 0,
+            duration_secs: 0.0,
+            failures: Vec::new(),
+            start: Instant::now(),
+            finished: false,
+        });
+    }
+
+    pub fn found(&mut self, count: usize) {
+        if let Some(folder) = self.folders.last_mut() {
+            folder.found += count;
+            self.progress.found(count);
+        }
+    }
+
+    pub fn skipped(&mut self, count: usize) {
+        if let Some(folder) = self.folders.last_mut() {
+            folder.skipped += count;
+            self.progress.skipped(count);
+        }
+    }
+
+    pub fn transferred(&mut self, message: &str, bytes: usize) {
+        let Some(folder) = self.folders.last_mut() else {
+            return;
+        };
+        folder.transferred += 1;
+        folder.bytes += bytes as u64;
+        let folder = folder.name.clone();
+        self.progress.transferred(bytes);
+        self.event(json!({
+            "event": "message",
+            "folder": folder,
+            "message": message,
+            "status": "transferred",
+            "bytes": bytes,
+        }));
+    }
+
+    pub fn failed(&mut self, message: &str, reason: &str) {
+        let Some(folder) = self.folders.last_mut() else {
+            return;
+        };
+        folder.failed += 1;
+        folder.failures.push(MessageFailure {
+            message: message.to_string(),
+            reason: reason.to_string(),
+        });
+        let folder = folder.name.clone();
+        self.progress.failed();
+        self.event(json!({
+            "event": "message",
+            "folder": folder,
+            "message": message,
+            "status": "failed",
+            "reason": reason,
+        }));
+    }
+
+    pub fn cancel(&mut self) {
+        self.cancelled = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Sets the overall status from the result of the run, then writes the
+    /// report to `report_path` if given.
+    pub fn finish(
+        &mut self,
+        result: &anyhow::Result<()>,
+        report_path: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.finish_folder();
+        self.progress.finish();
+        self.duration_secs = self.start.elapsed().as_secs_f64();
+        self.status = match result {
+            Err(err) => {
+                self.error = Some(format!("{:#}", err));
+                RunStatus::Failed
+            }
+            Ok(_) if self.cancelled => RunStatus::Cancelled,
+            Ok(_) if self.paused => RunStatus::Paused,
+            Ok(_) if self.folders.iter().any(|folder| folder.failed > 0) => RunStatus::Partial,
+            Ok(_) => RunStatus::Ok,
+        };
+        self.exit_code = match self.status {
+            RunStatus::Ok => 0,
+            RunStatus::Partial => EXIT_PARTIAL,
+            RunStatus::Cancelled => EXIT_CANCELLED,
+            RunStatus::Paused => EXIT_PAUSED,
+            RunStatus::Failed => EXIT_FAILED,
+        };
+        self.event(json!({
+            "event": "finished",
+            "status": self.status,
+            "exit_code": self.exit_code,
+            "error": self.error,
+            "duration_secs": self.duration_secs,
+        }));
+
+        if let Some(report_path) = report_path {
+            let data = serde_json::to_vec_pretty(self)?;
+            fs::write(report_path, data)
+                .context(format!("unable to write report {report_path}"))?;
+            log::info!("Report saved to {report_path}");
+        }
+
+        Ok(())
+    }
+
+    fn finish_folder(&mut self) {
+        let Some(folder) = self.folders.last_mut() else {
+            return;
+        };
+        if folder.finished {
+            return;
+        }
+        folder.finished = true;
+        folder.duration_secs = folder.start.elapsed().as_secs_f64();
+        let summary = format!(
+            "{}: {} transferred, {} skipped, {} failed, {} in {:?}",
+            folder.name,
+            folder.transferred,
+            folder.skipped,
+            folder.failed,
+            human_bytes(folder.bytes as f64),
+            Duration::from_secs_f64(folder.duration_secs)
+        );
+
+        let event = json!({
+            "event": "folder_finished",
+            "folder": folder.name,
+            "found": folder.found,
+            "transferred": folder.transferred,
+            "skipped": folder.skipped,
+            "failed": folder.failed,
+            "bytes": folder.bytes,
+            "duration_secs": folder.duration_secs,
+        });
+        self.event(event);
+        self.progress.finish_folder(&summary);
+    }
+
+    fn event(&self, event: serde_json::Value) {
+        if !self.events {
+            return;
+        }
+        // Logs go to stderr, so stdout carries events only
+        let mut stdout = std::io::stdout().lock();
+        if let Err(err) = writeln!(stdout, "{event}").and_then(|_| stdout.flush()) {
+            log::debug!("Unable to write event: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished_report(update: impl FnOnce(&mut Report), result: anyhow::Result<()>) -> Report {
+        let mut report = Report::new("imap push", "user@example.com", false);
+        report.start_folder("INBOX", None);
+        report.found(2);
+        report.transferred("00000001.eml", 10);
+        update(&mut report);
+        report.finish(&result, None).unwrap();
+        report
+    }
+
+    fn status_and_exit_code(report: Report) -> (RunStatus, u8) {
+        (report.status, report.exit_code)
+    }
+
+    #[test]
+    fn finish_maps_status_to_exit_code() {
+        assert_eq!(
+            status_and_exit_code(finished_report(|_| {}, Ok(()))),
+            (RunStatus::Ok, 0)
+        );
+        assert_eq!(
+            status_and_exit_code(finished_report(
+                |report| report.failed("00000002.eml", "rejected"),
+                Ok(())
+            )),
+            (RunStatus::Partial, 2)
+        );
+        assert_eq!(
+            status_and_exit_code(finished_report(
+                |report| {
+                    report.failed("00000002.eml", "rejected");
+                    report.cancel();
+                },
+                Ok(())
+            )),
+            (RunStatus::Cancelled, 130)
+        );
+        assert_eq!(
+            status_and_exit_code(finished_report(|report| report.pause(), Ok(()))),
+            (RunStatus::Paused, 3)
+        );
+        assert_eq!(
+            status_and_exit_code(finished_report(
+                |report| report.cancel(),
+                Err(anyhow::anyhow!("connection lost"))
+            )),
+            (RunStatus::Failed, 1)
+        );
+    }
+
+    #[test]
+    fn finish_writes_report_with_schema_version() {
+        let out_dir = tempfile::tempdir().unwrap();
+        let report_path = out_dir.path().join("report.json");
+        let mut report = Report::new("imap pull", "user@example.com", false);
+        report.start_folder("INBOX", Some("Inbox"));
+        report.failed("00000001.eml", "too large");
+        report
+            .finish(
+                &Err(anyhow::anyhow!("connection lost")),
+                Some(report_path.to_str().unwrap()),
+            )
+            .unwrap();
+
+        let value: serde_json::Value =
+            serde_json::from_slice(&fs::read(&report_path).unwrap()).unwrap();
+        assert_eq!(value["schema_version"], json!(1));
+        assert_eq!(value["status"], json!("failed"));
+        assert_eq!(value["exit_code"], json!(1));
+        assert_eq!(value["error"], json!("connection lost"));
+        assert_eq!(value["folders"][0]["target"], json!("Inbox"));
+        assert_eq!(
+            value["folders"][0]["failures"],
+            json!([{ "message": "00000001.eml", "reason": "too large" }])
+        );
+    }
+}
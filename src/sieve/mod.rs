@@ -0,0 +1,280 @@
+use anyhow::Context;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+use crate::{config::SieveSecurity, net::Stream};
+
+pub mod pull;
+pub mod push;
+pub mod script;
+
+pub const SIEVE_DIR: &str = "sieve";
+pub const SIEVE_EXTENSION: &str = "sieve";
+/// Holds the name of the active script
+pub const ACTIVE_FILE_NAME: &str = ".active";
+
+#[derive(Debug)]
+enum Token {
+    Atom(String),
+    String(Vec<u8>),
+}
+
+/// Minimal ManageSieve client (RFC 5804) with implicit TLS and STARTTLS support.
+pub struct SieveClient {
+    stream: BufStream<Box<dyn Stream>>,
+    capabilities: Vec<(String, String)>,
+}
+
+impl SieveClient {
+    pub async fn connect(
+        server: &str,
+        port: u16,
+        security: &SieveSecurity,
+    ) -> anyhow::Result<SieveClient> {
+        let tcp_stream = TcpStream::connect((server, port)).await?;
+        let tls = async_native_tls::TlsConnector::new();
+
+        let mut client = match security {
+            SieveSecurity::Tls => {
+                let tls_stream = tls.connect(server, tcp_stream).await?;
+                SieveClient::new(Box::new(tls_stream))
+            }
+            SieveSecurity::Starttls => {
+                let mut client = SieveClient::new(Box::new(tcp_stream));
+                client.read_capabilities().await?;
+                if !client.has_capability("STARTTLS") {
+                    anyhow::bail!("server doesn't support STARTTLS");
+                }
+                client.command("STARTTLS").await?;
+                let tcp_stream = client.stream.into_inner();
+                let tls_stream = tls.connect(server, tcp_stream).await?;
+                SieveClient::new(Box::new(tls_stream))
+            }
+            SieveSecurity::Plain => SieveClient::new(Box::new(tcp_stream)),
+        };
+
+        // Capabilities are sent again once TLS is negotiated
+        client.read_capabilities().await?;
+
+        Ok(client)
+    }
+
+    fn new(stream: Box<dyn Stream>) -> SieveClient {
+        SieveClient {
+            stream: BufStream::new(stream),
+            capabilities: Vec::new(),
+        }
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|(capability, _)| capability.eq_ignore_ascii_case(name))
+    }
+
+    pub async fn login(&mut self, user: &str, password: &str) -> anyhow::Result<()> {
+        let credentials = BASE64_STANDARD.encode(format!("\0{user}\0{password}"));
+        self.command(&format!("AUTHENTICATE \"PLAIN\" \"{credentials}\""))
+            .await
+            .context("authentication failed")?;
+        Ok(())
+    }
+
+    /// Returns script names with a flag set for the active one.
+    pub async fn list_scripts(&mut self) -> anyhow::Result<Vec<(String, bool)>> {
+        let lines = self.command("LISTSCRIPTS").await?;
+
+        let mut scripts = Vec::new();
+        for line in lines {
+            let mut tokens = line.into_iter();
+            let Some(Token::String(name)) = tokens.next() else {
+                anyhow::bail!("malformed LISTSCRIPTS response");
+            };
+            let active = match tokens.next() {
+                Some(Token::Atom(atom)) => atom.eq_ignore_ascii_case("ACTIVE"),
+                _ => false,
+            };
+            scripts.push((String::from_utf8_lossy(&name).to_string(), active));
+        }
+
+        Ok(scripts)
+    }
+
+    pub async fn get_script(&mut self, name: &str) -> anyhow::Result<Vec<u8>> {
+        let lines = self.command(&format!("GETSCRIPT {}", quote(name))).await?;
+        match lines.into_iter().flatten().next() {
+            Some(Token::String(script)) => Ok(script),
+            _ => anyhow::bail!("malformed GETSCRIPT response"),
+        }
+    }
+
+    pub async fn put_script(&mut self, name: &str, script: &[u8]) -> anyhow::Result<()> {
+        log::debug!("C: PUTSCRIPT {} {{{}+}}", quote(name), script.len());
+        self.stream
+            .write_all(format!("PUTSCRIPT {} {{{}+}}\r\n", quote(name), script.len()).as_bytes())
+            .await?;
+        self.stream.write_all(script).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        self.read_response().await?;
+        Ok(())
+    }
+
+    pub async fn set_active(&mut self, name: &str) -> anyhow::Result<()> {
+        self.command(&format!("SETACTIVE {}", quote(name))).await?;
+        Ok(())
+    }
+
+    pub async fn logout(&mut self) -> anyhow::Result<()> {
+        self.command("LOGOUT").await?;
+        Ok(())
+    }
+
+    async fn read_capabilities(&mut self) -> anyhow::Result<()> {
+        let lines = self.read_response().await?;
+
+        self.capabilities.clear();
+        for line in lines {
+            let mut values = line.into_iter().map(|token| match token {
+                Token::Atom(atom) => atom,
+                Token::String(value) => String::from_utf8_lossy(&value).to_string(),
+            });
+            if let Some(name) = values.next() {
+                self.capabilities
+                    .push((name, values.next().unwrap_or_default()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends a command and returns the response lines preceding `OK`.
+    async fn command(&mut self, command: &str) -> anyhow::Result<Vec<Vec<Token>>> {
+        if command.starts_with("AUTHENTICATE ") {
+            log::debug!("C: AUTHENTICATE ***");
+        } else {
+            log::debug!("C: {command}");
+        }
+        self.stream.write_all(command.as_bytes()).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        self.read_response().await
+    }
+
+    async fn read_response(&mut self) -> anyhow::Result<Vec<Vec<Token>>> {
+        let mut lines = Vec::new();
+
+        loop {
+            let line = self.read_line().await?;
+            let status = match line.first() {
+                Some(Token::Atom(atom)) => atom.to_uppercase(),
+                _ => String::new(),
+            };
+
+            match status.as_str() {
+                "OK" => return Ok(lines),
+                "NO" | "BYE" => {
+                    let message = line
+                        .iter()
+                        .rev()
+                        .find_map(|token| match token {
+                            Token::String(text) => Some(String::from_utf8_lossy(text).to_string()),
+                            Token::Atom(_) => None,
+                        })
+                        .unwrap_or(status);
+                    anyhow::bail!("server error: {}", message.trim());
+                }
+                _ => lines.push(line),
+            }
+        }
+    }
+
+    /// Reads a response line, literals it contains are read as strings.
+    async fn read_line(&mut self) -> anyhow::Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let mut line = Vec::new();
+            if self.stream.read_until(b'\n', &mut line).await? == 0 {
+                anyhow::bail!("connection closed by server");
+            }
+            while line.last().is_some_and(|c| *c == b'\n' || *c == b'\r') {
+                line.pop();
+            }
+            log::debug!("S: {}", String::from_utf8_lossy(&line));
+
+            let literal_size = parse_line(&line, &mut tokens)?;
+            match literal_size {
+                Some(size) => {
+                    let mut literal = vec![0; size];
+                    self.stream.read_exact(&mut literal).await?;
+                    tokens.push(Token::String(literal));
+                }
+                None => return Ok(tokens),
+            }
+        }
+    }
+}
+
+/// Splits a line into tokens, returns the size of a trailing literal.
+fn parse_line(line: &[u8], tokens: &mut Vec<Token>) -> anyhow::Result<Option<usize>> {
+    let mut i = 0;
+
+    while i < line.len() {
+        match line[i] {
+            b' ' => i += 1,
+            b'"' => {
+                let mut value = Vec::new();
+                i += 1;
+                while i < line.len() && line[i] != b'"' {
+                    if line[i] == b'\\' && i + 1 < line.len() {
+                        i += 1;
+                    }
+                    value.push(line[i]);
+                    i += 1;
+                }
+                i += 1;
+                tokens.push(Token::String(value));
+            }
+            b'{' => {
+                let end = line[i..]
+                    .iter()
+                    .position(|c| *c == b'}')
+                    .context("malformed literal")?;
+                let size = String::from_utf8_lossy(&line[i + 1..i + end]);
+                return Ok(Some(size.trim_end_matches('+').parse()?));
+            }
+            b'(' => {
+                let end = line[i..]
+                    .iter()
+                    .position(|c| *c == b')')
+                    .map(|end| i + end + 1)
+                    .unwrap_or(line.len());
+                tokens.push(Token::Atom(
+                    String::from_utf8_lossy(&line[i..end]).to_string(),
+                ));
+                i = end;
+            }
+            _ => {
+                let end = line[i..]
+                    .iter()
+                    .position(|c| *c == b' ')
+                    .map(|end| i + end)
+                    .unwrap_or(line.len());
+                tokens.push(Token::Atom(
+                    String::from_utf8_lossy(&line[i..end]).to_string(),
+                ));
+                i = end;
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
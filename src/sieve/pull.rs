@@ -0,0 +1,110 @@
+use anyhow::Context;
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use tokio::time::Instant;
+
+use super::{SieveClient, ACTIVE_FILE_NAME, SIEVE_DIR, SIEVE_EXTENSION};
+use crate::{config::Config, dav::sanitize_name};
+
+pub async fn pull(
+    config: &Config,
+    email: String,
+    password: String,
+    out_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let pull_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pulled_count = 0;
+
+    let sieve_config = config
+        .sieve
+        .clone()
+        .context("Sieve config is not provided in config.yaml")?
+        .pull
+        .context("Sieve pull server config not provided")?;
+    let sieve_server = sieve_config.server.clone();
+    let sieve_port = sieve_config.port.unwrap_or(4190);
+    let sieve_security = sieve_config.security.unwrap_or_default();
+
+    log::debug!("Pulling Sieve for account {email}...");
+    let mut client = SieveClient::connect(&sieve_server, sieve_port, &sieve_security).await?;
+    log::info!(
+        "Connected to ManageSieve server {}:{}",
+        sieve_server,
+        sieve_port
+    );
+
+    client.login(&email, &password).await?;
+    log::info!("Logged in as {}", email);
+
+    let scripts = client.list_scripts().await?;
+    log::info!("Loaded {} scripts", scripts.len());
+
+    let folder_name = format!("{out_dir}/{domain}/{email}/{SIEVE_DIR}");
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+    fs::create_dir_all(&folder_path)?;
+
+    log::info!("Folder {}", folder_path.display());
+
+    let active_file_path = folder_path.join(ACTIVE_FILE_NAME);
+    let mut active_script = None;
+
+    for (script_name, active) in scripts {
+        if pull_cancellation_token.is_cancelled() {
+            break;
+        }
+
+        log::debug!("Downloading script {script_name:?}");
+        let script = client.get_script(&script_name).await?;
+
+        let stored_name = sanitize_name(&script_name);
+        let file_name = format!("{stored_name}.{SIEVE_EXTENSION}");
+        fs::write(folder_path.join(&file_name), &script).context("unable to save *.sieve file")?;
+
+        if active {
+            log::info!("Script {script_name:?} is active");
+            active_script = Some(stored_name);
+        }
+
+        total_pulled_count += 1;
+
+        log::debug!("{} bytes script stored as {file_name}", script.len());
+    }
+
+    if !pull_cancellation_token.is_cancelled() {
+        match active_script {
+            Some(active_script) => fs::write(&active_file_path, active_script)
+                .context("unable to save active script name")?,
+            None if active_file_path.exists() => fs::remove_file(&active_file_path)?,
+            None => {}
+        }
+    }
+
+    client.logout().await?;
+
+    log::info!(
+        "Done in {:?}, {} scripts stored ok.",
+        start.elapsed(),
+        total_pulled_count
+    );
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use anyhow::Context;
+use human_bytes::human_bytes;
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use tokio::time::Instant;
+
+use super::{script, SieveClient, ACTIVE_FILE_NAME, SIEVE_DIR, SIEVE_EXTENSION};
+use crate::config::Config;
+
+pub async fn push(
+    config: &Config,
+    email: String,
+    password: String,
+    in_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let push_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pushed_count = 0;
+
+    let folder_name = format!("{in_dir}/{domain}/{email}/{SIEVE_DIR}");
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+
+    log::info!("Getting scripts in {}", folder_path.display());
+
+    let mut scripts = Vec::<(String, PathBuf)>::new();
+    if folder_path.is_dir() {
+        for entry in fs::read_dir(&folder_path)? {
+            let path = entry?.path();
+            if path.extension() == Some(SIEVE_EXTENSION.as_ref()) {
+                let script_name = path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                scripts.push((script_name, path));
+            }
+        }
+    }
+    scripts.sort();
+
+    log::info!("Found {} scripts", scripts.len());
+
+    if !scripts.is_empty() {
+        let sieve_config = config
+            .sieve
+            .clone()
+            .context("Sieve config is not provided in config.yaml")?
+            .push
+            .context("Sieve push server config not provided")?;
+        let sieve_server = sieve_config.server.clone();
+        let sieve_port = sieve_config.port.unwrap_or(4190);
+        let sieve_security = sieve_config.security.unwrap_or_default();
+
+        // Folders are renamed the same way `imap push` renames them
+        let imap_push_config = config.imap.clone().and_then(|imap_config| imap_config.push);
+        // Filters into dropped folders keep their name
+        let map_folder = |name: &str| {
+            imap_push_config
+                .as_ref()
+                .and_then(|imap_config| imap_config.map_folder_name(name))
+                .unwrap_or_else(|| name.to_string())
+        };
+        let folder_delimiter = imap_push_config
+            .as_ref()
+            .and_then(|imap_config| imap_config.folder_delimiter)
+            .unwrap_or('/')
+            .to_string();
+
+        let active_script = fs::read_to_string(folder_path.join(ACTIVE_FILE_NAME))
+            .ok()
+            .map(|active_script| active_script.trim().to_string());
+
+        log::debug!("Pushing Sieve for account {email}...");
+        let mut client = SieveClient::connect(&sieve_server, sieve_port, &sieve_security).await?;
+        log::info!(
+            "Connected to ManageSieve server {}:{}",
+            sieve_server,
+            sieve_port
+        );
+
+        client.login(&email, &password).await?;
+        log::info!("Logged in as {}", email);
+
+        let mut active_pushed = false;
+
+        for (script_name, script_path) in scripts {
+            if push_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            log::debug!("Pushing script {script_name:?}...");
+
+            let data = fs::read_to_string(&script_path)
+                .context(format!("unable to read {}", script_path.display()))?;
+            let data = script::rewrite_fileinto(&data, map_folder, &folder_delimiter);
+
+            match client.put_script(&script_name, data.as_bytes()).await {
+                Ok(_) => {
+                    if active_script.as_ref() == Some(&script_name) {
+                        active_pushed = true;
+                    }
+
+                    total_pushed_count += 1;
+
+                    log::debug!("{} sent ok", human_bytes(data.len() as f64));
+                }
+                Err(err) => log::warn!("Error pushing script {}: {:#}", script_name, err),
+            }
+        }
+
+        if let (true, Some(active_script)) = (active_pushed, active_script) {
+            client.set_active(&active_script).await?;
+            log::info!("Script {active_script:?} activated");
+        }
+
+        client.logout().await?;
+    }
+
+    log::info!(
+        "Done in {:?}, {} scripts uploaded ok.",
+        start.elapsed(),
+        total_pushed_count
+    );
+
+    Ok(())
+}
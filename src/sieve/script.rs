@@ -0,0 +1,190 @@
+//! Rewrites mailbox names referenced by `fileinto` (RFC 5228) so filters keep
+//! working after folders were renamed on the target server.
+
+use super::quote;
+
+/// Maps the mailbox argument of every `fileinto` command with `map_folder`.
+/// Quoted strings, comments and the rest of the script are left untouched.
+pub fn rewrite_fileinto(
+    script: &str,
+    map_folder: impl Fn(&str) -> String,
+    folder_delimiter: &str,
+) -> String {
+    let bytes = script.as_bytes();
+    let mut result = String::with_capacity(script.len());
+    let mut copied = 0;
+    let mut i = 0;
+
+    // Byte range of the last string argument of the current fileinto
+    let mut in_fileinto = false;
+    let mut mailbox: Option<(usize, usize)> = None;
+    let mut list_depth = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                i = find(bytes, i, b"\n").unwrap_or(bytes.len());
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = find(bytes, i + 2, b"*/").map_or(bytes.len(), |end| end + 2);
+            }
+            b'"' => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i = (i + 1).min(bytes.len());
+                if in_fileinto && list_depth == 0 {
+                    mailbox = Some((start, i));
+                }
+            }
+            b'[' => {
+                list_depth += 1;
+                i += 1;
+            }
+            b']' => {
+                list_depth -= 1;
+                i += 1;
+            }
+            b';' | b'{' | b'}' => {
+                if let (true, Some((start, end))) = (in_fileinto, mailbox.take()) {
+                    let name = unquote(&script[start + 1..end - 1]);
+                    let mapped_name = map_folder(&name).replace("/", folder_delimiter);
+                    if mapped_name != name {
+                        log::debug!("fileinto {name:?} rewritten to {mapped_name:?}");
+                        result.push_str(&script[copied..start]);
+                        result.push_str(&quote(&mapped_name));
+                        copied = end;
+                    }
+                }
+                in_fileinto = false;
+                i += 1;
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                // Tagged arguments like :copy are preceded by a colon
+                let tagged = start > 0 && bytes[start - 1] == b':';
+                if !tagged && script[start..i].eq_ignore_ascii_case("fileinto") {
+                    in_fileinto = true;
+                    mailbox = None;
+                    list_depth = 0;
+                } else if script[start..i].eq_ignore_ascii_case("text")
+                    && bytes.get(i) == Some(&b':')
+                {
+                    // Multi-line string runs until a line holding a single dot
+                    i = [find(bytes, i, b"\n.\r\n"), find(bytes, i, b"\n.\n")]
+                        .into_iter()
+                        .flatten()
+                        .min()
+                        .map_or(bytes.len(), |end| end + 3);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+
+    result.push_str(&script[copied..]);
+    result
+}
+
+fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes[from.min(bytes.len())..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+fn unquote(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(c) = chars.next() {
+                result.push(c);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(name: &str) -> String {
+        match name {
+            "Old" => "New".to_string(),
+            "INBOX.Work" => "Work/Projects".to_string(),
+            "a\"b\\c" => "d\"e".to_string(),
+            name => name.to_string(),
+        }
+    }
+
+    #[test]
+    fn rewrites_fileinto_mailboxes() {
+        let script = "require \"fileinto\";\r\nif header :contains \"subject\" \"report\" {\r\n  FileInto \"INBOX.Work\";\r\n} else { fileinto \"Old\" }\r\n";
+        assert_eq!(
+            rewrite_fileinto(script, rename, "."),
+            "require \"fileinto\";\r\nif header :contains \"subject\" \"report\" {\r\n  FileInto \"Work.Projects\";\r\n} else { fileinto \"New\" }\r\n"
+        );
+        assert_eq!(
+            rewrite_fileinto(script, |name| name.to_string(), "."),
+            script
+        );
+    }
+
+    #[test]
+    fn skips_comments() {
+        let script =
+            "# fileinto \"Old\";\n/* fileinto \"Old\";\n fileinto \"Old\"; */ fileinto \"Old\";\n";
+        assert_eq!(
+            rewrite_fileinto(script, rename, "/"),
+            "# fileinto \"Old\";\n/* fileinto \"Old\";\n fileinto \"Old\"; */ fileinto \"New\";\n"
+        );
+    }
+
+    #[test]
+    fn handles_escapes_in_quoted_strings() {
+        let script =
+            r#"if header :is "subject" "say \"fileinto \\\"Old\"; \\" { fileinto "a\"b\\c"; }"#;
+        assert_eq!(
+            rewrite_fileinto(script, rename, "/"),
+            r#"if header :is "subject" "say \"fileinto \\\"Old\"; \\" { fileinto "d\"e"; }"#
+        );
+    }
+
+    #[test]
+    fn skips_multi_line_strings() {
+        let script = "vacation text:\nfileinto \"Old\";\n..still text;\n.\n;\nfileinto \"Old\";\n";
+        assert_eq!(
+            rewrite_fileinto(script, rename, "/"),
+            "vacation text:\nfileinto \"Old\";\n..still text;\n.\n;\nfileinto \"New\";\n"
+        );
+        let script = "reject text:\r\nfileinto \"Old\";\r\n.\r\n;\r\nfileinto \"Old\";\r\n";
+        assert_eq!(
+            rewrite_fileinto(script, rename, "/"),
+            "reject text:\r\nfileinto \"Old\";\r\n.\r\n;\r\nfileinto \"New\";\r\n"
+        );
+    }
+
+    #[test]
+    fn rewrites_only_the_mailbox_argument() {
+        let script =
+            "fileinto :copy \"Old\"; fileinto :flags [\"Old\", \"\\\\Seen\"] :copy \"Old\";";
+        assert_eq!(
+            rewrite_fileinto(script, rename, "/"),
+            "fileinto :copy \"New\"; fileinto :flags [\"Old\", \"\\\\Seen\"] :copy \"New\";"
+        );
+        let script = "if exists \"x\" { redirect :copy \"Old\"; keep; }";
+        assert_eq!(rewrite_fileinto(script, rename, "/"), script);
+    }
+}
@@ -0,0 +1,255 @@
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+use crate::{
+    config::{DeliveryProtocol, SmtpSecurity},
+    net::Stream,
+};
+
+pub mod push;
+
+/// Reply of the server that rejected a command.
+#[derive(Debug)]
+pub struct SmtpError {
+    pub code: u16,
+    pub message: String,
+}
+
+impl SmtpError {
+    /// 4xx replies are temporary, the message may be delivered on the next run.
+    pub fn is_transient(&self) -> bool {
+        (400..500).contains(&self.code)
+    }
+}
+
+impl std::fmt::Display for SmtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for SmtpError {}
+
+/// Minimal SMTP (RFC 5321) and LMTP (RFC 2033) client for message delivery.
+pub struct SmtpClient {
+    stream: BufStream<Box<dyn Stream>>,
+    protocol: DeliveryProtocol,
+}
+
+impl SmtpClient {
+    pub async fn connect(
+        server: &str,
+        port: u16,
+        protocol: &DeliveryProtocol,
+        security: &SmtpSecurity,
+    ) -> anyhow::Result<SmtpClient> {
+        let tcp_stream = TcpStream::connect((server, port)).await?;
+        let tls = async_native_tls::TlsConnector::new();
+
+        let mut client = match security {
+            SmtpSecurity::Tls => {
+                let tls_stream = tls.connect(server, tcp_stream).await?;
+                SmtpClient::new(Box::new(tls_stream), protocol)
+            }
+            SmtpSecurity::Starttls => {
+                let mut client = SmtpClient::new(Box::new(tcp_stream), protocol);
+                client.read_reply().await?;
+                let extensions = client.hello().await?;
+                if !extensions
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case("STARTTLS"))
+                {
+                    anyhow::bail!("server doesn't support STARTTLS");
+                }
+                client.command("STARTTLS").await?;
+                let tcp_stream = client.stream.into_inner();
+                let tls_stream = tls.connect(server, tcp_stream).await?;
+                let mut client = SmtpClient::new(Box::new(tls_stream), protocol);
+                client.hello().await?;
+                return Ok(client);
+            }
+            SmtpSecurity::Plain => SmtpClient::new(Box::new(tcp_stream), protocol),
+        };
+
+        client.read_reply().await?;
+        client.hello().await?;
+
+        Ok(client)
+    }
+
+    fn new(stream: Box<dyn Stream>, protocol: &DeliveryProtocol) -> SmtpClient {
+        SmtpClient {
+            stream: BufStream::new(stream),
+            protocol: protocol.clone(),
+        }
+    }
+
+    /// Sends EHLO or LHLO and returns the advertised extensions.
+    async fn hello(&mut self) -> anyhow::Result<Vec<String>> {
+        let verb = match self.protocol {
+            DeliveryProtocol::Smtp => "EHLO",
+            DeliveryProtocol::Lmtp => "LHLO",
+        };
+        let lines = self.command(&format!("{verb} off-the-cloud")).await?;
+        Ok(lines.into_iter().skip(1).collect())
+    }
+
+    /// Delivers a message to a single recipient with the null reverse-path.
+    pub async fn send(&mut self, recipient: &str, data: &[u8]) -> anyhow::Result<()> {
+        let result = self.transaction(recipient, data).await;
+        if result.is_err() {
+            // Leaves the session ready for the next message
+            self.command("RSET").await.ok();
+        }
+        result
+    }
+
+    async fn transaction(&mut self, recipient: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.command("MAIL FROM:<>").await?;
+        self.command(&format!("RCPT TO:<{recipient}>")).await?;
+        self.command("DATA").await?;
+
+        log::debug!("C: <{} bytes>", data.len());
+        self.stream.write_all(&dot_stuff(data)).await?;
+        self.stream.write_all(b".\r\n").await?;
+        self.stream.flush().await?;
+        // LMTP replies once per recipient, there is exactly one here
+        self.read_reply().await?;
+
+        Ok(())
+    }
+
+    pub async fn quit(&mut self) -> anyhow::Result<()> {
+        self.command("QUIT").await?;
+        Ok(())
+    }
+
+    /// Sends a command and returns the reply lines.
+    async fn command(&mut self, command: &str) -> anyhow::Result<Vec<String>> {
+        log::debug!("C: {command}");
+        self.stream.write_all(command.as_bytes()).await?;
+        self.stream.write_all(b"\r\n").await?;
+        self.stream.flush().await?;
+        self.read_reply().await
+    }
+
+    /// Reads a possibly multi-line reply, fails on 4xx and 5xx codes.
+    async fn read_reply(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut lines = Vec::new();
+
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                anyhow::bail!("connection closed by server");
+            }
+            let line = line.trim_end();
+            log::debug!("S: {line}");
+
+            let Some(code) = line
+                .get(..3)
+                .filter(|code| code.bytes().all(|c| c.is_ascii_digit()))
+                .and_then(|code| code.parse::<u16>().ok())
+            else {
+                anyhow::bail!("malformed reply {line:?}");
+            };
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+
+            if line.as_bytes().get(3) != Some(&b'-') {
+                if code >= 400 {
+                    return Err(SmtpError {
+                        code,
+                        message: lines.join(" "),
+                    }
+                    .into());
+                }
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+/// Normalizes line endings to CRLF and escapes lines starting with a dot.
+fn dot_stuff(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + 1024);
+    let mut line_start = true;
+
+    for (i, c) in data.iter().enumerate() {
+        if line_start && *c == b'.' {
+            result.push(b'.');
+        }
+        match c {
+            b'\n' if i == 0 || data[i - 1] != b'\r' => result.extend_from_slice(b"\r\n"),
+            _ => result.push(*c),
+        }
+        line_start = *c == b'\n';
+    }
+
+    if !line_start {
+        result.extend_from_slice(b"\r\n");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    /// Client reading the given replies, then the end of the connection.
+    async fn mock_client(replies: &str) -> SmtpClient {
+        let (client_stream, mut server_stream) = duplex(4096);
+        server_stream.write_all(replies.as_bytes()).await.unwrap();
+        SmtpClient::new(Box::new(client_stream), &DeliveryProtocol::Smtp)
+    }
+
+    #[tokio::test]
+    async fn read_reply_multiline() {
+        let mut client =
+            mock_client("250-mail.example.com\r\n250-PIPELINING\r\n250 STARTTLS\r\n").await;
+        assert_eq!(
+            client.read_reply().await.unwrap(),
+            vec!["mail.example.com", "PIPELINING", "STARTTLS"]
+        );
+    }
+
+    #[tokio::test]
+    async fn read_reply_error_codes() {
+        let mut client = mock_client("452-4.2.2 Mailbox\r\n452 full\r\n").await;
+        let err = client.read_reply().await.unwrap_err();
+        let err = err.downcast_ref::<SmtpError>().unwrap();
+        assert_eq!(err.code, 452);
+        assert_eq!(err.message, "4.2.2 Mailbox full");
+        assert!(err.is_transient());
+
+        let mut client = mock_client("550 5.1.1 unknown user\r\n").await;
+        let err = client.read_reply().await.unwrap_err();
+        assert!(!err.downcast_ref::<SmtpError>().unwrap().is_transient());
+    }
+
+    #[tokio::test]
+    async fn read_reply_malformed() {
+        for reply in ["2\r\n", "ä5 ok\r\n", "25ä ok\r\n", "+25 ok\r\n"] {
+            let mut client = mock_client(reply).await;
+            let err = client.read_reply().await.unwrap_err();
+            assert!(err.to_string().starts_with("malformed reply"), "{reply:?}");
+        }
+
+        let mut client = mock_client("").await;
+        let err = client.read_reply().await.unwrap_err();
+        assert_eq!(err.to_string(), "connection closed by server");
+    }
+
+    #[test]
+    fn dot_stuff_escapes_dots_and_normalizes_line_endings() {
+        assert_eq!(
+            dot_stuff(b".a\n..b\r\nc.\n.\n"),
+            b"..a\r\n...b\r\nc.\r\n..\r\n"
+        );
+        assert_eq!(dot_stuff(b"no newline"), b"no newline\r\n");
+        assert_eq!(dot_stuff(b"\n.x"), b"\r\n..x\r\n");
+        assert_eq!(dot_stuff(b""), b"");
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::Context;
+use async_walkdir::{Filtering, WalkDir};
+use futures_lite::stream::StreamExt;
+use human_bytes::human_bytes;
+use std::{env::current_dir, fs, path::PathBuf, str::FromStr};
+use tokio::time::Instant;
+
+use super::{SmtpClient, SmtpError};
+use crate::config::{Config, DeliveryProtocol};
+
+pub async fn push(config: &Config, email: String, in_dir: String) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let push_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pushed_count = 0;
+
+    let folder_name = format!("{in_dir}/{domain}/{email}/",);
+    let folder_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+    let folder_path = folder_path.to_str().context("wrong in_dir path")?;
+
+    log::info!("Getting mailboxes in {}", folder_path);
+
+    let mut mailboxes = Vec::<(String, String)>::new();
+
+    let mut entries = WalkDir::new(folder_path).filter(|entry| async move {
+        match entry.file_type().await {
+            Ok(file_type) => {
+                if file_type.is_dir() {
+                    Filtering::Continue
+                } else {
+                    Filtering::Ignore
+                }
+            }
+            Err(_) => Filtering::Continue,
+        }
+    });
+
+    while !push_cancellation_token.is_cancelled() {
+        match entries.next().await {
+            Some(Ok(entry)) => {
+                let mailbox_path = entry.path().to_str().unwrap_or_default().to_string();
+                let mailbox_name = mailbox_path[folder_path.len()..].to_string();
+                mailboxes.push((mailbox_name, mailbox_path));
+            }
+            Some(Err(e)) => {
+                log::warn!("Error reading dir: {}", e);
+                break;
+            }
+            None => break,
+        }
+    }
+
+    log::info!("Found {} mailboxes", mailboxes.len());
+
+    if !mailboxes.is_empty() {
+        let smtp_config = config
+            .smtp
+            .clone()
+            .context("SMTP config is not provided in config.yaml")?
+            .push
+            .context("SMTP push server config not provided")?;
+        let smtp_server = smtp_config.server.clone();
+        let smtp_protocol = smtp_config.protocol.clone().unwrap_or_default();
+        let (smtp_protocol_name, smtp_default_port) = match smtp_protocol {
+            DeliveryProtocol::Smtp => ("SMTP", 25),
+            DeliveryProtocol::Lmtp => ("LMTP", 24),
+        };
+        let smtp_port = smtp_config.port.unwrap_or(smtp_default_port);
+        let smtp_security = smtp_config.security.clone().unwrap_or_default();
+        let folder_delimiter = &smtp_config.folder_delimiter.unwrap_or('/').to_string();
+
+        log::debug!("Pushing {smtp_protocol_name} for account {email}...");
+        let mut client =
+            SmtpClient::connect(&smtp_server, smtp_port, &smtp_protocol, &smtp_security).await?;
+        log::info!(
+            "Connected to {} server {}:{}",
+            smtp_protocol_name,
+            smtp_server,
+            smtp_port
+        );
+
+        for (mailbox_name, mailbox_path) in mailboxes {
+            if push_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            let Some(mailbox_mapped_name) = smtp_config.map_folder_name(&mailbox_name) else {
+                log::info!("Skipping mailbox {mailbox_name}, dropped by folder_rules");
+                continue;
+            };
+            let target_folder_name = mailbox_mapped_name.replace("/", folder_delimiter);
+
+            // Messages of the inbox are delivered as is
+            let target_folder = if target_folder_name.eq_ignore_ascii_case("INBOX") {
+                None
+            } else {
+                Some(target_folder_name)
+            };
+            let (recipient, folder_header) = match (&target_folder, &smtp_config.folder_header) {
+                (Some(target_folder), Some(header_name)) => (
+                    email.clone(),
+                    Some(format!("{header_name}: {target_folder}\r\n")),
+                ),
+                (Some(target_folder), None) => (subaddress(&email, target_folder)?, None),
+                (None, _) => (email.clone(), None),
+            };
+
+            log::info!(
+                "Processing mailbox {} (recipient {})",
+                mailbox_mapped_name,
+                recipient
+            );
+
+            let mut eml_files = Vec::new();
+            for entry in fs::read_dir(&mailbox_path)? {
+                let path = entry?.path();
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                if path.extension() == Some("eml".as_ref()) && file_name.starts_with('.') {
+                    eml_files.push(path);
+                }
+            }
+            eml_files.sort();
+
+            let mut pushed_count = 0;
+
+            for eml_file_path in eml_files {
+                if push_cancellation_token.is_cancelled() {
+                    break;
+                }
+
+                let eml_file_name = eml_file_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string();
+                let new_eml_file_name = eml_file_name.trim_start_matches('.');
+
+                log::debug!(
+                    "Pushing message {} to {}...",
+                    new_eml_file_name,
+                    mailbox_mapped_name
+                );
+
+                let mut data = Vec::new();
+                if let Some(ref folder_header) = folder_header {
+                    data.extend_from_slice(folder_header.as_bytes());
+                }
+                data.extend_from_slice(&fs::read(&eml_file_path)?);
+                let size = data.len();
+
+                match client.send(&recipient, &data).await {
+                    Ok(_) => {
+                        fs::rename(
+                            &eml_file_path,
+                            eml_file_path.with_file_name(new_eml_file_name),
+                        )?;
+
+                        pushed_count += 1;
+                        total_pushed_count += 1;
+
+                        log::debug!("{} sent ok", human_bytes(size as f64));
+                    }
+                    Err(err) => match err.downcast_ref::<SmtpError>() {
+                        Some(smtp_error) if smtp_error.is_transient() => {
+                            log::warn!("Message {} deferred: {}", new_eml_file_name, smtp_error)
+                        }
+                        Some(smtp_error) => {
+                            log::warn!("Message {} rejected: {}", new_eml_file_name, smtp_error)
+                        }
+                        // Connection level failures make the rest of the run pointless
+                        None => return Err(err),
+                    },
+                }
+            }
+
+            log::info!("Delivered {pushed_count} messages to {mailbox_mapped_name}");
+        }
+
+        client.quit().await?;
+    }
+
+    log::info!(
+        "Done in {:?}, {} messages delivered ok.",
+        start.elapsed(),
+        total_pushed_count
+    );
+
+    Ok(())
+}
+
+/// Builds `user+folder@domain`, quoting the local part when the folder name
+/// contains characters not allowed in a dot-atom.
+fn subaddress(email: &str, folder: &str) -> anyhow::Result<String> {
+    let (local_part, domain) = email.rsplit_once('@').context("wrong email address")?;
+    let local_part = format!("{local_part}+{folder}");
+
+    let is_atom = local_part.split('.').all(|atom| {
+        !atom.is_empty()
+            && atom
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+    });
+
+    if is_atom {
+        Ok(format!("{local_part}@{domain}"))
+    } else {
+        let quoted = local_part.replace('\\', "\\\\").replace('"', "\\\"");
+        Ok(format!("\"{quoted}\"@{domain}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subaddress_uses_dot_atom_when_possible() {
+        assert_eq!(
+            subaddress("user@example.com", "Work").unwrap(),
+            "user+Work@example.com"
+        );
+        assert_eq!(
+            subaddress("first.last@example.com", "a.b").unwrap(),
+            "first.last+a.b@example.com"
+        );
+    }
+
+    #[test]
+    fn subaddress_quotes_other_folders() {
+        assert_eq!(
+            subaddress("user@example.com", "My Folder").unwrap(),
+            "\"user+My Folder\"@example.com"
+        );
+        assert_eq!(
+            subaddress("user@example.com", "a\"b\\c").unwrap(),
+            "\"user+a\\\"b\\\\c\"@example.com"
+        );
+        assert_eq!(
+            subaddress("user@example.com", "Sent.").unwrap(),
+            "\"user+Sent.\"@example.com"
+        );
+    }
+
+    #[test]
+    fn subaddress_requires_domain() {
+        assert!(subaddress("user", "Work").is_err());
+    }
+}
@@ -0,0 +1,312 @@
+//! Migration progress of accounts read from the local `{in_dir}/{domain}/{email}`
+//! trees, no server is contacted.
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use human_bytes::human_bytes;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{archive::absolute_path, args::OutputFormat};
+
+#[derive(Debug, Default, Serialize)]
+pub struct AccountStatus {
+    pub email: String,
+    pub domain: String,
+    /// `true` once every pulled message was pushed
+    pub complete: bool,
+    pub pulled: usize,
+    pub pending: usize,
+    pub pushed: usize,
+    pub bytes: u64,
+    pub last_modified: Option<DateTime<Local>>,
+    pub folders: Vec<FolderStatus>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FolderStatus {
+    pub name: String,
+    pub pulled: usize,
+    pub pending: usize,
+    pub pushed: usize,
+    pub bytes: u64,
+    /// Latest change of messages and state files of the folder
+    pub last_modified: Option<DateTime<Local>>,
+}
+
+pub async fn status(
+    in_dir: String,
+    domain: Option<String>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let in_path = absolute_path(&in_dir);
+    if !in_path.is_dir() {
+        anyhow::bail!("no messages in {}", in_path.display());
+    }
+
+    let mut accounts = Vec::new();
+    for domain_path in sorted_dirs(&in_path)? {
+        let domain_name = file_name(&domain_path);
+        if domain.as_ref().is_some_and(|domain| *domain != domain_name) {
+            continue;
+        }
+
+        for account_path in sorted_dirs(&domain_path)? {
+            accounts.push(account_status(&domain_name, &account_path)?);
+        }
+    }
+
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&accounts)?),
+        OutputFormat::Table => print_table(&accounts),
+    }
+
+    Ok(())
+}
+
+/// Totals of the folders of an account, complete once messages were pushed
+/// and none are pending.
+fn account_status(domain: &str, account_path: &Path) -> anyhow::Result<AccountStatus> {
+    let mut account = AccountStatus {
+        email: file_name(account_path),
+        domain: domain.to_string(),
+        ..Default::default()
+    };
+    collect_folders(account_path, account_path, &mut account.folders)?;
+
+    for folder in account.folders.iter() {
+        account.pulled += folder.pulled;
+        account.pending += folder.pending;
+        account.pushed += folder.pushed;
+        account.bytes += folder.bytes;
+        account.last_modified = account.last_modified.max(folder.last_modified);
+    }
+    account.complete = account.pending == 0 && account.pushed > 0;
+
+    Ok(account)
+}
+
+/// Adds folders below `path` holding messages or state files, named relative
+/// to the account.
+fn collect_folders(
+    account_path: &Path,
+    path: &Path,
+    folders: &mut Vec<FolderStatus>,
+) -> anyhow::Result<()> {
+    let mut folder = FolderStatus {
+        name: path
+            .strip_prefix(account_path)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string(),
+        ..Default::default()
+    };
+    let mut has_files = false;
+
+    for entry in fs::read_dir(path).context(format!("unable to read {}", path.display()))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        has_files = true;
+
+        let modified = metadata.modified().ok().map(DateTime::<Local>::from);
+        folder.last_modified = folder.last_modified.max(modified);
+
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(".eml") {
+            continue;
+        }
+        folder.pulled += 1;
+        folder.bytes += metadata.len();
+        if file_name.starts_with('.') {
+            folder.pending += 1;
+        } else {
+            folder.pushed += 1;
+        }
+    }
+
+    if has_files && !folder.name.is_empty() {
+        folders.push(folder);
+    }
+
+    for dir_path in sorted_dirs(path)? {
+        collect_folders(account_path, &dir_path, folders)?;
+    }
+
+    Ok(())
+}
+
+fn print_table(accounts: &[AccountStatus]) {
+    let name_width = accounts
+        .iter()
+        .flat_map(|account| {
+            std::iter::once(account.email.len())
+                .chain(account.folders.iter().map(|folder| folder.name.len() + 2))
+        })
+        .chain(std::iter::once("ACCOUNT / FOLDER".len()))
+        .max()
+        .unwrap_or_default();
+
+    println!(
+        "{:<name_width$}    PULLED   PENDING    PUSHED        SIZE  LAST CHANGE       STATE",
+        "ACCOUNT / FOLDER"
+    );
+    for account in accounts {
+        let state = if account.complete {
+            "complete"
+        } else if account.pushed > 0 {
+            "pushing"
+        } else if account.pulled > 0 {
+            "pulled"
+        } else {
+            "empty"
+        };
+        println!(
+            "{:<name_width$}  {:>8}  {:>8}  {:>8}  {:>10}  {:<16}  {}",
+            account.email,
+            account.pulled,
+            account.pending,
+            account.pushed,
+            human_bytes(account.bytes as f64),
+            format_time(account.last_modified),
+            state
+        );
+
+        for folder in account.folders.iter() {
+            println!(
+                "{:<name_width$}  {:>8}  {:>8}  {:>8}  {:>10}  {:<16}",
+                format!("  {}", folder.name),
+                folder.pulled,
+                folder.pending,
+                folder.pushed,
+                human_bytes(folder.bytes as f64),
+                format_time(folder.last_modified)
+            );
+        }
+    }
+}
+
+fn format_time(time: Option<DateTime<Local>>) -> String {
+    time.map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn sorted_dirs(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(path).context(format!("unable to read {}", path.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+
+    Ok(dirs)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCOUNT_DIR: &str = "example.com/user@example.com";
+
+    fn write(in_path: &Path, path: &str, data: &str) {
+        let file_path = in_path.join(ACCOUNT_DIR).join(path);
+        fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        fs::write(file_path, data).unwrap();
+    }
+
+    fn folder_counts(account: &AccountStatus) -> Vec<(&str, usize, usize, usize, u64)> {
+        account
+            .folders
+            .iter()
+            .map(|folder| {
+                (
+                    folder.name.as_str(),
+                    folder.pulled,
+                    folder.pending,
+                    folder.pushed,
+                    folder.bytes,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn account_status_counts_pending_and_pushed_messages_by_folder() {
+        let in_dir = tempfile::tempdir().unwrap();
+        write(in_dir.path(), "INBOX/00000001.eml", "pushed");
+        write(in_dir.path(), "INBOX/.00000002.eml", "pending");
+        write(in_dir.path(), "INBOX/.imap-pull", "{}");
+        write(in_dir.path(), "Work/Projects/.00000001.eml", "p");
+        write(in_dir.path(), "Work/Projects/notes.txt", "ignored");
+        write(in_dir.path(), "Archive/.imap-pull", "{}");
+        fs::create_dir_all(in_dir.path().join(ACCOUNT_DIR).join("Empty")).unwrap();
+
+        let account = account_status("example.com", &in_dir.path().join(ACCOUNT_DIR)).unwrap();
+
+        assert_eq!(account.email, "user@example.com");
+        assert_eq!(account.domain, "example.com");
+        assert_eq!(
+            folder_counts(&account),
+            vec![
+                ("Archive", 0, 0, 0, 0),
+                ("INBOX", 2, 1, 1, 13),
+                ("Work/Projects", 1, 1, 0, 1),
+            ]
+        );
+        assert_eq!(
+            (
+                account.pulled,
+                account.pending,
+                account.pushed,
+                account.bytes
+            ),
+            (3, 2, 1, 14)
+        );
+        assert!(account.last_modified.is_some());
+        assert!(!account.complete);
+    }
+
+    #[test]
+    fn account_status_is_complete_once_nothing_is_pending() {
+        let in_dir = tempfile::tempdir().unwrap();
+        let account_path = in_dir.path().join(ACCOUNT_DIR);
+        write(in_dir.path(), "INBOX/.imap-pull", "{}");
+        assert!(
+            !account_status("example.com", &account_path)
+                .unwrap()
+                .complete
+        );
+
+        write(in_dir.path(), "INBOX/.00000001.eml", "pending");
+        assert!(
+            !account_status("example.com", &account_path)
+                .unwrap()
+                .complete
+        );
+
+        fs::rename(
+            account_path.join("INBOX/.00000001.eml"),
+            account_path.join("INBOX/00000001.eml"),
+        )
+        .unwrap();
+        assert!(
+            account_status("example.com", &account_path)
+                .unwrap()
+                .complete
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Context;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+/// Returns the highest message number among `*.eml` files in the folder, both
+/// pending (`.00000001.eml`) and pushed (`00000001.eml`).
+pub fn last_message_id(folder_path: &Path) -> anyhow::Result<usize> {
+    let mut last_message_id = 0;
+
+    for entry in fs::read_dir(folder_path)? {
+        let path = entry?.path();
+        if path.extension() != Some("eml".as_ref()) {
+            continue;
+        }
+        let message_id = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .trim_start_matches('.')
+            .parse()
+            .unwrap_or(0);
+        last_message_id = last_message_id.max(message_id);
+    }
+
+    Ok(last_message_id)
+}
+
+/// Loads server-side ids of already downloaded messages from a state file
+/// with one `{id} {file_name}` entry per line.
+pub fn load_pulled_ids(state_file_path: &Path) -> anyhow::Result<HashSet<String>> {
+    if !state_file_path.exists() {
+        return Ok(HashSet::new());
+    }
+
+    let data = fs::read_to_string(state_file_path).context(format!(
+        "unable to read state file {}",
+        state_file_path.display()
+    ))?;
+    Ok(data
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(|id| id.to_string())
+        .collect())
+}
+
+/// Loads ETags of already downloaded resources from a state file with one
+/// `{name}\t{etag}` entry per line. Later entries win, so the file can be
+/// appended to as resources change.
+pub fn load_etags(state_file_path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    if !state_file_path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let data = fs::read_to_string(state_file_path).context(format!(
+        "unable to read state file {}",
+        state_file_path.display()
+    ))?;
+    Ok(data
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, etag)| (name.to_string(), etag.to_string()))
+        .collect())
+}
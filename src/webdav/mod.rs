@@ -0,0 +1,12 @@
+pub mod pull;
+pub mod push;
+
+/// Folder inside the account directory mirroring the WebDAV collection.
+pub const FILES_DIR: &str = "files";
+
+/// Keeps ETags of downloaded files, one `{path}\t{etag}` per line. Stored in
+/// the account directory so that it is not mirrored itself.
+pub const PULL_STATE_FILE_NAME: &str = ".webdav-etags";
+
+/// Keeps `{mtime} {size}` of uploaded files, one `{path}\t{mtime} {size}` per line.
+pub const PUSH_STATE_FILE_NAME: &str = ".webdav-pushed";
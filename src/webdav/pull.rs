@@ -0,0 +1,268 @@
+use anyhow::Context;
+use human_bytes::human_bytes;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use std::{
+    collections::{HashSet, VecDeque},
+    env::current_dir,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    time::SystemTime,
+};
+use tokio::time::Instant;
+
+use super::{FILES_DIR, PULL_STATE_FILE_NAME};
+use crate::{config::Config, dav::DavClient, storage::load_etags};
+
+pub async fn pull(
+    config: &Config,
+    email: String,
+    password: String,
+    out_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let pull_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pulled_count = 0;
+    let mut total_pulled_bytes = 0u64;
+
+    let webdav_config = config
+        .webdav
+        .clone()
+        .context("WebDAV config is not provided in config.yaml")?
+        .pull
+        .context("WebDAV pull server config not provided")?;
+    let root_url = collection_url(&webdav_config.url, &email)?;
+
+    let folder_name = format!("{out_dir}/{domain}/{email}");
+    let account_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+    let files_path = account_path.join(FILES_DIR);
+    fs::create_dir_all(&files_path)?;
+
+    log::info!("Mirroring {} to {}", root_url, files_path.display());
+
+    let state_file_path = account_path.join(PULL_STATE_FILE_NAME);
+    let etags = load_etags(&state_file_path)?;
+    let mut state_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&state_file_path)
+        .context("unable to open WebDAV state file")?;
+
+    let client = DavClient::new(&email, &password);
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getetag/><d:getlastmodified/><d:getcontentlength/></d:prop></d:propfind>"#;
+
+    // Depth: infinity is disabled on most servers, so the tree is walked level by level
+    let mut collections = VecDeque::from([root_url.clone()]);
+    // Servers may list a collection under another spelling of its path, e.g.
+    // without the trailing slash, which must not be walked twice
+    let mut visited = HashSet::from([normalized_path(&root_url)]);
+
+    while let Some(collection_url) = collections.pop_front() {
+        if pull_cancellation_token.is_cancelled() {
+            break;
+        }
+
+        log::debug!("Listing {collection_url}");
+        let resources = client.propfind(&collection_url, "1", body).await?;
+
+        for resource in resources {
+            if pull_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            if normalized_path(&resource.url) == normalized_path(&collection_url) {
+                continue;
+            }
+
+            let relative_path = relative_path(&root_url, &resource.url)?;
+            let local_path = files_path.join(&relative_path);
+
+            if resource.is("collection") {
+                fs::create_dir_all(&local_path)?;
+                if visited.insert(normalized_path(&resource.url)) {
+                    collections.push_back(resource.url.clone());
+                }
+                continue;
+            }
+
+            let etag = resource.prop("getetag").unwrap_or_default().to_string();
+            if !etag.is_empty() && etags.get(&relative_path) == Some(&etag) && local_path.exists() {
+                continue;
+            }
+
+            log::debug!("Downloading {relative_path}");
+            if let Some(parent) = local_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            client.download(&resource.url, &local_path).await?;
+
+            if let Some(modified) = resource
+                .prop("getlastmodified")
+                .and_then(|modified| chrono::DateTime::parse_from_rfc2822(modified).ok())
+            {
+                let modified: SystemTime = modified.into();
+                File::options()
+                    .write(true)
+                    .open(&local_path)?
+                    .set_modified(modified)
+                    .context(format!(
+                        "unable to set modification time of {relative_path}"
+                    ))?;
+            }
+
+            writeln!(state_file, "{relative_path}\t{etag}")
+                .context("unable to update WebDAV state file")?;
+
+            let size = fs::metadata(&local_path)?.len();
+            total_pulled_count += 1;
+            total_pulled_bytes += size;
+
+            log::debug!("{} bytes file stored", size);
+        }
+    }
+
+    state_file.flush().context("error flushing file")?;
+
+    log::info!(
+        "Done in {:?}, {} new or changed files ({}) stored ok.",
+        start.elapsed(),
+        total_pulled_count,
+        human_bytes(total_pulled_bytes as f64)
+    );
+
+    Ok(())
+}
+
+/// Parses the configured collection URL, `{email}` is replaced with the account.
+pub fn collection_url(url: &str, email: &str) -> anyhow::Result<Url> {
+    let mut url = url.replace("{email}", email);
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    Url::parse(&url).context("malformed WebDAV url")
+}
+
+/// Decoded segments of the URL path, empty segments are left out.
+fn path_segments(url: &Url) -> Vec<String> {
+    url.path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        .collect()
+}
+
+/// Path of the URL compared regardless of percent-encoding and trailing slash.
+fn normalized_path(url: &Url) -> String {
+    path_segments(url).join("/")
+}
+
+/// Decoded path of the resource relative to the mirrored collection.
+fn relative_path(root_url: &Url, url: &Url) -> anyhow::Result<String> {
+    let root_segments = path_segments(root_url);
+    let segments = path_segments(url);
+    let segments = segments
+        .strip_prefix(root_segments.as_slice())
+        .context(format!("{url} is outside of {root_url}"))?;
+
+    if segments
+        .iter()
+        .any(|segment| segment == ".." || segment.contains(['/', '\\']))
+    {
+        anyhow::bail!("unsafe path {}", url.path());
+    }
+
+    Ok(segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn collection_url_appends_slash_and_email() {
+        assert_eq!(
+            collection_url("https://dav.example.com/files/{email}", "a@b.c")
+                .unwrap()
+                .as_str(),
+            "https://dav.example.com/files/a@b.c/"
+        );
+        assert!(collection_url("not a url", "a@b.c").is_err());
+    }
+
+    #[test]
+    fn normalized_path_ignores_encoding_and_trailing_slash() {
+        assert_eq!(
+            normalized_path(&url("https://dav.example.com/files/My%20Docs/")),
+            normalized_path(&url("https://dav.example.com/files/My Docs"))
+        );
+        assert_eq!(
+            normalized_path(&url("https://dav.example.com/files/%7Euser/")),
+            "files/~user"
+        );
+        assert_ne!(
+            normalized_path(&url("https://dav.example.com/files/a")),
+            normalized_path(&url("https://dav.example.com/files/b"))
+        );
+    }
+
+    #[test]
+    fn relative_path_decodes_segments() {
+        let root_url = url("https://dav.example.com/files/a%40b.c/");
+        assert_eq!(
+            relative_path(
+                &root_url,
+                &url("https://dav.example.com/files/a@b.c/Docs/My%20File.txt")
+            )
+            .unwrap(),
+            "Docs/My File.txt"
+        );
+        assert_eq!(
+            relative_path(&root_url, &url("https://dav.example.com/files/a@b.c")).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn relative_path_rejects_outside_and_unsafe_paths() {
+        let root_url = url("https://dav.example.com/files/user/");
+        assert!(relative_path(&root_url, &url("https://dav.example.com/files/other/x")).is_err());
+        assert!(relative_path(&root_url, &url("https://dav.example.com/files/user2/x")).is_err());
+        assert!(relative_path(
+            &root_url,
+            &url("https://dav.example.com/files/user/%2E%2E/x")
+        )
+        .is_err());
+        assert!(
+            relative_path(&root_url, &url("https://dav.example.com/files/user/a%2Fb")).is_err()
+        );
+        assert!(
+            relative_path(&root_url, &url("https://dav.example.com/files/user/a%5Cb")).is_err()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Context;
+use async_walkdir::WalkDir;
+use futures_lite::stream::StreamExt;
+use human_bytes::human_bytes;
+use std::{
+    env::current_dir,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
+use tokio::time::Instant;
+
+use super::{pull::collection_url, FILES_DIR, PUSH_STATE_FILE_NAME};
+use crate::{
+    config::Config,
+    dav::{encode_name, DavClient},
+    storage::load_etags,
+};
+
+pub async fn push(
+    config: &Config,
+    email: String,
+    password: String,
+    in_dir: String,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let cancellation_token = tokio_util::sync::CancellationToken::new();
+    let push_cancellation_token = cancellation_token.clone();
+
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        log::info!("\nShutting down...");
+        cancellation_token.cancel();
+    });
+
+    let domain = email
+        .split("@")
+        .last()
+        .context("wrong email address {email}")?;
+    log::info!("Domain: {domain}");
+
+    let mut total_pushed_count = 0;
+    let mut total_pushed_bytes = 0u64;
+
+    let folder_name = format!("{in_dir}/{domain}/{email}");
+    let account_path = if folder_name.clone().starts_with("/") {
+        PathBuf::from_str("/").unwrap().join(folder_name.clone())
+    } else {
+        current_dir().unwrap().join(folder_name.clone())
+    };
+    let files_path = account_path.join(FILES_DIR);
+
+    log::info!("Getting files in {}", files_path.display());
+
+    let mut dirs = Vec::<String>::new();
+    let mut files = Vec::<(String, PathBuf)>::new();
+
+    if files_path.is_dir() {
+        let mut entries = WalkDir::new(&files_path);
+
+        while !push_cancellation_token.is_cancelled() {
+            match entries.next().await {
+                Some(Ok(entry)) => {
+                    let path = entry.path();
+                    let relative_path = path
+                        .strip_prefix(&files_path)?
+                        .to_str()
+                        .context("wrong file name")?
+                        .to_string();
+                    if entry.file_type().await?.is_dir() {
+                        dirs.push(relative_path);
+                    } else {
+                        files.push((relative_path, path));
+                    }
+                }
+                Some(Err(e)) => {
+                    log::warn!("Error reading dir: {}", e);
+                    break;
+                }
+                None => break,
+            }
+        }
+    }
+
+    // Sorted paths list parents before their children
+    dirs.sort();
+    files.sort();
+
+    log::info!("Found {} files in {} folders", files.len(), dirs.len());
+
+    if !files.is_empty() || !dirs.is_empty() {
+        let webdav_config = config
+            .webdav
+            .clone()
+            .context("WebDAV config is not provided in config.yaml")?
+            .push
+            .context("WebDAV push server config not provided")?;
+        let root_url = collection_url(&webdav_config.url, &email)?;
+
+        log::debug!("Pushing WebDAV for account {email}...");
+        let client = DavClient::new(&email, &password);
+        log::info!("Mirroring {} to {}", files_path.display(), root_url);
+
+        let state_file_path = account_path.join(PUSH_STATE_FILE_NAME);
+        let pushed = load_etags(&state_file_path)?;
+        let mut state_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&state_file_path)
+            .context("unable to open WebDAV state file")?;
+
+        // The target collection itself may not exist yet on a fresh account
+        client.mkcol("MKCOL", &root_url, None).await?;
+
+        for dir in dirs {
+            if push_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            let dir_url = root_url.join(&format!("{}/", encode_path(&dir)))?;
+            client.mkcol("MKCOL", &dir_url, None).await?;
+        }
+
+        for (relative_path, path) in files {
+            if push_cancellation_token.is_cancelled() {
+                break;
+            }
+
+            let metadata = fs::metadata(&path)?;
+            let mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or_default();
+            let state = format!("{mtime} {}", metadata.len());
+
+            if pushed.get(&relative_path) == Some(&state) {
+                continue;
+            }
+
+            log::debug!("Pushing {relative_path}...");
+
+            let file_url = root_url.join(&encode_path(&relative_path))?;
+            let data = fs::read(&path)?;
+
+            match client.upload(&file_url, data, mtime).await {
+                Ok(_) => {
+                    writeln!(state_file, "{relative_path}\t{state}")
+                        .context("unable to update WebDAV state file")?;
+
+                    total_pushed_count += 1;
+                    total_pushed_bytes += metadata.len();
+
+                    log::debug!("{} sent ok", human_bytes(metadata.len() as f64));
+                }
+                Err(err) => log::warn!("Error pushing file {}: {:#}", relative_path, err),
+            }
+        }
+
+        state_file.flush().context("error flushing file")?;
+    }
+
+    log::info!(
+        "Done in {:?}, {} files ({}) uploaded ok.",
+        start.elapsed(),
+        total_pushed_count,
+        human_bytes(total_pushed_bytes as f64)
+    );
+
+    Ok(())
+}
+
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(encode_name)
+        .collect::<Vec<_>>()
+        .join("/")
+}